@@ -0,0 +1,20 @@
+-- Your SQL goes here
+CREATE TABLE local_identities (
+  id SERIAL PRIMARY KEY,
+  username VARCHAR NOT NULL UNIQUE,
+  email VARCHAR NOT NULL,
+  sub VARCHAR NOT NULL UNIQUE,
+  password_hash VARCHAR NOT NULL,
+  confirmed BOOLEAN NOT NULL,
+  confirmation_code_hash VARCHAR,
+  confirmation_code_expires_at TIMESTAMP,
+  reset_code_hash VARCHAR,
+  reset_code_expires_at TIMESTAMP,
+  -- wrong guesses at each emailed code, it is burnt after too many
+  confirmation_code_attempts INTEGER NOT NULL DEFAULT 0,
+  reset_code_attempts INTEGER NOT NULL DEFAULT 0,
+  created_at TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP,
+  deleted_at TIMESTAMP,
+  active BOOLEAN NOT NULL
+);
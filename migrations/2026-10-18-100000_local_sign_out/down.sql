@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE local_revoked_tokens;
+
+ALTER TABLE local_identities
+  DROP signed_out_at;
@@ -0,0 +1,10 @@
+-- Your SQL goes here
+ALTER TABLE local_identities
+  ADD signed_out_at TIMESTAMP;
+
+CREATE TABLE local_revoked_tokens (
+  id SERIAL PRIMARY KEY,
+  jti VARCHAR NOT NULL UNIQUE,
+  expires_at TIMESTAMP NOT NULL,
+  created_at TIMESTAMP NOT NULL
+);
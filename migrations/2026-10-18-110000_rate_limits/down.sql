@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+DROP TABLE rate_limit_lockouts;
+
+DROP TABLE rate_limit_hits;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE rate_limit_hits (
+  id SERIAL PRIMARY KEY,
+  bucket VARCHAR NOT NULL,
+  hit_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX rate_limit_hits_bucket_hit_at ON rate_limit_hits (bucket, hit_at);
+CREATE INDEX rate_limit_hits_hit_at ON rate_limit_hits (hit_at);
+
+CREATE TABLE rate_limit_lockouts (
+  bucket VARCHAR PRIMARY KEY,
+  locked_until TIMESTAMP NOT NULL
+);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- merged duplicates are not split back apart
+DROP INDEX users_active_email;
+
+DROP INDEX users_authentication_id;
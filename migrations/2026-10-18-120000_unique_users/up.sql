@@ -0,0 +1,50 @@
+-- Your SQL goes here
+
+-- racing first requests could each insert a user for the same sub, keep the
+-- active row (oldest first) and move everything hanging off the others onto it
+CREATE TEMPORARY TABLE duplicate_users AS
+SELECT id, keep_id
+FROM (
+  SELECT
+    id,
+    FIRST_VALUE(id) OVER (
+      PARTITION BY authentication_id
+      ORDER BY active DESC, id ASC
+    ) AS keep_id
+  FROM users
+) ranked
+WHERE id <> keep_id;
+
+UPDATE emailers
+SET user_id = duplicate_users.keep_id
+FROM duplicate_users
+WHERE emailers.user_id = duplicate_users.id;
+
+UPDATE listing_data
+SET user_id = duplicate_users.keep_id
+FROM duplicate_users
+WHERE listing_data.user_id = duplicate_users.id;
+
+DELETE FROM users
+USING duplicate_users
+WHERE users.id = duplicate_users.id;
+
+DROP TABLE duplicate_users;
+
+-- an email that came back under a new sub keeps only its newest active row
+UPDATE users
+SET active = false, deleted_at = NOW()
+FROM (
+  SELECT
+    id,
+    ROW_NUMBER() OVER (PARTITION BY email ORDER BY id DESC) AS position
+  FROM users
+  WHERE active
+) ranked
+WHERE users.id = ranked.id
+  AND ranked.position > 1;
+
+CREATE UNIQUE INDEX users_authentication_id ON users (authentication_id);
+
+-- soft deleted accounts don't hold on to their email
+CREATE UNIQUE INDEX users_active_email ON users (email) WHERE active;
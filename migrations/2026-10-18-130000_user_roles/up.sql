@@ -0,0 +1,3 @@
+-- Your SQL goes here
+ALTER TABLE users
+  ADD role VARCHAR NOT NULL DEFAULT 'user';
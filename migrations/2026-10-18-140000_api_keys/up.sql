@@ -0,0 +1,16 @@
+-- Your SQL goes here
+CREATE TABLE api_keys (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL,
+  name VARCHAR NOT NULL,
+  key_prefix VARCHAR NOT NULL,
+  key_hash VARCHAR NOT NULL UNIQUE,
+  scopes TEXT[] NOT NULL,
+  last_used_at TIMESTAMP,
+  expires_at TIMESTAMP,
+  created_at TIMESTAMP NOT NULL,
+  deleted_at TIMESTAMP,
+  active BOOLEAN NOT NULL
+);
+
+CREATE INDEX api_keys_user_id ON api_keys (user_id);
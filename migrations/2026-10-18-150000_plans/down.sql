@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+  ADD user_tier INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE users
+  ALTER user_tier DROP DEFAULT;
+
+ALTER TABLE users
+  DROP plan_id;
+
+DROP TABLE plans;
@@ -0,0 +1,37 @@
+-- Your SQL goes here
+CREATE TABLE plans (
+  id SERIAL PRIMARY KEY,
+  name VARCHAR NOT NULL UNIQUE,
+  stripe_product_name VARCHAR UNIQUE,
+  max_emailers INTEGER NOT NULL,
+  allowed_frequencies TEXT[] NOT NULL,
+  max_listings_per_email INTEGER NOT NULL,
+  monthly_api_quota INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP
+);
+
+-- the stripe products we sold as "Tier N" before plans existed
+INSERT INTO plans
+  (name, stripe_product_name, max_emailers, allowed_frequencies, max_listings_per_email, monthly_api_quota, created_at)
+VALUES
+  ('free', 'Tier 0', 0, '{daily,weekly,monthly}', 0, 0, NOW()),
+  ('tier_1', 'Tier 1', 1, '{daily,weekly,monthly}', 25, 1000, NOW()),
+  ('tier_2', 'Tier 2', 3, '{hourly,daily,weekly,monthly}', 25, 3000, NOW()),
+  ('tier_3', 'Tier 3', 5, '{hourly,daily,weekly,monthly}', 50, 5000, NOW());
+
+ALTER TABLE users
+  ADD plan_id INTEGER REFERENCES plans (id);
+
+UPDATE users
+SET plan_id = COALESCE(
+  (SELECT plans.id FROM plans WHERE plans.stripe_product_name = users.billing_id),
+  (SELECT plans.id FROM plans WHERE plans.name = 'free')
+);
+
+ALTER TABLE users
+  ALTER plan_id SET NOT NULL;
+
+-- never read by anything, the plan is what counts now
+ALTER TABLE users
+  DROP user_tier;
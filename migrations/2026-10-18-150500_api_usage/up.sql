@@ -0,0 +1,10 @@
+-- Your SQL goes here
+-- the calls made to paid apis on a user's behalf each month, held against their plan's quota
+CREATE TABLE api_usage (
+  user_id INTEGER NOT NULL REFERENCES users (id),
+  month DATE NOT NULL,
+  calls INTEGER NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP,
+  PRIMARY KEY (user_id, month)
+);
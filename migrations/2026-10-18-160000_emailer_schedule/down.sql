@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users
+  DROP time_zone;
+
+DROP INDEX emailers_due;
+
+ALTER TABLE emailers
+  DROP next_run_at,
+  DROP last_run_at;
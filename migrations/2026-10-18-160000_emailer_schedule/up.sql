@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- frequency was free text, settle it on the values the scheduler understands
+UPDATE emailers
+SET frequency = CASE LOWER(TRIM(frequency))
+  WHEN 'instant' THEN 'hourly'
+  WHEN 'hourly' THEN 'hourly'
+  WHEN 'weekly' THEN 'weekly'
+  WHEN 'monthly' THEN 'monthly'
+  ELSE 'daily'
+END;
+
+-- everything that exists today is due on the next run
+ALTER TABLE emailers
+  ADD next_run_at TIMESTAMP NOT NULL DEFAULT NOW(),
+  ADD last_run_at TIMESTAMP;
+
+ALTER TABLE emailers
+  ALTER next_run_at DROP DEFAULT;
+
+CREATE INDEX emailers_due ON emailers (next_run_at) WHERE active;
+
+ALTER TABLE users
+  ADD time_zone VARCHAR NOT NULL DEFAULT 'UTC';
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE jobs (
+  id BIGSERIAL PRIMARY KEY,
+  kind VARCHAR NOT NULL,
+  payload JSONB NOT NULL,
+  -- queued, running, done or dead
+  state VARCHAR NOT NULL DEFAULT 'queued',
+  attempts INTEGER NOT NULL DEFAULT 0,
+  max_attempts INTEGER NOT NULL,
+  -- not picked up before this, pushed out by the backoff after a failure
+  run_at TIMESTAMP NOT NULL,
+  -- a running job whose worker goes quiet past this is handed to another one
+  locked_until TIMESTAMP,
+  locked_by VARCHAR,
+  last_error TEXT,
+  created_at TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP,
+  finished_at TIMESTAMP
+);
+
+CREATE INDEX jobs_ready ON jobs (run_at) WHERE state IN ('queued', 'running');
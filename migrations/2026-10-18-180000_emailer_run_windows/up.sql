@@ -0,0 +1,9 @@
+-- Your SQL goes here
+-- one row per period an emailer has gone out for, so a second run of the same period sends nothing
+CREATE TABLE emailer_run_windows (
+  emailer_id INTEGER NOT NULL REFERENCES emailers (id),
+  run_window TIMESTAMP NOT NULL,
+  created_at TIMESTAMP NOT NULL,
+  updated_at TIMESTAMP,
+  PRIMARY KEY (emailer_id, run_window)
+);
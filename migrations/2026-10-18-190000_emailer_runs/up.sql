@@ -0,0 +1,17 @@
+-- Your SQL goes here
+CREATE TABLE emailer_runs (
+  id BIGSERIAL PRIMARY KEY,
+  emailer_id INTEGER NOT NULL REFERENCES emailers (id),
+  run_window TIMESTAMP NOT NULL,
+  started_at TIMESTAMP NOT NULL,
+  finished_at TIMESTAMP,
+  zillow_url VARCHAR NOT NULL,
+  total_results BIGINT,
+  properties_fetched INTEGER NOT NULL DEFAULT 0,
+  properties_failed INTEGER NOT NULL DEFAULT 0,
+  listings_written INTEGER NOT NULL DEFAULT 0,
+  email_status VARCHAR NOT NULL,
+  error TEXT
+);
+
+CREATE INDEX emailer_runs_by_emailer ON emailer_runs (emailer_id, started_at DESC);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- bumped on every write, it is the etag for If-Match
+ALTER TABLE emailers
+  ADD version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- zillow, or file: and the name of an export in LISTING_FILES_DIR
+ALTER TABLE emailers
+  ADD listing_source VARCHAR NOT NULL DEFAULT 'zillow';
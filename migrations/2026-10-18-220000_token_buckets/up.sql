@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- request budgets shared by every worker, refilled from updated_at whenever one is taken from
+CREATE TABLE token_buckets (
+  bucket VARCHAR PRIMARY KEY,
+  tokens DOUBLE PRECISION NOT NULL,
+  updated_at TIMESTAMP NOT NULL
+);
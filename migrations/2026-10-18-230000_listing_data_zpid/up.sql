@@ -0,0 +1,23 @@
+-- Your SQL goes here
+-- the listing's id at its source, so a property goes out at most once per emailer
+ALTER TABLE listing_data
+  ADD zpid VARCHAR;
+
+-- older rows only have the zillow url, and only the first of any repeats keeps its zpid
+UPDATE listing_data
+SET zpid = sent.zpid
+FROM (
+  SELECT
+    id,
+    SUBSTRING(url FROM '(\d+)_zpid') AS zpid,
+    ROW_NUMBER() OVER (
+      PARTITION BY emailer_id, SUBSTRING(url FROM '(\d+)_zpid')
+      ORDER BY id ASC
+    ) AS position
+  FROM listing_data
+) sent
+WHERE listing_data.id = sent.id
+  AND sent.position = 1;
+
+ALTER TABLE listing_data
+  ADD CONSTRAINT listing_data_emailer_id_zpid UNIQUE (emailer_id, zpid);
@@ -1,5 +1,5 @@
 use env_logger::Env;
-use ostrich_api::{config::Config, handle_rejection, handlers, routes, services, with_config};
+use ostrich_api::{config, db_conn::DbConn, handle_rejection, handlers, routes, services};
 use std::{net::SocketAddr, sync::Arc};
 use warp::Filter;
 
@@ -9,31 +9,67 @@ async fn main() {
 
     log::info!("🔍 Booting up the Authentication Service!");
 
-    let config = Arc::new(Config::new(false));
-    let cognito =
-        Arc::new(services::cognito::get_cognito_client(config.clone().aws_region.clone()).await);
+    let config = Arc::new(config::generate_config());
+    let db_conn = Arc::new(DbConn::new(&config.db_path));
+    let identity_provider =
+        services::identity::get_identity_provider(config.clone(), db_conn.clone()).await;
+    let reqwest_client = Arc::new(reqwest::Client::new());
+    let jwt_verifier =
+        Arc::new(services::jwt::get_jwt_verifier(config.clone(), reqwest_client.clone()).await);
+    let rate_limiter = Arc::new(services::rate_limit::get_rate_limiter(
+        &config.rate_limit,
+        db_conn.clone(),
+    ));
 
-    let login =
-        routes::auth::login(config.clone(), cognito.clone()).and_then(handlers::auth::login);
+    let login = routes::auth::login(identity_provider.clone(), rate_limiter.clone())
+        .and_then(handlers::auth::login);
 
-    let sign_up =
-        routes::auth::sign_up(config.clone(), cognito.clone()).and_then(handlers::auth::sign_up);
+    let sign_up = routes::auth::sign_up(identity_provider.clone(), rate_limiter.clone())
+        .and_then(handlers::auth::sign_up);
 
-    let verify =
-        routes::auth::verify(config.clone(), cognito.clone()).and_then(handlers::auth::verify);
+    let verify = routes::auth::verify(identity_provider.clone(), rate_limiter.clone())
+        .and_then(handlers::auth::verify);
 
-    let resend_code = routes::auth::resend_code(config.clone(), cognito.clone())
+    let resend_code = routes::auth::resend_code(identity_provider.clone(), rate_limiter.clone())
         .and_then(handlers::auth::resend_code);
 
-    let forgot_password = routes::auth::forgot_password(config.clone(), cognito.clone())
-        .and_then(handlers::auth::forgot_password);
+    let forgot_password =
+        routes::auth::forgot_password(identity_provider.clone(), rate_limiter.clone())
+            .and_then(handlers::auth::forgot_password);
 
     let confirm_forgot_password =
-        routes::auth::confirm_forgot_password(config.clone(), cognito.clone())
+        routes::auth::confirm_forgot_password(identity_provider.clone(), rate_limiter.clone())
             .and_then(handlers::auth::confirm_forgot_password);
 
-    let refresh = routes::auth::refresh_token(config.clone(), cognito.clone())
-        .and_then(handlers::auth::refresh);
+    let refresh =
+        routes::auth::refresh_token(identity_provider.clone()).and_then(handlers::auth::refresh);
+
+    let respond_to_challenge =
+        routes::auth::respond_to_challenge(identity_provider.clone(), rate_limiter.clone())
+            .and_then(handlers::auth::respond_to_challenge);
+
+    let associate_software_token =
+        routes::auth::associate_software_token(identity_provider.clone())
+            .and_then(handlers::auth::associate_software_token);
+
+    let verify_software_token = routes::auth::verify_software_token(identity_provider.clone())
+        .and_then(handlers::auth::verify_software_token);
+
+    let change_password =
+        routes::auth::change_password(identity_provider.clone(), jwt_verifier.clone())
+            .and_then(handlers::auth::change_password);
+
+    let sign_out = routes::auth::sign_out(identity_provider.clone(), jwt_verifier.clone())
+        .and_then(handlers::auth::sign_out);
+
+    let revoke = routes::auth::revoke(identity_provider.clone()).and_then(handlers::auth::revoke);
+
+    let delete_account = routes::auth::delete_account(
+        identity_provider.clone(),
+        jwt_verifier.clone(),
+        db_conn.clone(),
+    )
+    .and_then(handlers::auth::delete_account);
 
     let auth = login
         .or(sign_up)
@@ -42,18 +78,35 @@ async fn main() {
         .or(forgot_password)
         .or(confirm_forgot_password)
         .or(refresh)
+        .or(respond_to_challenge)
+        .or(associate_software_token)
+        .or(verify_software_token)
+        .or(change_password)
+        .or(sign_out)
+        .or(revoke)
+        .or(delete_account)
         .recover(handle_rejection);
 
     let with_control_origin = warp::reply::with::header("Access-Control-Allow-Origin", "*");
     let with_content_allow =
-        warp::reply::with::header("Access-Control-Allow-Headers", "Content-Type");
+        warp::reply::with::header("Access-Control-Allow-Headers", "Content-Type,authorization");
 
     let health = warp::get().and(warp::path("health")).map(|| warp::reply());
 
-    let end = health
-        .or(auth)
-        .with(with_control_origin)
-        .with(with_content_allow)
+    // bearer and DELETE requests get preflighted by browsers
+    let with_content_methods =
+        warp::reply::with::header("Access-Control-Allow-Methods", "DELETE,GET,OPTIONS,POST");
+    let cors = warp::options()
+        .map(|| warp::reply())
+        .with(with_content_allow.clone())
+        .with(with_content_methods)
+        .with(with_control_origin.clone());
+
+    let end = cors
+        .or(health
+            .or(auth)
+            .with(with_control_origin)
+            .with(with_content_allow))
         .with(warp::log("auth"));
 
     let socket_address = config
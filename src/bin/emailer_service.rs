@@ -1,84 +1,90 @@
 use env_logger::Env;
 use ostrich_api::{
-    config::Config,
+    config,
     db_conn::DbConn,
-    error::{map_ostrich_error, OstrichErrorType},
-    models, services,
+    services::{self, run_lock},
 };
 use std::sync::Arc;
 
+// emailer_service --force 12 sends emailer 12 again even if it already went out this period
+fn forced_emailer_ids(args: &[String]) -> Result<Vec<i32>, String> {
+    args.iter()
+        .enumerate()
+        .filter(|(_, arg)| *arg == "--force")
+        .map(|(i, _)| match args.get(i + 1).map(|id| id.parse::<i32>()) {
+            Some(Ok(id)) => Ok(id),
+            Some(Err(_)) => Err(format!("--force takes an emailer id, not {}", args[i + 1])),
+            None => Err(String::from("--force takes an emailer id")),
+        })
+        .collect()
+}
+
+// a single pass for running from cron: queue whatever is due, then work the queue until it
+// is empty; scheduler_service and worker_service do the same for good
 #[tokio::main]
 async fn main() -> Result<(), ()> {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
     log::info!("🏛 Booting up the Ostrich Service!");
 
-    let config = Arc::new(Config::new(false));
-    let email_client = services::email::get_email_client(config.clone());
-    let reqwest_client = Arc::new(reqwest::Client::new());
-
+    let forced = match forced_emailer_ids(&std::env::args().collect::<Vec<String>>()) {
+        Ok(forced) => forced,
+        Err(e) => {
+            log::error!("{}, usage: emailer_service [--force <emailer id>]...", e);
+            return Err(());
+        }
+    };
+    let config = Arc::new(config::generate_config());
+    let email_client = Arc::new(services::email::get_email_client(config.clone()));
     let db_conn = Arc::new(DbConn::new(&config.db_path));
-    let conn = db_conn.get_conn();
-
-    // hopefully this will keep us around 2 requests per second
-    let delay = 700;
-
-    let users = models::user::read(&conn);
-    for user in users {
-        let emailers =
-            services::user::get_emails_for_user_with_tier(&conn, user.id, user.billing_id);
+    let zillow_client = Arc::new(services::zillow::ZillowClient::new(
+        &config,
+        db_conn.clone(),
+    ));
 
-        for emailer in emailers {
-            let search_param = &emailer.search_param;
-            let to = &emailer.email;
-
-            let body = services::email::get_ostrich_email_body(&emailer);
+    let _lock = match run_lock::RunLock::try_acquire(&db_conn, run_lock::EMAILER_SERVICE) {
+        Ok(Some(lock)) => lock,
+        Ok(None) => {
+            log::warn!("Another emailer_service run is still going, leaving it to finish");
+            return Ok(());
+        }
+        Err(e) => {
+            log::error!("Could not take the emailer_service lock: {}", e);
+            return Err(());
+        }
+    };
 
-            log::info!("Running search on {} for {}", search_param, to);
+    let scheduler = services::scheduler::Scheduler::new(config.clone(), db_conn.clone());
+    let enqueued = scheduler.enqueue_due_emailers();
+    log::info!("Queued {} emailers", enqueued);
 
-            match services::zillow::get_listing_email_for_search_params(
-                config.clone(),
-                db_conn.clone(),
-                reqwest_client.clone(),
-                &emailer,
-                body,
-                delay,
-                Some(1), // this means 1 day on market
-            )
-            .await
-            {
-                Err(e) => {
-                    let etype = e.etype.clone();
-                    map_ostrich_error(e);
-                    match etype {
-                        OstrichErrorType::ListingResultError => {
-                            log::info!("Sending followup email to {}", to);
-                            let _ = services::email::send_empty_zillow_listings_email(
-                                &email_client,
-                                config.clone(),
-                                &to,
-                                search_param,
-                            )
-                            .await
-                            .map_err(map_ostrich_error);
-                        }
-                        _ => (),
-                    }
-                }
-                Ok(body) => {
-                    let _ = services::email::send_zillow_listings_email(
-                        &email_client,
-                        config.clone(),
-                        &to,
-                        &body,
-                        search_param,
-                    )
-                    .await
-                    .map_err(map_ostrich_error);
-                }
-            }
+    for emailer_id in forced {
+        match scheduler.enqueue_forced(emailer_id) {
+            Ok(true) => log::info!("Queued emailer {} again", emailer_id),
+            Ok(false) => log::warn!("There is no emailer {}", emailer_id),
+            Err(e) => log::error!("Could not queue emailer {}: {}", emailer_id, e),
         }
     }
 
+    let worker = services::jobs::Worker::new(config, db_conn, zillow_client, email_client);
+    let worked = worker.drain().await;
+    log::info!("Worked {} jobs", worked);
+
     Ok(())
 }
+
+#[test]
+fn forced_ids_are_checked() {
+    let args = |args: &[&str]| {
+        args.iter()
+            .map(|arg| String::from(*arg))
+            .collect::<Vec<String>>()
+    };
+    assert_eq!(
+        forced_emailer_ids(&args(&["emailer_service", "--force", "12", "--force", "3"])),
+        Ok(vec![12, 3])
+    );
+    assert_eq!(forced_emailer_ids(&args(&["emailer_service"])), Ok(vec![]));
+    assert!(forced_emailer_ids(&args(&["emailer_service", "--force", "twelve"])).is_err());
+    assert!(forced_emailer_ids(&args(&["emailer_service", "--force"])).is_err());
+}
@@ -0,0 +1,40 @@
+use env_logger::Env;
+use ostrich_api::{
+    config,
+    db_conn::DbConn,
+    services::{metrics::Metrics, scheduler::Scheduler, server},
+};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    log::info!("⏰ Booting up Scheduler Service!");
+
+    let config = Arc::new(config::generate_config());
+    let db_conn = Arc::new(DbConn::new(&config.db_path));
+
+    let scheduler = Arc::new(Scheduler::new(config.clone(), db_conn));
+    let (shutdown, shutdown_rx) = watch::channel(false);
+
+    let metrics: Arc<dyn Metrics> = scheduler.metrics();
+    let server = server::serve_with_shutdown(
+        server::health_and_metrics(metrics, "scheduler"),
+        &config,
+        shutdown_rx.clone(),
+    );
+
+    let worker = {
+        let scheduler = scheduler.clone();
+        tokio::spawn(async move { scheduler.run(shutdown_rx).await })
+    };
+
+    server::shutdown_signal().await;
+    log::info!("Shutting down");
+    let _ = shutdown.send(true);
+
+    let _ = worker.await;
+    let _ = server.await;
+    log::info!("Bye!");
+}
@@ -1,5 +1,5 @@
 use env_logger::Env;
-use ostrich_api::{config::Config, db_conn::DbConn, handle_rejection, handlers, routes, services};
+use ostrich_api::{config, db_conn::DbConn, handle_rejection, handlers, routes, services};
 use std::{net::SocketAddr, sync::Arc};
 use warp::Filter;
 
@@ -8,7 +8,7 @@ async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     log::info!("💸 Booting up Stripe Service!");
 
-    let config = Arc::new(Config::new(false));
+    let config = Arc::new(config::generate_config());
     let db_conn = Arc::new(DbConn::new(&config.db_path));
     let stripe_client = Arc::new(services::stripe::get_stripe_client(config.clone()));
     let email_client = Arc::new(services::email::get_email_client(config.clone()));
@@ -1,13 +1,17 @@
 use env_logger::Env;
 use ostrich_api::{
-    config::Config,
+    config,
     db_conn::DbConn,
     error::{map_ostrich_error, OstrichErrorType},
     models::emailer,
-    services::{email, zillow},
+    services::{
+        email,
+        listing_source::{self, SearchParameters},
+        zillow::{ZillowClient, ZillowSource},
+    },
     utils,
 };
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 #[tokio::main]
 async fn main() -> Result<(), ()> {
@@ -15,14 +19,10 @@ async fn main() -> Result<(), ()> {
 
     log::info!("🏛 Booting up the Ostrich Service!");
 
-    let config = Arc::new(Config::new(false));
+    let config = Arc::new(config::generate_config());
     let email_client = email::get_email_client(config.clone());
-    let reqwest_client = Arc::new(reqwest::Client::new());
-
     let db_conn = Arc::new(DbConn::new(&config.db_path));
-
-    // hopefully this will keep us around 2 requests per second
-    let delay = 700;
+    let zillow_client = Arc::new(ZillowClient::new(&config, db_conn.clone()));
 
     let emailer = emailer::Emailer {
         id: 0,
@@ -50,6 +50,10 @@ async fn main() -> Result<(), ()> {
         deleted_at: None,
         active: true,
         no_bathrooms: Some(1),
+        next_run_at: utils::now(),
+        last_run_at: None,
+        version: 1,
+        listing_source: String::from(listing_source::ZILLOW),
     };
 
     let search_param = &emailer.search_param;
@@ -59,14 +63,15 @@ async fn main() -> Result<(), ()> {
 
     log::info!("Running search on {} for {}", search_param, to);
 
-    match zillow::get_listing_email_for_search_params(
-        config.clone(),
-        db_conn.clone(),
-        reqwest_client.clone(),
+    let source = ZillowSource::new(config.clone(), zillow_client.clone());
+
+    match listing_source::get_listing_email_for_search_params(
+        &source,
         &emailer,
         body,
-        delay,
+        &SearchParameters::from(&emailer),
         None,
+        &HashSet::new(),
     )
     .await
     {
@@ -88,12 +93,12 @@ async fn main() -> Result<(), ()> {
                 _ => (),
             }
         }
-        Ok(body) => {
+        Ok(listing_email) => {
             let _ = email::send_zillow_listings_email(
                 &email_client,
                 config.clone(),
                 &to,
-                &body,
+                &listing_email.body,
                 search_param,
             )
             .await
@@ -1,5 +1,5 @@
 use env_logger::Env;
-use ostrich_api::{config::Config, db_conn::DbConn, handle_rejection, handlers, routes, services};
+use ostrich_api::{config, db_conn::DbConn, handle_rejection, handlers, routes, services};
 use std::{net::SocketAddr, sync::Arc};
 use warp::Filter;
 
@@ -8,55 +8,116 @@ async fn main() {
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
     log::info!("🥸 Booting up User Service!");
 
-    let config = Arc::new(Config::new(false));
+    let config = Arc::new(config::generate_config());
     let db_conn = Arc::new(DbConn::new(&config.db_path));
     let reqwest_client = Arc::new(reqwest::Client::new());
     let email_client = Arc::new(services::email::get_email_client(config.clone()));
+    let jwt_verifier =
+        Arc::new(services::jwt::get_jwt_verifier(config.clone(), reqwest_client.clone()).await);
+    let zillow_client = Arc::new(services::zillow::ZillowClient::new(
+        &config,
+        db_conn.clone(),
+    ));
+    let rate_limiter = Arc::new(services::rate_limit::get_rate_limiter(
+        &config.rate_limit,
+        db_conn.clone(),
+    ));
 
     let with_control_origin = warp::reply::with::header("Access-Control-Allow-Origin", "*");
     let with_content_allow =
         warp::reply::with::header("Access-Control-Allow-Headers", "Content-Type");
 
-    let get_all_emailers = routes::emailer::get_all_emailers(db_conn.clone())
+    let get_all_emailers = routes::emailer::get_all_emailers(db_conn.clone(), jwt_verifier.clone())
         .and_then(handlers::emailer::get_all_emailers);
 
     let post_emailer =
-        routes::emailer::add_emailer(db_conn.clone()).and_then(handlers::emailer::insert_emailer);
+        routes::emailer::add_emailer(db_conn.clone(), jwt_verifier.clone(), config.clone())
+            .and_then(handlers::emailer::insert_emailer);
 
-    let test_emailer_params =
-        routes::emailer::test_emailer_params(config.clone(), reqwest_client.clone())
-            .and_then(handlers::emailer::test_emailer_search_params);
+    let test_emailer_params = routes::emailer::test_emailer_params(
+        db_conn.clone(),
+        jwt_verifier.clone(),
+        config.clone(),
+        zillow_client.clone(),
+        rate_limiter.clone(),
+    )
+    .and_then(handlers::emailer::test_emailer_search_params);
 
     let get_emailer_by_authentication_id =
-        routes::emailer::get_emailer_by_authentication_id(db_conn.clone())
+        routes::emailer::get_emailer_by_authentication_id(db_conn.clone(), jwt_verifier.clone())
             .and_then(handlers::emailer::get_all_emailers_from_authentication_id);
 
     let delete_emailer_by_authentication_id =
-        routes::emailer::delete_emailer_by_authentication_id(db_conn.clone())
+        routes::emailer::delete_emailer_by_authentication_id(db_conn.clone(), jwt_verifier.clone())
             .and_then(handlers::emailer::delete_emailer_by_id_and_authentication_id);
 
-    let update_emailer = routes::emailer::update_emailer(db_conn.clone())
-        .and_then(handlers::emailer::update_emailer);
+    let get_emailer_runs = routes::emailer::get_emailer_runs(db_conn.clone(), jwt_verifier.clone())
+        .and_then(handlers::emailer::get_emailer_runs);
+
+    let update_emailer =
+        routes::emailer::update_emailer(db_conn.clone(), jwt_verifier.clone(), config.clone())
+            .and_then(handlers::emailer::update_emailer);
+
+    let patch_emailer =
+        routes::emailer::patch_emailer(db_conn.clone(), jwt_verifier.clone(), config.clone())
+            .and_then(handlers::emailer::patch_emailer);
+
+    let preview_emailer = routes::emailer::preview_emailer(
+        db_conn.clone(),
+        jwt_verifier.clone(),
+        config.clone(),
+        zillow_client.clone(),
+        rate_limiter.clone(),
+    )
+    .and_then(handlers::emailer::preview_emailer);
+
+    let preview_new_emailer = routes::emailer::preview_new_emailer(
+        db_conn.clone(),
+        jwt_verifier.clone(),
+        config.clone(),
+        zillow_client.clone(),
+        rate_limiter.clone(),
+    )
+    .and_then(handlers::emailer::preview_new_emailer);
+
+    let post_api_key = routes::api_key::add_api_key(db_conn.clone(), jwt_verifier.clone())
+        .and_then(handlers::api_key::insert_api_key);
+
+    let get_api_keys = routes::api_key::get_api_keys(db_conn.clone(), jwt_verifier.clone())
+        .and_then(handlers::api_key::get_api_keys);
+
+    let delete_api_key = routes::api_key::delete_api_key(db_conn.clone(), jwt_verifier.clone())
+        .and_then(handlers::api_key::delete_api_key);
+
+    let api_key = post_api_key.or(get_api_keys).or(delete_api_key);
 
     let user = routes::user::get_user_by_authentication_id(
         config.clone(),
         email_client.clone(),
         db_conn.clone(),
+        jwt_verifier.clone(),
     )
     .and_then(handlers::user::get_user);
 
+    let update_user = routes::user::update_user(db_conn.clone(), jwt_verifier.clone())
+        .and_then(handlers::user::update_user);
+
     let emailer = get_all_emailers
         .or(post_emailer)
         .or(test_emailer_params)
         .or(get_emailer_by_authentication_id)
         .or(delete_emailer_by_authentication_id)
-        .or(update_emailer);
+        .or(get_emailer_runs)
+        .or(update_emailer)
+        .or(patch_emailer)
+        .or(preview_emailer)
+        .or(preview_new_emailer);
 
     let health = warp::get().and(warp::path("health")).map(|| warp::reply());
 
     let with_content_headers = warp::reply::with::header(
         "Access-Control-Allow-Headers",
-        "Content-Type,authorization,X-Amz-Date,X-Api-Key,X-Amz-Security-Token",
+        "Content-Type,authorization,If-Match,X-Amz-Date,X-Api-Key,X-Amz-Security-Token",
     );
     let with_content_methods = warp::reply::with::header(
         "Access-Control-Allow-Methods",
@@ -71,10 +132,16 @@ async fn main() {
     let end = cors
         .or(health
             .or(emailer)
+            .or(api_key)
             .or(user)
+            .or(update_user)
             .recover(handle_rejection)
             .with(with_control_origin)
-            .with(with_content_allow))
+            .with(with_content_allow)
+            .with(warp::reply::with::header(
+                "Access-Control-Expose-Headers",
+                "ETag",
+            )))
         .with(warp::log("user"));
 
     let socket_address = config
@@ -0,0 +1,51 @@
+use env_logger::Env;
+use ostrich_api::{
+    config,
+    db_conn::DbConn,
+    services::{self, metrics::Metrics, server},
+};
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[tokio::main]
+async fn main() {
+    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    log::info!("👷 Booting up Worker Service!");
+
+    let config = Arc::new(config::generate_config());
+    let db_conn = Arc::new(DbConn::new(&config.db_path));
+    let email_client = Arc::new(services::email::get_email_client(config.clone()));
+
+    let zillow_client = Arc::new(services::zillow::ZillowClient::new(
+        &config,
+        db_conn.clone(),
+    ));
+
+    let worker = Arc::new(services::jobs::Worker::new(
+        config.clone(),
+        db_conn,
+        zillow_client,
+        email_client,
+    ));
+    let (shutdown, shutdown_rx) = watch::channel(false);
+
+    let metrics: Arc<dyn Metrics> = worker.metrics();
+    let server = server::serve_with_shutdown(
+        server::health_and_metrics(metrics, "worker"),
+        &config,
+        shutdown_rx.clone(),
+    );
+
+    let working = {
+        let worker = worker.clone();
+        tokio::spawn(async move { worker.run(shutdown_rx).await })
+    };
+
+    server::shutdown_signal().await;
+    log::info!("Shutting down once the job in flight is finished");
+    let _ = shutdown.send(true);
+
+    let _ = working.await;
+    let _ = server.await;
+    log::info!("Bye!");
+}
@@ -2,6 +2,10 @@ use dotenv::dotenv;
 use log::info;
 use std::env;
 
+// mocks never talk to AWS, so the fake user pool lives at a fixed address
+pub const MOCK_AWS_REGION: &str = "us-east-2";
+pub const MOCK_USER_POOL_ID: &str = "us-east-2_mocks";
+
 #[derive(Clone)]
 pub struct Config {
     pub app_addr: String,
@@ -11,8 +15,15 @@ pub struct Config {
     pub db_path: String,
     pub is_mocking: bool,
     pub aws_region: String,
+    pub identity_provider: IdentityProviderKind,
     pub cognito: CognitoConfig,
+    pub local_auth: Option<LocalAuthConfig>,
+    pub rate_limit: RateLimitConfig,
+    pub scheduler: SchedulerConfig,
+    pub jobs: JobsConfig,
     pub zillow_api: ZillowApiConfig,
+    // where the exports behind file: listing sources live
+    pub listing_files_dir: Option<String>,
     pub email: EmailConfig,
     pub user_service_url: String,
     pub stripe_api: StripeApiConfig,
@@ -47,9 +58,48 @@ impl Config {
         // url to connect to the database
         let db_path = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
-        let aws_region = env::var("AWS_REGION").expect("AWS_REGION must be set");
         let user_service_url = env::var("USER_SERVICE_URL").expect("USER_SERVICE_URL must be set");
 
+        let listing_files_dir = env::var("LISTING_FILES_DIR").ok();
+
+        if is_mocking {
+            info!("🤡 Mocking Cognito, Zillow, SendGrid and Stripe!");
+            return Config {
+                app_addr,
+                tls,
+                cert_path,
+                key_path,
+                db_path,
+                is_mocking,
+                aws_region: String::from(MOCK_AWS_REGION),
+                identity_provider: IdentityProviderKind::Cognito,
+                cognito: CognitoConfig::mock(),
+                local_auth: None,
+                rate_limit: RateLimitConfig::new(),
+                scheduler: SchedulerConfig::new(),
+                jobs: JobsConfig::new(),
+                zillow_api: ZillowApiConfig::mock(),
+                listing_files_dir,
+                email: EmailConfig::mock(),
+                stripe_api: StripeApiConfig::mock(),
+                user_service_url,
+            };
+        }
+
+        let aws_region = env::var("AWS_REGION").expect("AWS_REGION must be set");
+
+        // who signs our users in, cognito unless we are self hosting
+        let identity_provider = match env::var("IDENTITY_PROVIDER").as_deref() {
+            Ok("local") => IdentityProviderKind::Local,
+            Ok("cognito") | Err(_) => IdentityProviderKind::Cognito,
+            Ok(other) => panic!("IDENTITY_PROVIDER must be cognito or local, not {}", other),
+        };
+
+        let local_auth = match identity_provider {
+            IdentityProviderKind::Local => Some(LocalAuthConfig::new()),
+            IdentityProviderKind::Cognito => None,
+        };
+
         Config {
             app_addr,
             tls,
@@ -58,8 +108,14 @@ impl Config {
             db_path,
             is_mocking,
             aws_region,
+            identity_provider,
             cognito: CognitoConfig::new(),
+            local_auth,
+            rate_limit: RateLimitConfig::new(),
+            scheduler: SchedulerConfig::new(),
+            jobs: JobsConfig::new(),
             zillow_api: ZillowApiConfig::new(),
+            listing_files_dir,
             email: EmailConfig::new(),
             stripe_api: StripeApiConfig::new(),
             user_service_url,
@@ -67,24 +123,189 @@ impl Config {
     }
 }
 
+impl Config {
+    // the iss claim on tokens from our user pool
+    pub fn cognito_issuer(&self) -> String {
+        format!(
+            "https://cognito-idp.{}.amazonaws.com/{}",
+            self.aws_region, self.cognito.user_pool_id
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IdentityProviderKind {
+    Cognito,
+    Local,
+}
+
 #[derive(Clone)]
 pub struct CognitoConfig {
     pub client_id: String,
     pub secret_key: String,
+    pub user_pool_id: String,
+    pub jwks_path: Option<String>,
+    pub jwt_leeway: u64,
 }
 
 impl CognitoConfig {
     pub fn new() -> Self {
         let client_id = env::var("COGNITO_CLIENT_ID").expect("COGNITO_CLIENT_ID must be set");
         let secret_key = env::var("COGNITO_SECRET_KEY").expect("COGNITO_SECRET_KEY must be set");
+        let user_pool_id =
+            env::var("COGNITO_USER_POOL_ID").expect("COGNITO_USER_POOL_ID must be set");
+
+        // read the signing keys from disk instead of the user pool, for offline testing
+        let jwks_path = env::var("COGNITO_JWKS_PATH").ok();
+
+        // seconds of clock skew we tolerate when checking exp
+        let jwt_leeway = env::var("JWT_LEEWAY_SECONDS")
+            .map(|leeway| leeway.parse().expect("JWT_LEEWAY_SECONDS must be a number"))
+            .unwrap_or(60);
 
         CognitoConfig {
             client_id,
             secret_key,
+            user_pool_id,
+            jwks_path,
+            jwt_leeway,
+        }
+    }
+
+    // tokens from the fake user pool are signed with the key in fixtures/
+    pub fn mock() -> Self {
+        CognitoConfig {
+            client_id: String::from("mock-client-id"),
+            secret_key: String::from("mock-secret-key"),
+            user_pool_id: String::from(MOCK_USER_POOL_ID),
+            jwks_path: Some(String::from("fixtures/jwks.json")),
+            jwt_leeway: 60,
+        }
+    }
+}
+
+// signing key for the tokens we issue when self hosting auth,
+// its public half goes in the jwks file at COGNITO_JWKS_PATH
+#[derive(Clone)]
+pub struct LocalAuthConfig {
+    pub private_key_path: String,
+    pub key_id: String,
+    // signs refresh tokens and keys the emailed codes, kept apart from cognito's secret
+    pub secret: String,
+}
+
+impl LocalAuthConfig {
+    pub fn new() -> Self {
+        let private_key_path = env::var("LOCAL_AUTH_PRIVATE_KEY_PATH")
+            .expect("LOCAL_AUTH_PRIVATE_KEY_PATH must be set");
+        let key_id =
+            env::var("LOCAL_AUTH_KEY_ID").unwrap_or_else(|_| String::from("ostrich-local-key"));
+        let secret = env::var("LOCAL_AUTH_SECRET").expect("LOCAL_AUTH_SECRET must be set");
+
+        LocalAuthConfig {
+            private_key_path,
+            key_id,
+            secret,
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RateLimitStoreKind {
+    Memory,
+    Postgres,
+}
+
+// memory forgets everything on restart, postgres is shared and sticks around
+fn store_kind(name: &str) -> RateLimitStoreKind {
+    match env::var(name).as_deref() {
+        Ok("postgres") => RateLimitStoreKind::Postgres,
+        Ok("memory") | Err(_) => RateLimitStoreKind::Memory,
+        Ok(other) => panic!("{} must be memory or postgres, not {}", name, other),
+    }
+}
+
+// throttling for the anonymous auth routes, every request counts against
+// both the username and the client ip for a sliding window of window_seconds
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    pub store: RateLimitStoreKind,
+    pub window_seconds: i64,
+    pub max_per_username: i64,
+    pub max_per_ip: i64,
+    // for signed in calls that cost us a search, like previews
+    pub max_per_user: i64,
+    pub lockout_seconds: i64,
+}
+
+impl RateLimitConfig {
+    pub fn new() -> Self {
+        RateLimitConfig {
+            store: store_kind("RATE_LIMIT_STORE"),
+            window_seconds: env_number("RATE_LIMIT_WINDOW_SECONDS", 900),
+            max_per_username: env_number("RATE_LIMIT_MAX_PER_USERNAME", 10),
+            max_per_ip: env_number("RATE_LIMIT_MAX_PER_IP", 50),
+            max_per_user: env_number("RATE_LIMIT_MAX_PER_USER", 30),
+            lockout_seconds: env_number("RATE_LIMIT_LOCKOUT_SECONDS", 900),
+        }
+    }
+}
+
+// how often the scheduler looks for due emailers
+#[derive(Clone)]
+pub struct SchedulerConfig {
+    pub tick_seconds: i64,
+}
+
+impl SchedulerConfig {
+    pub fn new() -> Self {
+        SchedulerConfig {
+            tick_seconds: env_number("SCHEDULER_TICK_SECONDS", 60),
+        }
+    }
+}
+
+// how workers drain the jobs table: how long they sleep when it is empty, how long a
+// claimed job stays hidden from other workers, and how failures are retried
+#[derive(Clone)]
+pub struct JobsConfig {
+    pub poll_seconds: i64,
+    pub visibility_timeout_seconds: i64,
+    pub max_attempts: i64,
+    pub backoff_seconds: i64,
+}
+
+impl JobsConfig {
+    pub fn new() -> Self {
+        JobsConfig {
+            poll_seconds: env_number("JOBS_POLL_SECONDS", 5),
+            visibility_timeout_seconds: env_number("JOBS_VISIBILITY_TIMEOUT_SECONDS", 600),
+            max_attempts: env_number("JOBS_MAX_ATTEMPTS", 5),
+            backoff_seconds: env_number("JOBS_BACKOFF_SECONDS", 30),
+        }
+    }
+}
+
+fn env_number(name: &str, default: i64) -> i64 {
+    env::var(name)
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number", name))
+        })
+        .unwrap_or(default)
+}
+
+fn env_float(name: &str, default: f64) -> f64 {
+    env::var(name)
+        .map(|value| {
+            value
+                .parse()
+                .unwrap_or_else(|_| panic!("{} must be a number", name))
+        })
+        .unwrap_or(default)
+}
+
 #[derive(Clone)]
 pub struct EmailConfig {
     pub api_key: String,
@@ -103,12 +324,40 @@ impl EmailConfig {
             admin_email,
         }
     }
+
+    pub fn mock() -> Self {
+        EmailConfig {
+            api_key: String::from("mock-sendgrid-key"),
+            from: String::from("mocks@ostrich.so"),
+            admin_email: String::from("v@ostrich.so"),
+        }
+    }
 }
 
 #[derive(Clone)]
 pub struct ZillowApiConfig {
     pub api_host: String,
     pub api_key: String,
+    // the most pages of one search we read, every page costs a request
+    pub max_pages: i64,
+    // the RapidAPI budget: requests_per_second on average, with up to burst at once, kept
+    // in throttle_store so workers can share it
+    pub requests_per_second: f64,
+    pub burst: i64,
+    pub throttle_store: RateLimitStoreKind,
+    // how many properties are fetched at the same time, inside the budget
+    pub concurrency: i64,
+    pub timeout_seconds: i64,
+    pub connect_timeout_seconds: i64,
+    // a 429, 5xx or timeout is tried again up to max_retries times, waiting about
+    // retry_base_milliseconds doubled each time, or as long as Retry-After asks up to
+    // retry_max_seconds
+    pub max_retries: i64,
+    pub retry_base_milliseconds: i64,
+    pub retry_max_seconds: i64,
+    // after breaker_failures of those in a row we stop asking for breaker_cooldown_seconds
+    pub breaker_failures: i64,
+    pub breaker_cooldown_seconds: i64,
 }
 
 impl ZillowApiConfig {
@@ -116,7 +365,38 @@ impl ZillowApiConfig {
         let api_host = env::var("ZILLOW_API_HOST").expect("ZILLOW_API_HOST must be set");
         let api_key = env::var("ZILLOW_API_KEY").expect("ZILLOW_API_KEY must be set");
 
-        ZillowApiConfig { api_host, api_key }
+        ZillowApiConfig::with_host(api_host, api_key)
+    }
+
+    pub fn mock() -> Self {
+        ZillowApiConfig::with_host(
+            String::from("zillow.mocks.ostrich.so"),
+            String::from("mock-rapidapi-key"),
+        )
+    }
+
+    fn with_host(api_host: String, api_key: String) -> Self {
+        let requests_per_second = env_float("ZILLOW_REQUESTS_PER_SECOND", 2.0);
+        if requests_per_second.is_nan() || requests_per_second <= 0.0 {
+            panic!("ZILLOW_REQUESTS_PER_SECOND must be more than 0");
+        }
+
+        ZillowApiConfig {
+            api_host,
+            api_key,
+            max_pages: env_number("ZILLOW_MAX_PAGES", 5),
+            requests_per_second,
+            burst: env_number("ZILLOW_BURST", 2).max(1),
+            throttle_store: store_kind("ZILLOW_THROTTLE_STORE"),
+            concurrency: env_number("ZILLOW_CONCURRENCY", 2).max(1),
+            timeout_seconds: env_number("ZILLOW_TIMEOUT_SECONDS", 10).max(1),
+            connect_timeout_seconds: env_number("ZILLOW_CONNECT_TIMEOUT_SECONDS", 5).max(1),
+            max_retries: env_number("ZILLOW_MAX_RETRIES", 3).max(0),
+            retry_base_milliseconds: env_number("ZILLOW_RETRY_BASE_MILLISECONDS", 500).max(1),
+            retry_max_seconds: env_number("ZILLOW_RETRY_MAX_SECONDS", 30).max(1),
+            breaker_failures: env_number("ZILLOW_BREAKER_FAILURES", 5).max(1),
+            breaker_cooldown_seconds: env_number("ZILLOW_BREAKER_COOLDOWN_SECONDS", 300).max(1),
+        }
     }
 }
 
@@ -136,6 +416,13 @@ impl StripeApiConfig {
             client_secret,
         }
     }
+
+    pub fn mock() -> Self {
+        StripeApiConfig {
+            webhook_signature_secret: String::from("whsec_mock"),
+            client_secret: String::from("sk_test_mock"),
+        }
+    }
 }
 
 #[cfg(feature = "mocks")]
@@ -143,6 +430,7 @@ pub fn generate_mocking_config() -> Config {
     Config::new(true)
 }
 
+// picks the mocking config whenever we are built with `--features mocks`
 pub fn generate_config() -> Config {
-    Config::new(false)
+    Config::new(cfg!(feature = "mocks"))
 }
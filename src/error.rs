@@ -6,6 +6,8 @@ pub enum OstrichErrorType {
     ListingResultError,
     PropertyResultError,
     ApiError,
+    // the provider is down and the circuit breaker has stopped asking it
+    OutageError,
     // UserRecordMissing
 }
 
@@ -0,0 +1,92 @@
+use crate::{
+    db_conn::DbConn,
+    error::{OstrichError, OstrichErrorType},
+    models::{
+        api_key::{self, ApiKey},
+        user::User,
+    },
+    services::api_key::{create_api_key, expires_at, SCOPES},
+    utils::JwtPayload,
+    Forbidden,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+// post body for a new key, it gets every scope unless told otherwise
+#[derive(Deserialize)]
+pub struct PostApiKey {
+    pub name: String,
+    pub scopes: Option<Vec<String>>,
+    pub expires_in_days: Option<i64>,
+}
+
+// the only time the key itself is ever sent back
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+fn bad_request(message: String) -> warp::Rejection {
+    warp::reject::custom(OstrichError::new(message, OstrichErrorType::ApiError))
+}
+
+pub async fn insert_api_key(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+    new_api_key: PostApiKey,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Creating api key {} for {}", new_api_key.name, jwt.sub);
+    let scopes = new_api_key
+        .scopes
+        .unwrap_or_else(|| SCOPES.iter().map(|scope| String::from(*scope)).collect());
+
+    if let Some(unknown) = scopes
+        .iter()
+        .find(|scope| !SCOPES.contains(&scope.as_str()))
+    {
+        return Err(bad_request(format!("UNKNOWN_SCOPE: {}", unknown)));
+    }
+    if matches!(new_api_key.expires_in_days, Some(days) if days <= 0) {
+        return Err(bad_request(String::from("INVALID_EXPIRES_IN_DAYS")));
+    }
+
+    let conn = db_conn.get_conn();
+    let (api_key, key) = create_api_key(
+        &conn,
+        user.id,
+        new_api_key.name,
+        scopes,
+        expires_at(new_api_key.expires_in_days),
+    );
+    Ok(warp::reply::json(&CreatedApiKey { key, api_key }))
+}
+
+pub async fn get_api_keys(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let conn = db_conn.get_conn();
+    log::info!("Getting api keys for {}", jwt.sub);
+    Ok(warp::reply::json(&api_key::read_by_user_id(&conn, user.id)))
+}
+
+pub async fn delete_api_key(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+    id: i32,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let conn = db_conn.get_conn();
+    log::info!("Revoking api key {} for {}", id, jwt.sub);
+    let revoked = api_key::delete_by_id_and_user_id(&conn, id, user.id);
+
+    if revoked.is_empty() {
+        Err(warp::reject::custom(Forbidden))
+    } else {
+        Ok(warp::reply::json(&revoked))
+    }
+}
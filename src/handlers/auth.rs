@@ -1,16 +1,12 @@
 use crate::{
+    db_conn::DbConn,
     handle_succcess_message,
-    services::{
-        self,
-        cognito::{self as cognito_service},
-    },
-    Config,
+    services::{self, identity::IdentityProvider, jwt::AccessToken, rate_limit::RateLimitKey},
+    utils::bearer_token,
 };
-use aws_sdk_cognitoidentityprovider::types::SdkError;
-use aws_sdk_cognitoidentityprovider::Client as CognitoClient;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
-use warp::reject;
+use std::{collections::HashMap, sync::Arc};
+use warp::{http::StatusCode, reject};
 
 // the token data we send back upon login
 #[derive(Serialize)]
@@ -22,6 +18,35 @@ pub struct AuthenticationDetails {
     pub id_token: Option<String>,
 }
 
+// a step the user has to finish before we hand out tokens, like an mfa code
+#[derive(Serialize)]
+pub struct AuthChallenge {
+    pub challenge_name: String,
+    pub session: Option<String>,
+    pub challenge_parameters: HashMap<String, String>,
+}
+
+// login either hands back tokens or a challenge to answer at /respond-to-challenge
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum LoginResult {
+    Authenticated(AuthenticationDetails),
+    Challenge(AuthChallenge),
+}
+
+// the secret to load into an authenticator app
+#[derive(Serialize)]
+pub struct SoftwareTokenAssociation {
+    pub secret_code: Option<String>,
+    pub session: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct SoftwareTokenVerification {
+    pub status: String,
+    pub session: Option<String>,
+}
+
 // post body when logging in
 #[derive(Deserialize)]
 pub struct LoginCredentials {
@@ -57,196 +82,395 @@ pub struct RefreshCredentials {
     pub refresh_token: String,
 }
 
-// error type that we send back to user
+// post body when answering a login challenge, responses are keyed
+// like cognito's, e.g. NEW_PASSWORD or SOFTWARE_TOKEN_MFA_CODE
+#[derive(Deserialize)]
+pub struct ChallengeCredentials {
+    pub username: String,
+    pub challenge_name: String,
+    pub session: String,
+    pub responses: HashMap<String, String>,
+}
+
+// post body when starting totp enrollment, the session is only
+// needed when enrolling in the middle of an MFA_SETUP challenge
+#[derive(Deserialize)]
+pub struct AssociateSoftwareTokenCredentials {
+    pub session: Option<String>,
+}
+
+// post body when finishing totp enrollment with a code from the app
+#[derive(Deserialize)]
+pub struct VerifySoftwareTokenCredentials {
+    pub session: Option<String>,
+    pub code: String,
+    pub device_name: Option<String>,
+}
+
+impl RateLimitKey for LoginCredentials {
+    fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+impl RateLimitKey for ConfirmationCredentials {
+    fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+impl RateLimitKey for UsernameCredentials {
+    fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+impl RateLimitKey for ConfirmForgotPasswordCredentials {
+    fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+impl RateLimitKey for ChallengeCredentials {
+    fn username(&self) -> &str {
+        &self.username
+    }
+}
+
+// post body when a logged in user changes their password
+#[derive(Deserialize)]
+pub struct ChangePasswordCredentials {
+    pub previous_password: String,
+    pub proposed_password: String,
+}
+
+// post body when throwing away a refresh token, like on logout
+#[derive(Deserialize)]
+pub struct RevokeCredentials {
+    pub refresh_token: String,
+}
+
+// stable codes the frontend can switch on instead of parsing provider messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthErrorCode {
+    UserNotConfirmed,
+    NotAuthorized,
+    UserNotFound,
+    UsernameExists,
+    CodeMismatch,
+    ExpiredCode,
+    LimitExceeded,
+    TooManyRequests,
+    TooManyFailedAttempts,
+    InvalidPassword,
+    InvalidParameter,
+    PasswordResetRequired,
+    CodeDeliveryFailure,
+    SoftwareTokenMfaNotFound,
+    InvalidRequest,
+    ServerError,
+}
+
+impl AuthErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthErrorCode::UserNotConfirmed => "USER_NOT_CONFIRMED",
+            AuthErrorCode::NotAuthorized => "NOT_AUTHORIZED",
+            AuthErrorCode::UserNotFound => "USER_NOT_FOUND",
+            AuthErrorCode::UsernameExists => "USERNAME_EXISTS",
+            AuthErrorCode::CodeMismatch => "CODE_MISMATCH",
+            AuthErrorCode::ExpiredCode => "EXPIRED_CODE",
+            AuthErrorCode::LimitExceeded => "LIMIT_EXCEEDED",
+            AuthErrorCode::TooManyRequests => "TOO_MANY_REQUESTS",
+            AuthErrorCode::TooManyFailedAttempts => "TOO_MANY_FAILED_ATTEMPTS",
+            AuthErrorCode::InvalidPassword => "INVALID_PASSWORD",
+            AuthErrorCode::InvalidParameter => "INVALID_PARAMETER",
+            AuthErrorCode::PasswordResetRequired => "PASSWORD_RESET_REQUIRED",
+            AuthErrorCode::CodeDeliveryFailure => "CODE_DELIVERY_FAILURE",
+            AuthErrorCode::SoftwareTokenMfaNotFound => "SOFTWARE_TOKEN_MFA_NOT_FOUND",
+            AuthErrorCode::InvalidRequest => "INVALID_REQUEST",
+            AuthErrorCode::ServerError => "SERVER_ERROR",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AuthErrorCode::UserNotConfirmed | AuthErrorCode::PasswordResetRequired => {
+                StatusCode::FORBIDDEN
+            }
+            AuthErrorCode::NotAuthorized => StatusCode::UNAUTHORIZED,
+            AuthErrorCode::UserNotFound => StatusCode::NOT_FOUND,
+            AuthErrorCode::UsernameExists => StatusCode::CONFLICT,
+            AuthErrorCode::LimitExceeded
+            | AuthErrorCode::TooManyRequests
+            | AuthErrorCode::TooManyFailedAttempts => StatusCode::TOO_MANY_REQUESTS,
+            AuthErrorCode::CodeDeliveryFailure => StatusCode::BAD_GATEWAY,
+            AuthErrorCode::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            AuthErrorCode::CodeMismatch
+            | AuthErrorCode::ExpiredCode
+            | AuthErrorCode::InvalidPassword
+            | AuthErrorCode::InvalidParameter
+            | AuthErrorCode::SoftwareTokenMfaNotFound
+            | AuthErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+// error type that we send back to user, the cause is the provider's own message
 #[derive(Debug)]
-pub struct CognitoError {
+pub struct AuthError {
+    pub code: AuthErrorCode,
     pub cause: String,
 }
 
-impl reject::Reject for CognitoError {}
+impl reject::Reject for AuthError {}
 
-// wrapper to send back cognito errors to our user
-pub fn reject_with_cognito_error(message: &str) -> warp::Rejection {
-    reject::custom(CognitoError::new(String::from(message)))
+// wrapper to send back identity provider errors to our user
+pub fn reject_with_auth_error(code: AuthErrorCode, message: &str) -> warp::Rejection {
+    reject::custom(AuthError::new(code, String::from(message)))
 }
 
-impl CognitoError {
-    pub fn new(cause: String) -> Self {
-        CognitoError { cause }
+impl AuthError {
+    pub fn new(code: AuthErrorCode, cause: String) -> Self {
+        AuthError { code, cause }
     }
 }
 
 pub async fn login(
     login_credentials: LoginCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("Login from {}", login_credentials.username);
-    let login_results = cognito_service::initiate(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        login_credentials.username,
-        login_credentials.password,
-    )
-    .await;
-
-    match login_results {
-        Err(error) => Err(handle_cognito_error(error)),
-        Ok(login_result) => match login_result.authentication_result {
-            Some(authentication_result) => Ok(warp::reply::json(&AuthenticationDetails::from(
-                authentication_result,
-            ))),
-            None => Err(reject_with_cognito_error("No Authentication Results")),
-        },
+    identity_provider
+        .initiate(login_credentials.username, login_credentials.password)
+        .await
+        .map_err(reject::custom)
+        .map(|login_result| warp::reply::json(&login_result))
+}
+
+pub async fn respond_to_challenge(
+    challenge_credentials: ChallengeCredentials,
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!(
+        "Responding to {} from {}",
+        challenge_credentials.challenge_name,
+        challenge_credentials.username
+    );
+    identity_provider
+        .respond_to_challenge(
+            challenge_credentials.username,
+            challenge_credentials.challenge_name,
+            challenge_credentials.session,
+            challenge_credentials.responses,
+        )
+        .await
+        .map_err(reject::custom)
+        .map(|login_result| warp::reply::json(&login_result))
+}
+
+// enrolling either happens while logged in, with the access token as the bearer,
+// or during login with the session from an MFA_SETUP challenge
+fn access_token_or_session(
+    authorization: &Option<String>,
+    session: &Option<String>,
+) -> Result<Option<String>, warp::Rejection> {
+    let access_token = authorization
+        .as_deref()
+        .and_then(bearer_token)
+        .map(String::from);
+
+    if access_token.is_none() && session.is_none() {
+        return Err(reject_with_auth_error(
+            AuthErrorCode::InvalidParameter,
+            "InvalidParameterException: An access token or session is required",
+        ));
     }
+    Ok(access_token)
+}
+
+pub async fn associate_software_token(
+    authorization: Option<String>,
+    associate_credentials: AssociateSoftwareTokenCredentials,
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Associating a software token");
+    let access_token = access_token_or_session(&authorization, &associate_credentials.session)?;
+    identity_provider
+        .associate_software_token(access_token, associate_credentials.session)
+        .await
+        .map_err(reject::custom)
+        .map(|association| warp::reply::json(&association))
+}
+
+pub async fn verify_software_token(
+    authorization: Option<String>,
+    verify_credentials: VerifySoftwareTokenCredentials,
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Verifying a software token");
+    let access_token = access_token_or_session(&authorization, &verify_credentials.session)?;
+    identity_provider
+        .verify_software_token(
+            access_token,
+            verify_credentials.session,
+            verify_credentials.code,
+            verify_credentials.device_name,
+        )
+        .await
+        .map_err(reject::custom)
+        .map(|verification| warp::reply::json(&verification))
 }
 
 pub async fn sign_up(
     login_credentials: LoginCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("SignUp from {}", login_credentials.username);
-    let sign_up_results = cognito_service::sign_up(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        login_credentials.username.clone(),
-        login_credentials.password,
-        login_credentials.username.clone(),
-    )
-    .await;
-
-    match sign_up_results {
-        Err(error) => Err(handle_cognito_error(error)),
-        // here we could check if the user is confirmed already
-        Ok(sign_up_result) => match sign_up_result.user_sub {
-            Some(_) => {
-                log::info!("Creating new user {}", login_credentials.username);
-                Ok(handle_succcess_message(format!(
-                    "SIGN_UP_SUCCESSFULL: {}",
-                    login_credentials.username
-                )))
-            }
-            None => Err(reject_with_cognito_error("No Sign Up Results")),
-        },
-    }
+    identity_provider
+        .sign_up(
+            login_credentials.username.clone(),
+            login_credentials.password,
+            login_credentials.username.clone(),
+        )
+        .await
+        .map_err(reject::custom)?;
+
+    // here we could check if the user is confirmed already
+    log::info!("Creating new user {}", login_credentials.username);
+    Ok(handle_succcess_message(format!(
+        "SIGN_UP_SUCCESSFULL: {}",
+        login_credentials.username
+    )))
 }
 
 pub async fn verify(
     confirmation_credentials: ConfirmationCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("Confirmation from {}", confirmation_credentials.username);
-    let verify_results = cognito_service::verify(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        confirmation_credentials.username.clone(),
-        confirmation_credentials.code,
-    )
-    .await;
-
-    verify_results
-        .map_err(handle_cognito_error)
-        .map(|_| Ok(handle_succcess_message(format!("USER_VERIFIED"))))
+    identity_provider
+        .verify(
+            confirmation_credentials.username,
+            confirmation_credentials.code,
+        )
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("USER_VERIFIED")))
 }
 
 pub async fn resend_code(
     username_credentials: UsernameCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("Resend Confirmation from {}", username_credentials.username);
-    let resend_results = cognito_service::resend_confirmation_code(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        username_credentials.username,
-    )
-    .await;
-
-    resend_results
-        .map_err(handle_cognito_error)
-        .map(|_| Ok(handle_succcess_message(format!("CONFIRMATION_RESENT"))))
+    identity_provider
+        .resend_confirmation_code(username_credentials.username)
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("CONFIRMATION_RESENT")))
 }
 
 pub async fn forgot_password(
     username_credentials: UsernameCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("Forgot password from {}", username_credentials.username);
-    let resend_results = cognito_service::forgot_password(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        username_credentials.username,
-    )
-    .await;
-
-    resend_results
-        .map_err(handle_cognito_error)
-        .map(|_| Ok(handle_succcess_message(format!("PASSWORD_RESET"))))
+    identity_provider
+        .forgot_password(username_credentials.username)
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("PASSWORD_RESET")))
 }
 
 pub async fn confirm_forgot_password(
     confirm_forgot_password_credentials: ConfirmForgotPasswordCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!(
         "Confirming forgot password from {}",
         confirm_forgot_password_credentials.username
     );
-    let resend_results = cognito_service::confirm_forgot_password(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        confirm_forgot_password_credentials.username,
-        confirm_forgot_password_credentials.password,
-        confirm_forgot_password_credentials.code,
-    )
-    .await;
-
-    resend_results
-        .map_err(handle_cognito_error)
-        .map(|_| Ok(handle_succcess_message(format!("PASSWORD_RESET"))))
+    identity_provider
+        .confirm_forgot_password(
+            confirm_forgot_password_credentials.username,
+            confirm_forgot_password_credentials.password,
+            confirm_forgot_password_credentials.code,
+        )
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("PASSWORD_RESET")))
 }
 
 pub async fn refresh(
     refresh_credentials: RefreshCredentials,
-    config: Arc<Config>,
-    cognito: Arc<CognitoClient>,
+    identity_provider: Arc<dyn IdentityProvider>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     log::info!("Login from {}", refresh_credentials.username);
-    let login_results = cognito_service::refresh(
-        cognito,
-        format!("{}", config.cognito.client_id),
-        format!("{}", config.cognito.secret_key),
-        refresh_credentials.username,
-        refresh_credentials.refresh_token,
-    )
-    .await;
-
-    match login_results {
-        Err(error) => Err(handle_cognito_error(error)),
-        Ok(login_result) => match login_result.authentication_result {
-            Some(authentication_result) => Ok(warp::reply::json(&AuthenticationDetails::from(
-                authentication_result,
-            ))),
-            None => Err(reject_with_cognito_error("No Authentication Results")),
-        },
-    }
+    identity_provider
+        .refresh(
+            refresh_credentials.username,
+            refresh_credentials.refresh_token,
+        )
+        .await
+        .map_err(reject::custom)
+        .map(|authentication_details| warp::reply::json(&authentication_details))
 }
 
-// this will either make a new error out of a cognito message
-// or provide a catch all message
-fn handle_cognito_error<T: std::fmt::Display>(error: SdkError<T>) -> warp::Rejection {
-    match error {
-        SdkError::ServiceError { err, raw: _ } => reject_with_cognito_error(&format!("{}", err)),
-        _ => reject_with_cognito_error("SERVER_ERROR"),
-    }
+pub async fn change_password(
+    access_token: AccessToken,
+    change_password_credentials: ChangePasswordCredentials,
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Changing password for {}", access_token.username);
+    identity_provider
+        .change_password(
+            &access_token,
+            change_password_credentials.previous_password,
+            change_password_credentials.proposed_password,
+        )
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("PASSWORD_CHANGED")))
+}
+
+pub async fn sign_out(
+    access_token: AccessToken,
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Signing out {} everywhere", access_token.username);
+    identity_provider
+        .global_sign_out(&access_token)
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("SIGNED_OUT")))
+}
+
+pub async fn revoke(
+    revoke_credentials: RevokeCredentials,
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Revoking a refresh token");
+    identity_provider
+        .revoke_refresh_token(revoke_credentials.refresh_token)
+        .await
+        .map_err(reject::custom)
+        .map(|_| handle_succcess_message(format!("TOKEN_REVOKED")))
+}
+
+pub async fn delete_account(
+    access_token: AccessToken,
+    identity_provider: Arc<dyn IdentityProvider>,
+    db_conn: Arc<DbConn>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Deleting account for {}", access_token.username);
+    // a bad token fails at the identity provider, which rolls back our rows
+    let user_ids = services::user::delete_account(
+        &db_conn,
+        access_token.sub.clone(),
+        identity_provider.delete_user(&access_token),
+    )
+    .await?;
+    log::info!("Soft deleted users {:?}", user_ids);
+    Ok(handle_succcess_message(format!("ACCOUNT_DELETED")))
 }
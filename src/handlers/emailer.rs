@@ -1,19 +1,66 @@
 use crate::{
     config::Config,
     db_conn::DbConn,
+    error::{OstrichError, OstrichErrorType},
+    handlers::auth::{reject_with_auth_error, AuthErrorCode},
     models::{
-        emailer::{self, PostEmailer, PutEmailer},
+        emailer::{self, Emailer, PatchEmailer, PostEmailer, PutEmailer},
+        emailer_run::{self, EmailerRun},
+        listing_data::NewListingData,
+        plan::Plan,
         user::User,
     },
-    services::zillow::{self, ZillowSearchParameters},
-    utils::JwtPayload,
+    services::{
+        email, entitlements,
+        listing_source::{self, ListingSource, SearchParameters, SourceName},
+        rate_limit::RateLimiter,
+        schedule::{self, Frequency},
+        validation::{self, EmailerFields},
+        zillow::{ZillowClient, ZillowSource},
+    },
+    utils::{now, JwtPayload, PageQuery},
     Forbidden,
 };
-use std::sync::Arc;
+use diesel::PgConnection;
+use serde::Serialize;
+use std::{collections::HashSet, sync::Arc};
+use tokio_stream::StreamExt;
+use warp::http::header::ETAG;
+
+#[derive(Serialize)]
+pub struct EmailerRuns {
+    runs: Vec<EmailerRun>,
+    page: i64,
+    per_page: i64,
+    total: i64,
+}
+
+// frequency used to be free text, so settle it on one of ours before it is saved
+fn parse_frequency(frequency: &str) -> Result<Frequency, warp::Rejection> {
+    frequency
+        .parse::<Frequency>()
+        .map_err(|e| warp::reject::custom(OstrichError::new(e, OstrichErrorType::ApiError)))
+}
+
+// settled on one spelling before it is saved, validation has already ruled out anything else
+fn normalize_listing_source(listing_source: &str) -> String {
+    listing_source
+        .parse::<SourceName>()
+        .map(|source| source.as_string())
+        .unwrap_or_else(|_| String::from(listing_source))
+}
+
+fn validate(config: &Config, user: &User, emailer: EmailerFields) -> Result<(), warp::Rejection> {
+    validation::check_emailer(&emailer, config.listing_files_dir.as_deref(), user.id)
+        .map_err(warp::reject::custom)
+}
 
-pub async fn get_all_emailers(db_conn: Arc<DbConn>) -> Result<impl warp::Reply, warp::Rejection> {
+pub async fn get_all_emailers(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+) -> Result<impl warp::Reply, warp::Rejection> {
     let conn = db_conn.get_conn();
-    log::info!("Getting all emailers");
+    log::info!("Getting all emailers for admin {}", jwt.sub);
     Ok(warp::reply::json(&emailer::read(&conn)))
 }
 
@@ -44,55 +91,433 @@ pub async fn delete_emailer_by_id_and_authentication_id(
     }
 }
 
+pub async fn get_emailer_runs(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+    id: i32,
+    page: PageQuery,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let conn = db_conn.get_conn();
+    log::info!("Getting runs of emailer {} for {}", id, jwt.sub);
+    let owned = emailer::get_by_id(&conn, id)
+        .iter()
+        .any(|emailer| emailer.user_id == user.id);
+    if !owned {
+        return Err(warp::reject::custom(Forbidden));
+    }
+
+    let server_error = |e: diesel::result::Error| {
+        log::error!("Could not load runs of emailer {}: {}", id, e);
+        reject_with_auth_error(
+            AuthErrorCode::ServerError,
+            "Could not load the emailer's runs",
+        )
+    };
+    let runs = emailer_run::read_by_emailer_id(&conn, id, page.per_page(), page.offset())
+        .map_err(server_error)?;
+    let total = emailer_run::count_by_emailer_id(&conn, id).map_err(server_error)?;
+    Ok(warp::reply::json(&EmailerRuns {
+        runs,
+        page: page.page(),
+        per_page: page.per_page(),
+        total,
+    }))
+}
+
 pub async fn insert_emailer(
     jwt: JwtPayload,
     db_conn: Arc<DbConn>,
     user: User,
-    new_emailer: PostEmailer,
+    config: Arc<Config>,
+    mut new_emailer: PostEmailer,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let conn = db_conn.get_conn();
     log::info!("Inserting new emailer");
-    let new_emailer = emailer::NewEmailer::new(new_emailer, user.id, jwt.email);
-    Ok(warp::reply::json(&new_emailer.insert(&conn)))
+    validate(&config, &user, (&new_emailer).into())?;
+    let frequency = parse_frequency(&new_emailer.frequency)?;
+    new_emailer.frequency = String::from(frequency.as_str());
+    new_emailer.listing_source = normalize_listing_source(&new_emailer.listing_source);
+    // new emailers go out on the next run, then settle into their schedule
+    let new_emailer = emailer::NewEmailer::new(new_emailer, user.id, jwt.email, now());
+    let emailer = entitlements::create_emailer(&conn, user.id, frequency.as_str(), &new_emailer)?;
+    Ok(warp::reply::json(&emailer))
+}
+
+// the emailer someone is writing to has moved on since they read it
+#[derive(Debug)]
+pub struct VersionConflict {
+    pub id: i32,
+    pub version: i32,
+}
+impl warp::reject::Reject for VersionConflict {}
+
+// a write that didn't say which version it is writing over
+#[derive(Debug)]
+pub struct IfMatchRequired;
+impl warp::reject::Reject for IfMatchRequired {}
+
+fn etag(emailer: &Emailer) -> String {
+    format!("\"{}\"", emailer.version)
+}
+
+// If-Match takes the etag we hand out, and has to be there so clients can't write over each
+// other by leaving it off. * is how to say writing over whatever is there is on purpose
+fn if_match_version(if_match: Option<String>) -> Result<Option<i32>, warp::Rejection> {
+    let tag = match if_match.as_deref().map(str::trim) {
+        None => return Err(warp::reject::custom(IfMatchRequired)),
+        Some("*") => return Ok(None),
+        Some(tag) => tag,
+    };
+    tag.trim_start_matches("W/")
+        .trim_matches('"')
+        .parse::<i32>()
+        .map(Some)
+        .map_err(|_| {
+            warp::reject::custom(OstrichError::new(
+                format!("BAD_IF_MATCH: {}", tag),
+                OstrichErrorType::ApiError,
+            ))
+        })
+}
+
+fn owned_emailer(conn: &PgConnection, id: i32, user: &User) -> Result<Emailer, warp::Rejection> {
+    emailer::get_by_id_and_user_id(conn, id, user.id)
+        .into_iter()
+        .next()
+        .ok_or_else(|| warp::reject::custom(Forbidden))
+}
+
+// PUT and PATCH both end up here with the whole emailer as it should be
+fn save_emailer(
+    conn: &PgConnection,
+    config: &Config,
+    user: &User,
+    current: &Emailer,
+    version: Option<i32>,
+    mut updated_emailer: PutEmailer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let version = version.unwrap_or(current.version);
+    if version != current.version {
+        return Err(warp::reject::custom(VersionConflict {
+            id: current.id,
+            version: current.version,
+        }));
+    }
+
+    updated_emailer.id = current.id;
+    validate(config, user, (&updated_emailer).into())?;
+    let frequency = parse_frequency(&updated_emailer.frequency)?;
+    updated_emailer.frequency = String::from(frequency.as_str());
+    updated_emailer.listing_source = normalize_listing_source(&updated_emailer.listing_source);
+    updated_emailer.next_run_at = if updated_emailer.frequency != current.frequency {
+        frequency.next_run_at(now(), schedule::time_zone(&user.time_zone))
+    } else {
+        current.next_run_at
+    };
+    entitlements::check_emailer_update(conn, user, current.id, &updated_emailer.frequency)?;
+
+    let saved = emailer::update_emailer(conn, updated_emailer, user.id, version).map_err(|e| {
+        log::error!("Could not update emailer {}: {}", current.id, e);
+        reject_with_auth_error(AuthErrorCode::ServerError, "Could not update the emailer")
+    })?;
+    match saved {
+        Some(saved) => Ok(warp::reply::with_header(
+            warp::reply::json(&saved),
+            ETAG,
+            etag(&saved),
+        )),
+        // written or deleted between our read and our write
+        None => {
+            let current = owned_emailer(conn, current.id, user)?;
+            Err(warp::reject::custom(VersionConflict {
+                id: current.id,
+                version: current.version,
+            }))
+        }
+    }
 }
 
 pub async fn update_emailer(
     jwt: JwtPayload,
     db_conn: Arc<DbConn>,
     user: User,
+    id: i32,
+    if_match: Option<String>,
+    config: Arc<Config>,
     updated_emailer: PutEmailer,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let conn = db_conn.get_conn();
-    log::info!("Updating emailer");
-    let new_emailer = emailer::update_emailer(&conn, updated_emailer, user.id);
-    if new_emailer == 0 {
-        Err(warp::reject::custom(Forbidden))
-    } else {
-        Ok(warp::reply::json(&new_emailer))
-    }
+    log::info!("Updating emailer {} for {}", id, jwt.sub);
+    let version = if_match_version(if_match)?;
+    let current = owned_emailer(&conn, id, &user)?;
+    save_emailer(&conn, &config, &user, &current, version, updated_emailer)
+}
+
+pub async fn patch_emailer(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+    id: i32,
+    if_match: Option<String>,
+    config: Arc<Config>,
+    patch: PatchEmailer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let conn = db_conn.get_conn();
+    log::info!("Patching emailer {} for {}", id, jwt.sub);
+    let version = if_match_version(if_match)?;
+    let current = owned_emailer(&conn, id, &user)?;
+    let updated_emailer = patch.apply(&current);
+    save_emailer(&conn, &config, &user, &current, version, updated_emailer)
 }
 
 pub async fn test_emailer_search_params(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
     config: Arc<Config>,
-    reqwest_client: Arc<reqwest::Client>,
-    test_emailer_params: ZillowSearchParameters,
+    zillow_client: Arc<ZillowClient>,
+    rate_limiter: Arc<RateLimiter>,
+    test_emailer_params: SearchParameters,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    log::info!("Testing {}", test_emailer_params.search_param);
+    log::info!(
+        "Testing {} for {}",
+        test_emailer_params.search_param,
+        jwt.sub
+    );
+    check_search_rate_limit(&rate_limiter, &user)?;
 
-    let api_url =
-        zillow::get_zillow_listing_url_from_params(config.clone(), &test_emailer_params, None);
+    let source = ZillowSource::new(config, zillow_client);
+    let plan = entitlements::get_plan(&db_conn.get_conn(), &user);
+    check_api_quota(&db_conn.get_conn(), &user, &plan, &source)?;
+    let addresses = match source.search(&test_emailer_params).await {
+        Ok(listing_pages) => {
+            listing_pages
+                .listings
+                .map(|listing| listing.map(|l| l.address.map_or(String::from("Missing"), |x| x)))
+                .collect::<Result<Vec<String>, OstrichError>>()
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    entitlements::record_api_calls(&db_conn.get_conn(), user.id, &source);
+    addresses
+        .map(|addresses| warp::reply::json(&addresses))
+        .map_err(warp::reject::custom)
+}
 
-    match zillow::get_zillow_listing_results(config.clone(), reqwest_client.clone(), api_url).await
-    {
-        Ok(listing_results) => {
-            let addresses = listing_results
-                .props
-                .into_iter()
-                .map(|listing| listing.address.map_or(String::from("Missing"), |x| x))
-                .collect::<Vec<String>>();
+// previews and test searches spend the same zillow quota, so they are counted in one bucket
+const SEARCH_RATE_LIMIT: &str = "search";
 
-            Ok(warp::reply::json(&addresses))
+// searches against a paid api stop once the plan's calls for the month are used up, with a 402
+fn check_api_quota(
+    conn: &PgConnection,
+    user: &User,
+    plan: &Plan,
+    source: &dyn ListingSource,
+) -> Result<(), warp::Rejection> {
+    match entitlements::check_source_quota(conn, user.id, plan, source) {
+        Ok(Ok(())) => Ok(()),
+        Ok(Err(e)) => {
+            log::warn!(
+                "User {} has used {} of {} api calls",
+                user.id,
+                e.used,
+                e.allowed
+            );
+            Err(warp::reject::custom(e))
+        }
+        Err(e) => {
+            log::error!("Could not check api usage of user {}: {}", user.id, e);
+            Err(reject_with_auth_error(
+                AuthErrorCode::ServerError,
+                "Could not check the plan's api quota",
+            ))
         }
+    }
+}
+
+fn check_search_rate_limit(rate_limiter: &RateLimiter, user: &User) -> Result<(), warp::Rejection> {
+    rate_limiter
+        .check_user(SEARCH_RATE_LIMIT, user.id)
+        .map_err(|rate_limited| {
+            log::warn!("Rate limiting searches for user {}", user.id);
+            warp::reject::custom(rate_limited)
+        })
+}
+
+#[derive(Serialize)]
+pub struct EmailerPreview {
+    zillow_url: String,
+    total_results: Option<i64>,
+    properties_failed: i32,
+    already_sent: i32,
+    html: String,
+    listings: Vec<NewListingData>,
+}
+
+// runs the search the emailer's next email would, without saving listings or sending anything.
+// every one spends from the user's preview limit, since it searches like a real run
+async fn preview(
+    config: Arc<Config>,
+    zillow_client: Arc<ZillowClient>,
+    db_conn: Arc<DbConn>,
+    rate_limiter: Arc<RateLimiter>,
+    user: &User,
+    emailer: &Emailer,
+) -> Result<EmailerPreview, warp::Rejection> {
+    validate(&config, user, emailer.into())?;
+    check_search_rate_limit(&rate_limiter, user)?;
+    let source =
+        listing_source::get_listing_source(config, zillow_client, &emailer.listing_source, user.id)
+            .map_err(warp::reject::custom)?;
+    let plan = entitlements::get_plan(&db_conn.get_conn(), user);
+    check_api_quota(&db_conn.get_conn(), user, &plan, source.as_ref())?;
+    let already_sent =
+        listing_source::sent_listings(&db_conn, emailer).map_err(warp::reject::custom)?;
+    let preview = preview_search(source.as_ref(), emailer, &plan, &already_sent).await;
+    entitlements::record_api_calls(&db_conn.get_conn(), user.id, source.as_ref());
+    preview
+}
+
+// the search itself never gets a connection, so there is no way for it to save listings
+async fn preview_search(
+    source: &dyn ListingSource,
+    emailer: &Emailer,
+    plan: &Plan,
+    already_sent: &HashSet<String>,
+) -> Result<EmailerPreview, warp::Rejection> {
+    let frequency = parse_frequency(&emailer.frequency)?;
+    let days_on_market = schedule::days_on_market(frequency, emailer.last_run_at, now());
+    let mut search = SearchParameters::from(emailer);
+    search.days_on_market = Some(days_on_market);
+    let zillow_url = source.search_url(&search);
+
+    let result = listing_source::search_listings(
+        source,
+        emailer,
+        &search,
+        Some(entitlements::max_listings_per_email(plan)),
+        already_sent,
+    )
+    .await;
+    match result {
+        Ok(listing_search) => Ok(EmailerPreview {
+            zillow_url,
+            total_results: listing_search.total_results,
+            properties_failed: listing_search.properties_failed,
+            already_sent: listing_search.already_sent,
+            html: listing_source::listings_email_body(
+                email::get_ostrich_email_body(emailer),
+                &listing_search.listings,
+            ),
+            listings: listing_search.listings,
+        }),
+        // the same email the emailer would send when nothing turns up
+        Err(e) if matches!(e.etype, OstrichErrorType::ListingResultError) => Ok(EmailerPreview {
+            zillow_url,
+            total_results: Some(0),
+            properties_failed: 0,
+            already_sent: 0,
+            html: String::from(email::EMPTY_LISTINGS_BODY),
+            listings: Vec::new(),
+        }),
         Err(e) => Err(warp::reject::custom(e)),
     }
 }
+
+pub async fn preview_emailer(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+    id: i32,
+    config: Arc<Config>,
+    zillow_client: Arc<ZillowClient>,
+    rate_limiter: Arc<RateLimiter>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Previewing emailer {} for {}", id, jwt.sub);
+    let emailer = owned_emailer(&db_conn.get_conn(), id, &user)?;
+    let preview = preview(
+        config,
+        zillow_client,
+        db_conn,
+        rate_limiter,
+        &user,
+        &emailer,
+    )
+    .await?;
+    Ok(warp::reply::json(&preview))
+}
+
+pub async fn preview_new_emailer(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: User,
+    config: Arc<Config>,
+    zillow_client: Arc<ZillowClient>,
+    rate_limiter: Arc<RateLimiter>,
+    new_emailer: PostEmailer,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    log::info!("Previewing an unsaved emailer for {}", jwt.sub);
+    let emailer = new_emailer.unsaved(user.id, jwt.email);
+    let preview = preview(
+        config,
+        zillow_client,
+        db_conn,
+        rate_limiter,
+        &user,
+        &emailer,
+    )
+    .await?;
+    Ok(warp::reply::json(&preview))
+}
+
+#[tokio::test]
+async fn previews_search_like_a_run() {
+    let emailer = listing_source::test_emailer();
+    let plan = Plan {
+        id: 1,
+        name: String::from("tier_1"),
+        stripe_product_name: Some(String::from("Tier 1")),
+        max_emailers: 1,
+        allowed_frequencies: vec![String::from("daily")],
+        max_listings_per_email: 2,
+        monthly_api_quota: 1000,
+        created_at: now(),
+        updated_at: None,
+    };
+    let source = listing_source::FakeSource {
+        ids: vec!["1", "2", "3"],
+        page_error: None,
+        fetched: std::sync::Mutex::new(Vec::new()),
+    };
+
+    let preview = preview_search(&source, &emailer, &plan, &HashSet::new())
+        .await
+        .unwrap();
+    let zpids = preview
+        .listings
+        .iter()
+        .map(|listing| listing.zpid.clone().unwrap())
+        .collect::<Vec<String>>();
+    // held to the plan like the email would be
+    assert_eq!(zpids, vec!["1", "2"]);
+    assert_eq!(*source.fetched.lock().unwrap(), vec!["1", "2"]);
+    assert_eq!(preview.already_sent, 0);
+    assert!(preview.html.contains("<h1>"));
+}
+
+#[test]
+fn writes_need_if_match() {
+    assert!(if_match_version(None).is_err());
+    assert_eq!(if_match_version(Some(String::from("*"))).unwrap(), None);
+    assert_eq!(
+        if_match_version(Some(String::from("\"3\""))).unwrap(),
+        Some(3)
+    );
+    assert_eq!(
+        if_match_version(Some(String::from("W/\"3\""))).unwrap(),
+        Some(3)
+    );
+    assert!(if_match_version(Some(String::from("three"))).is_err());
+}
@@ -0,0 +1,11 @@
+use crate::services::metrics::Metrics;
+use std::sync::Arc;
+use warp::http::header::CONTENT_TYPE;
+
+pub async fn get_metrics(metrics: Arc<dyn Metrics>) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::with_header(
+        metrics.render(),
+        CONTENT_TYPE,
+        "text/plain; version=0.0.4",
+    ))
+}
@@ -1,4 +1,6 @@
+pub mod api_key;
 pub mod auth;
 pub mod emailer;
+pub mod metrics;
 pub mod stripe;
 pub mod user;
@@ -55,13 +55,15 @@ pub async fn handle_webhook(
             services::email::email_admin_on_tier_change(
                 &email_client,
                 config,
-                new_user_email,
+                new_user_email.clone(),
                 billing_id.clone(),
                 active_user.billing_id.clone(),
             )
             .await;
 
-            let results = user::update_user(&conn, customer.email.unwrap(), billing_id);
+            let plan = services::entitlements::get_plan_for_product(&conn, &billing_id);
+            log::info!("Moving {} onto the {} plan", new_user_email, plan.name);
+            let results = user::update_user(&conn, customer.email.unwrap(), billing_id, plan.id);
             Ok(warp::reply())
         }
         _ => Ok(warp::reply()),
@@ -1,5 +1,88 @@
-use crate::models::user;
+use crate::{
+    db_conn::DbConn,
+    error::{OstrichError, OstrichErrorType},
+    handlers::auth::{reject_with_auth_error, AuthErrorCode},
+    models::{emailer, user},
+    services::{
+        entitlements,
+        schedule::{self, Frequency},
+    },
+    utils::{now, JwtPayload},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
-pub async fn get_user(user: user::User) -> Result<impl warp::Reply, warp::Rejection> {
+// the settings a user can change about themselves
+#[derive(Deserialize)]
+pub struct PutUser {
+    pub time_zone: String,
+}
+
+// the user along with how much of their plan they are using
+#[derive(Serialize)]
+pub struct UserWithUsage {
+    #[serde(flatten)]
+    pub user: user::User,
+    pub plan: String,
+    pub emailer_limit: i64,
+    pub emailers_used: i64,
+    pub api_quota: i64,
+    pub api_calls_used: i64,
+}
+
+pub async fn get_user(
+    user: user::User,
+    db_conn: Arc<DbConn>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let conn = db_conn.get_conn();
+    let plan = entitlements::get_plan(&conn, &user);
+    let load_error = |e: diesel::result::Error| {
+        log::error!("Could not load usage of user {}: {}", user.id, e);
+        reject_with_auth_error(AuthErrorCode::ServerError, "Could not load user")
+    };
+    let emailers_used = emailer::count_by_user_id(&conn, user.id).map_err(load_error)?;
+    let api_calls_used = entitlements::api_calls_used(&conn, user.id).map_err(load_error)?;
+
+    Ok(warp::reply::json(&UserWithUsage {
+        emailer_limit: entitlements::emailer_limit(&plan) as i64,
+        api_quota: plan.monthly_api_quota as i64,
+        plan: plan.name,
+        emailers_used,
+        api_calls_used,
+        user,
+    }))
+}
+
+// moving time zones moves every emailer's next send time along with it
+pub async fn update_user(
+    jwt: JwtPayload,
+    db_conn: Arc<DbConn>,
+    user: user::User,
+    put_user: PutUser,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let time_zone = schedule::parse_time_zone(&put_user.time_zone).ok_or_else(|| {
+        warp::reject::custom(OstrichError::new(
+            format!("UNKNOWN_TIME_ZONE: {}", put_user.time_zone),
+            OstrichErrorType::ApiError,
+        ))
+    })?;
+
+    let conn = db_conn.get_conn();
+    log::info!("Moving {} to {}", jwt.sub, time_zone.name());
+    user::update_time_zone(&conn, user.id, String::from(time_zone.name()));
+
+    let now = now();
+    for emailer in emailer::read_by_user_id(&conn, user.id) {
+        let frequency = emailer
+            .frequency
+            .parse::<Frequency>()
+            .unwrap_or(Frequency::Daily);
+        emailer::reschedule(&conn, emailer.id, frequency.next_run_at(now, time_zone));
+    }
+
+    let user = user::get_user_by_id(&conn, user.id)
+        .first()
+        .cloned()
+        .ok_or_else(warp::reject::not_found)?;
     Ok(warp::reply::json(&user))
 }
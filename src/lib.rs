@@ -15,14 +15,25 @@ pub mod utils;
 use crate::{
     config::Config,
     db_conn::DbConn,
-    utils::{decode_bearer, JwtPayload},
+    models::user::{get_user_by_authentication_id, User, ADMIN_ROLE},
+    services::{
+        api_key,
+        entitlements::{FrequencyNotAllowed, PlanLimitExceeded},
+        jwt::{with_jwt_verifier, AccessToken, JwtError, JwtVerifier},
+        rate_limit::RateLimited,
+        validation::{FieldError, ValidationFailed},
+        zillow::ZillowClient,
+    },
+    utils::{api_key_token, bearer_token, JwtPayload},
 };
 use serde::Serialize;
 use std::error::Error;
 use std::{convert::Infallible, sync::Arc};
 use warp::{
-    filters::body::BodyDeserializeError, filters::BoxedFilter, http::StatusCode, Filter, Rejection,
-    Reply,
+    filters::body::BodyDeserializeError,
+    filters::BoxedFilter,
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    Filter, Rejection, Reply,
 };
 
 // A simple type alias so as to DRY.
@@ -36,13 +47,110 @@ pub fn with_db_conn(conn: Arc<DbConn>) -> warp::filters::BoxedFilter<(Arc<DbConn
     warp::any().map(move || conn.clone()).boxed()
 }
 
-async fn with_jwt(jwt: String) -> Result<JwtPayload, warp::Rejection> {
-    decode_bearer(&jwt).map_err(|_| warp::reject::custom(BadJwt))
+pub fn with_jwt(verifier: Arc<JwtVerifier>) -> BoxedFilter<(JwtPayload,)> {
+    warp::header::<String>("authorization")
+        .and(with_jwt_verifier(verifier))
+        .and_then(verify_jwt)
+        .boxed()
 }
 
-pub fn with_reqwest_client(
-    client: Arc<reqwest::Client>,
-) -> warp::filters::BoxedFilter<(Arc<reqwest::Client>,)> {
+async fn verify_jwt(
+    header: String,
+    verifier: Arc<JwtVerifier>,
+) -> Result<JwtPayload, warp::Rejection> {
+    let token = bearer_token(&header).ok_or_else(|| warp::reject::custom(BadJwt))?;
+    verifier.verify(token).await.map_err(reject_jwt)
+}
+
+// the emailer routes take either an id token or one of the user's api keys, and the
+// api key has to carry the scope; either way the handlers get the same user
+pub fn with_user_auth(
+    verifier: Arc<JwtVerifier>,
+    conn: Arc<DbConn>,
+    scope: &'static str,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User)> {
+    warp::header::<String>("authorization")
+        .and(with_jwt_verifier(verifier))
+        .and(with_db_conn(conn))
+        .and_then(move |header, verifier, conn| authenticate_user(header, verifier, conn, scope))
+        .untuple_one()
+        .boxed()
+}
+
+async fn authenticate_user(
+    header: String,
+    verifier: Arc<JwtVerifier>,
+    conn: Arc<DbConn>,
+    scope: &'static str,
+) -> Result<(JwtPayload, Arc<DbConn>, User), warp::Rejection> {
+    if let Some(key) = api_key_token(&header) {
+        let user = api_key::authenticate(&conn.get_conn(), key, scope)?;
+        let jwt = JwtPayload {
+            sub: user.authentication_id.clone(),
+            email: user.email.clone(),
+            groups: vec![],
+        };
+        return Ok((jwt, conn, user));
+    }
+
+    let jwt = verify_jwt(header, verifier).await?;
+    services::user::with_token_db_and_user(jwt, conn).await
+}
+
+// only lets admins through, by their cognito group or their users row
+pub fn with_admin(verifier: Arc<JwtVerifier>, conn: Arc<DbConn>) -> BoxedFilter<(JwtPayload,)> {
+    with_jwt(verifier)
+        .and(with_db_conn(conn))
+        .and_then(require_admin)
+        .boxed()
+}
+
+async fn require_admin(jwt: JwtPayload, conn: Arc<DbConn>) -> Result<JwtPayload, warp::Rejection> {
+    if jwt.groups.iter().any(|group| group == ADMIN_ROLE) {
+        return Ok(jwt);
+    }
+
+    let is_admin = get_user_by_authentication_id(&conn.get_conn(), jwt.sub.clone())
+        .iter()
+        .any(|user| user.is_admin());
+    if is_admin {
+        Ok(jwt)
+    } else {
+        log::warn!("{} is not an admin", jwt.sub);
+        Err(warp::reject::custom(Forbidden))
+    }
+}
+
+// account changes go through the user pool, which wants the access token rather than the id token
+pub fn with_access_token(verifier: Arc<JwtVerifier>) -> BoxedFilter<(AccessToken,)> {
+    warp::header::<String>("authorization")
+        .and(with_jwt_verifier(verifier))
+        .and_then(verify_access_token)
+        .boxed()
+}
+
+async fn verify_access_token(
+    header: String,
+    verifier: Arc<JwtVerifier>,
+) -> Result<AccessToken, warp::Rejection> {
+    let token = bearer_token(&header).ok_or_else(|| warp::reject::custom(BadJwt))?;
+    verifier.verify_access(token).await.map_err(reject_jwt)
+}
+
+fn reject_jwt(e: JwtError) -> warp::Rejection {
+    match e {
+        JwtError::Expired => warp::reject::custom(ExpiredJwt),
+        JwtError::InvalidAudience => warp::reject::custom(WrongAudience),
+        JwtError::Invalid(cause) => {
+            log::warn!("Rejecting jwt: {}", cause);
+            warp::reject::custom(BadJwt)
+        }
+    }
+}
+
+pub fn with_zillow_client(
+    client: Arc<ZillowClient>,
+) -> warp::filters::BoxedFilter<(Arc<ZillowClient>,)> {
     warp::any().map(move || client.clone()).boxed()
 }
 
@@ -51,6 +159,23 @@ pub fn with_reqwest_client(
 pub struct ErrorMessage {
     code: u16,
     message: String,
+    // the upstream explanation, when the message is one of our error codes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+    // which plan limit was hit and how much of it is in use
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<PlanLimit>,
+    // every field that failed validation
+    #[serde(skip_serializing_if = "Option::is_none")]
+    errors: Option<Vec<FieldError>>,
+}
+
+#[derive(Serialize)]
+struct PlanLimit {
+    plan: String,
+    name: String,
+    allowed: i64,
+    used: i64,
 }
 
 #[derive(Serialize)]
@@ -63,28 +188,108 @@ pub struct SuccessMessage {
 struct BadJwt;
 impl warp::reject::Reject for BadJwt {}
 
+#[derive(Debug)]
+struct ExpiredJwt;
+impl warp::reject::Reject for ExpiredJwt {}
+
+#[derive(Debug)]
+struct WrongAudience;
+impl warp::reject::Reject for WrongAudience {}
+
 #[derive(Debug)]
 struct Forbidden;
 impl warp::reject::Reject for Forbidden {}
 
+#[derive(Debug)]
+struct BadApiKey;
+impl warp::reject::Reject for BadApiKey {}
+
 // This function receives a `Rejection` and tries to return a custom
 // value, otherwise simply passes the rejection along.
 pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let code;
     let message;
+    let mut detail = None;
+    let mut retry_after = None;
+    let mut limit = None;
+    let mut errors = None;
 
     if err.is_not_found() {
         code = StatusCode::NOT_FOUND;
         message = String::from("NOT_FOUND");
-    } else if let Some(e) = err.find::<handlers::auth::CognitoError>() {
-        code = StatusCode::BAD_REQUEST;
-        message = e.cause.clone();
+    } else if let Some(e) = err.find::<handlers::auth::AuthError>() {
+        code = e.code.status();
+        message = String::from(e.code.as_str());
+        detail = Some(e.cause.clone());
+    } else if let Some(e) = err.find::<RateLimited>() {
+        code = StatusCode::TOO_MANY_REQUESTS;
+        message = String::from("TOO_MANY_REQUESTS");
+        detail = Some(format!("Try again in {} seconds", e.retry_after));
+        retry_after = Some(e.retry_after);
+    } else if let Some(e) = err.find::<PlanLimitExceeded>() {
+        code = StatusCode::PAYMENT_REQUIRED;
+        message = String::from("PLAN_LIMIT_EXCEEDED");
+        detail = Some(format!(
+            "The {} plan's {} is {} and {} are in use",
+            e.plan, e.limit, e.allowed, e.used
+        ));
+        limit = Some(PlanLimit {
+            plan: e.plan.clone(),
+            name: String::from(e.limit),
+            allowed: e.allowed,
+            used: e.used,
+        });
+    } else if let Some(e) = err.find::<FrequencyNotAllowed>() {
+        code = StatusCode::FORBIDDEN;
+        message = String::from("FREQUENCY_NOT_ALLOWED");
+        detail = Some(format!(
+            "The {} plan does not include {} emailers, only {}",
+            e.plan,
+            e.frequency,
+            e.allowed.join(", ")
+        ));
+    } else if let Some(e) = err.find::<handlers::emailer::VersionConflict>() {
+        code = StatusCode::CONFLICT;
+        message = String::from("VERSION_CONFLICT");
+        detail = Some(format!(
+            "Emailer {} has changed and is at version {}, reload it and try again",
+            e.id, e.version
+        ));
+    } else if err.find::<handlers::emailer::IfMatchRequired>().is_some() {
+        code = StatusCode::PRECONDITION_REQUIRED;
+        message = String::from("IF_MATCH_REQUIRED");
+        detail = Some(String::from(
+            "Send the emailer's ETag as If-Match, or * to write over whatever is there",
+        ));
+    } else if let Some(e) = err.find::<ValidationFailed>() {
+        code = StatusCode::UNPROCESSABLE_ENTITY;
+        message = String::from("VALIDATION_FAILED");
+        detail = Some(
+            e.errors
+                .iter()
+                .map(|e| e.message.clone())
+                .collect::<Vec<String>>()
+                .join("; "),
+        );
+        errors = Some(e.errors.clone());
     } else if let Some(e) = err.find::<error::OstrichError>() {
-        code = StatusCode::BAD_REQUEST;
+        code = match e.etype {
+            error::OstrichErrorType::OutageError => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::BAD_REQUEST,
+        };
         message = e.details.clone();
     } else if let Some(_) = err.find::<BadJwt>() {
-        code = StatusCode::BAD_REQUEST;
+        code = StatusCode::UNAUTHORIZED;
         message = String::from("BAD_JWT");
+    } else if let Some(_) = err.find::<ExpiredJwt>() {
+        code = StatusCode::UNAUTHORIZED;
+        message = String::from("EXPIRED_JWT");
+    } else if let Some(_) = err.find::<WrongAudience>() {
+        code = StatusCode::UNAUTHORIZED;
+        message = String::from("WRONG_AUDIENCE");
+    } else if let Some(_) = err.find::<BadApiKey>() {
+        code = StatusCode::UNAUTHORIZED;
+        message = String::from("BAD_API_KEY");
     } else if let Some(_) = err.find::<Forbidden>() {
         code = StatusCode::FORBIDDEN;
         message = String::from("FORBIDDEN");
@@ -111,9 +316,18 @@ pub async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible>
     let json = warp::reply::json(&ErrorMessage {
         code: code.as_u16(),
         message: message.into(),
+        detail,
+        limit,
+        errors,
     });
 
-    Ok(warp::reply::with_status(json, code))
+    let mut response = warp::reply::with_status(json, code).into_response();
+    if let Some(retry_after) = retry_after {
+        response
+            .headers_mut()
+            .insert(RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+    Ok(response)
 }
 
 pub fn handle_succcess_message(message: String) -> impl warp::Reply {
@@ -0,0 +1,107 @@
+use crate::{schema::api_keys, utils::now};
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+
+// a key a user made for scripting against the api, we only ever keep its hash
+#[derive(Queryable, Serialize, Clone)]
+pub struct ApiKey {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub key_prefix: String,
+    #[serde(skip_serializing)]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub active: bool,
+}
+
+#[derive(Insertable)]
+#[table_name = "api_keys"]
+pub struct NewApiKey {
+    user_id: i32,
+    name: String,
+    key_prefix: String,
+    key_hash: String,
+    scopes: Vec<String>,
+    last_used_at: Option<NaiveDateTime>,
+    expires_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+    deleted_at: Option<NaiveDateTime>,
+    active: bool,
+}
+
+impl NewApiKey {
+    pub fn new(
+        user_id: i32,
+        name: String,
+        key_prefix: String,
+        key_hash: String,
+        scopes: Vec<String>,
+        expires_at: Option<NaiveDateTime>,
+    ) -> Self {
+        NewApiKey {
+            user_id,
+            name,
+            key_prefix,
+            key_hash,
+            scopes,
+            last_used_at: None,
+            expires_at,
+            created_at: now(),
+            deleted_at: None,
+            active: true,
+        }
+    }
+
+    pub fn insert(&self, conn: &PgConnection) -> ApiKey {
+        create(conn, self)
+    }
+}
+
+pub fn create(conn: &PgConnection, new_api_key: &NewApiKey) -> ApiKey {
+    diesel::insert_into(api_keys::table)
+        .values(new_api_key)
+        .get_result(conn)
+        .expect("Error saving new api key")
+}
+
+pub fn read_by_user_id(conn: &PgConnection, user_id: i32) -> Vec<ApiKey> {
+    api_keys::table
+        .filter(api_keys::user_id.eq(user_id))
+        .filter(api_keys::active.eq(true))
+        .order(api_keys::id)
+        .load::<ApiKey>(conn)
+        .expect("Error loading api keys")
+}
+
+pub fn get_by_hash(conn: &PgConnection, key_hash: String) -> Vec<ApiKey> {
+    api_keys::table
+        .filter(api_keys::key_hash.eq(key_hash))
+        .filter(api_keys::active.eq(true))
+        .load::<ApiKey>(conn)
+        .expect("Error loading api key")
+}
+
+pub fn touch(conn: &PgConnection, id: i32) -> usize {
+    diesel::update(api_keys::table)
+        .filter(api_keys::id.eq(id))
+        .set(api_keys::last_used_at.eq(Some(now())))
+        .execute(conn)
+        .expect("Error updating api key")
+}
+
+pub fn delete_by_id_and_user_id(conn: &PgConnection, id: i32, user_id: i32) -> Vec<ApiKey> {
+    diesel::update(api_keys::table)
+        .set((api_keys::active.eq(false), api_keys::deleted_at.eq(now())))
+        .filter(api_keys::user_id.eq(user_id))
+        .filter(api_keys::id.eq(id))
+        .filter(api_keys::active.eq(true))
+        .load::<ApiKey>(conn)
+        .expect("Error revoking the api key")
+}
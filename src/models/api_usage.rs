@@ -0,0 +1,39 @@
+use crate::{schema::api_usage, utils::now};
+
+use chrono::NaiveDate;
+use diesel::{pg::upsert::excluded, prelude::*};
+
+// the user's calls so far in the month starting on month
+pub fn calls_in_month(conn: &PgConnection, user_id: i32, month: NaiveDate) -> QueryResult<i64> {
+    api_usage::table
+        .filter(api_usage::user_id.eq(user_id))
+        .filter(api_usage::month.eq(month))
+        .select(api_usage::calls)
+        .first::<i32>(conn)
+        .optional()
+        .map(|calls| calls.unwrap_or(0) as i64)
+}
+
+// adds to the month's calls, starting the row on the user's first call of the month
+pub fn record(
+    conn: &PgConnection,
+    user_id: i32,
+    month: NaiveDate,
+    calls: i32,
+) -> QueryResult<usize> {
+    let now = now();
+    diesel::insert_into(api_usage::table)
+        .values((
+            api_usage::user_id.eq(user_id),
+            api_usage::month.eq(month),
+            api_usage::calls.eq(calls),
+            api_usage::created_at.eq(now),
+        ))
+        .on_conflict((api_usage::user_id, api_usage::month))
+        .do_update()
+        .set((
+            api_usage::calls.eq(api_usage::calls + excluded(api_usage::calls)),
+            api_usage::updated_at.eq(Some(now)),
+        ))
+        .execute(conn)
+}
@@ -1,7 +1,11 @@
 use crate::{
     schema::emailers,
-    services::{cash_on_cash::CashOnCashCalculationParameters, zillow::ZillowSearchParameters},
-    utils::now,
+    services::{
+        cash_on_cash::CashOnCashCalculationParameters,
+        listing_source::{self, SearchParameters},
+        validation::EmailerFields,
+    },
+    utils::{double_option, now},
 };
 
 use chrono::naive::NaiveDateTime;
@@ -35,38 +39,83 @@ pub struct Emailer {
     pub user_id: i32,
     pub no_bathrooms: Option<i32>,
     pub notes: Option<String>,
+    pub next_run_at: NaiveDateTime,
+    pub last_run_at: Option<NaiveDateTime>,
+    pub version: i32,
+    pub listing_source: String,
 }
 
-impl Into<ZillowSearchParameters> for &Emailer {
-    fn into(self) -> ZillowSearchParameters {
-        ZillowSearchParameters {
-            search_param: self.search_param.clone(),
-            max_price: self.max_price.clone(),
-            min_price: self.min_price.clone(),
-            no_bedrooms: self.no_bedrooms.clone(),
-            no_bathrooms: self.no_bathrooms.clone(),
+// emailers made before there was a choice all searched zillow
+fn default_listing_source() -> String {
+    String::from(listing_source::ZILLOW)
+}
+
+impl From<&Emailer> for SearchParameters {
+    fn from(emailer: &Emailer) -> Self {
+        SearchParameters {
+            search_param: emailer.search_param.clone(),
+            max_price: emailer.max_price,
+            min_price: emailer.min_price,
+            no_bedrooms: emailer.no_bedrooms,
+            no_bathrooms: emailer.no_bathrooms,
+            days_on_market: None,
         }
     }
 }
 
-impl Into<CashOnCashCalculationParameters> for &Emailer {
-    fn into(self) -> CashOnCashCalculationParameters {
+impl From<&Emailer> for CashOnCashCalculationParameters {
+    fn from(emailer: &Emailer) -> Self {
         CashOnCashCalculationParameters {
-            insurance: self.insurance,
-            vacancy: self.vacancy,
-            property_management: self.property_management,
-            capex: self.capex,
-            repairs: self.repairs,
-            utilities: self.utilities,
-            down_payment: self.down_payment,
-            closing_cost: self.closing_cost,
-            loan_interest: self.loan_interest,
-            loan_months: self.loan_months,
-            additional_monthly_expenses: self.additional_monthly_expenses,
+            insurance: emailer.insurance,
+            vacancy: emailer.vacancy,
+            property_management: emailer.property_management,
+            capex: emailer.capex,
+            repairs: emailer.repairs,
+            utilities: emailer.utilities,
+            down_payment: emailer.down_payment,
+            closing_cost: emailer.closing_cost,
+            loan_interest: emailer.loan_interest,
+            loan_months: emailer.loan_months,
+            additional_monthly_expenses: emailer.additional_monthly_expenses,
         }
     }
 }
 
+// every shape of emailer is validated the same way, and they all name their fields alike
+macro_rules! emailer_fields_from {
+    ($($emailer:ty),*) => {
+        $(
+            impl<'a> From<&'a $emailer> for EmailerFields<'a> {
+                fn from(emailer: &'a $emailer) -> Self {
+                    EmailerFields {
+                        search_param: &emailer.search_param,
+                        frequency: &emailer.frequency,
+                        max_price: emailer.max_price,
+                        min_price: emailer.min_price,
+                        no_bedrooms: emailer.no_bedrooms,
+                        no_bathrooms: emailer.no_bathrooms,
+                        insurance: emailer.insurance,
+                        vacancy: emailer.vacancy,
+                        property_management: emailer.property_management,
+                        capex: emailer.capex,
+                        repairs: emailer.repairs,
+                        utilities: emailer.utilities,
+                        down_payment: emailer.down_payment,
+                        closing_cost: emailer.closing_cost,
+                        loan_interest: emailer.loan_interest,
+                        loan_months: emailer.loan_months,
+                        additional_monthly_expenses: emailer.additional_monthly_expenses,
+                        notes: emailer.notes.as_deref(),
+                        listing_source: &emailer.listing_source,
+                    }
+                }
+            }
+        )*
+    };
+}
+
+emailer_fields_from!(Emailer, PutEmailer, PostEmailer);
+
 #[derive(Insertable)]
 #[table_name = "emailers"]
 pub struct NewEmailer {
@@ -94,14 +143,20 @@ pub struct NewEmailer {
     user_id: i32,
     no_bathrooms: Option<i32>,
     notes: Option<String>,
+    next_run_at: NaiveDateTime,
+    last_run_at: Option<NaiveDateTime>,
+    listing_source: String,
 }
 
+// a PUT body, the id comes from the path and whatever is left out is cleared
 #[derive(AsChangeset, Deserialize)]
 #[table_name = "emailers"]
+#[changeset_options(treat_none_as_null = "true")]
 pub struct PutEmailer {
-    id: i32,
+    #[serde(default)]
+    pub id: i32,
     search_param: String,
-    frequency: String,
+    pub frequency: String,
     max_price: Option<f64>,
     min_price: Option<f64>,
     no_bedrooms: Option<i32>,
@@ -116,16 +171,22 @@ pub struct PutEmailer {
     loan_interest: f64,
     loan_months: f64,
     additional_monthly_expenses: f64,
+    #[serde(skip_deserializing)]
     updated_at: Option<NaiveDateTime>,
     no_bathrooms: Option<i32>,
     notes: Option<String>,
+    // worked out from the frequency, never taken from the body
+    #[serde(skip_deserializing, default = "now")]
+    pub next_run_at: NaiveDateTime,
+    #[serde(default = "default_listing_source")]
+    pub listing_source: String,
 }
 
 // this is a body that is accept when we are inserting an emailer over POST
 #[derive(Deserialize)]
 pub struct PostEmailer {
     search_param: String,
-    frequency: String,
+    pub frequency: String,
     max_price: Option<f64>,
     min_price: Option<f64>,
     no_bedrooms: Option<i32>,
@@ -142,10 +203,121 @@ pub struct PostEmailer {
     loan_months: f64,
     additional_monthly_expenses: f64,
     notes: Option<String>,
+    #[serde(default = "default_listing_source")]
+    pub listing_source: String,
+}
+
+// a PATCH body, only what is in it changes and null clears the fields that can be empty
+#[derive(Deserialize)]
+pub struct PatchEmailer {
+    search_param: Option<String>,
+    pub frequency: Option<String>,
+    #[serde(default, deserialize_with = "double_option")]
+    max_price: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    min_price: Option<Option<f64>>,
+    #[serde(default, deserialize_with = "double_option")]
+    no_bedrooms: Option<Option<i32>>,
+    #[serde(default, deserialize_with = "double_option")]
+    no_bathrooms: Option<Option<i32>>,
+    insurance: Option<f64>,
+    vacancy: Option<f64>,
+    property_management: Option<f64>,
+    capex: Option<f64>,
+    repairs: Option<f64>,
+    utilities: Option<f64>,
+    down_payment: Option<f64>,
+    closing_cost: Option<f64>,
+    loan_interest: Option<f64>,
+    loan_months: Option<f64>,
+    additional_monthly_expenses: Option<f64>,
+    #[serde(default, deserialize_with = "double_option")]
+    notes: Option<Option<String>>,
+    listing_source: Option<String>,
+}
+
+impl PatchEmailer {
+    // the whole emailer as it will be once this is applied
+    pub fn apply(self, emailer: &Emailer) -> PutEmailer {
+        PutEmailer {
+            id: emailer.id,
+            search_param: self
+                .search_param
+                .unwrap_or_else(|| emailer.search_param.clone()),
+            frequency: self.frequency.unwrap_or_else(|| emailer.frequency.clone()),
+            max_price: self.max_price.unwrap_or(emailer.max_price),
+            min_price: self.min_price.unwrap_or(emailer.min_price),
+            no_bedrooms: self.no_bedrooms.unwrap_or(emailer.no_bedrooms),
+            insurance: self.insurance.unwrap_or(emailer.insurance),
+            vacancy: self.vacancy.unwrap_or(emailer.vacancy),
+            property_management: self
+                .property_management
+                .unwrap_or(emailer.property_management),
+            capex: self.capex.unwrap_or(emailer.capex),
+            repairs: self.repairs.unwrap_or(emailer.repairs),
+            utilities: self.utilities.unwrap_or(emailer.utilities),
+            down_payment: self.down_payment.unwrap_or(emailer.down_payment),
+            closing_cost: self.closing_cost.unwrap_or(emailer.closing_cost),
+            loan_interest: self.loan_interest.unwrap_or(emailer.loan_interest),
+            loan_months: self.loan_months.unwrap_or(emailer.loan_months),
+            additional_monthly_expenses: self
+                .additional_monthly_expenses
+                .unwrap_or(emailer.additional_monthly_expenses),
+            updated_at: None,
+            no_bathrooms: self.no_bathrooms.unwrap_or(emailer.no_bathrooms),
+            notes: self.notes.unwrap_or_else(|| emailer.notes.clone()),
+            next_run_at: emailer.next_run_at,
+            listing_source: self
+                .listing_source
+                .unwrap_or_else(|| emailer.listing_source.clone()),
+        }
+    }
+}
+
+impl PostEmailer {
+    // the emailer this body would make, never saved so it has no id, for previews
+    pub fn unsaved(self, user_id: i32, email: String) -> Emailer {
+        Emailer {
+            id: 0,
+            search_param: self.search_param,
+            email,
+            frequency: self.frequency,
+            max_price: self.max_price,
+            min_price: self.min_price,
+            no_bedrooms: self.no_bedrooms,
+            insurance: self.insurance,
+            vacancy: self.vacancy,
+            property_management: self.property_management,
+            capex: self.capex,
+            repairs: self.repairs,
+            utilities: self.utilities,
+            down_payment: self.down_payment,
+            closing_cost: self.closing_cost,
+            loan_interest: self.loan_interest,
+            loan_months: self.loan_months,
+            additional_monthly_expenses: self.additional_monthly_expenses,
+            created_at: now(),
+            updated_at: None,
+            deleted_at: None,
+            active: true,
+            user_id,
+            no_bathrooms: self.no_bathrooms,
+            notes: self.notes,
+            next_run_at: now(),
+            last_run_at: None,
+            version: 0,
+            listing_source: self.listing_source,
+        }
+    }
 }
 
 impl NewEmailer {
-    pub fn new(post_emailer: PostEmailer, user_id: i32, email: String) -> Self {
+    pub fn new(
+        post_emailer: PostEmailer,
+        user_id: i32,
+        email: String,
+        next_run_at: NaiveDateTime,
+    ) -> Self {
         NewEmailer {
             search_param: post_emailer.search_param,
             user_id,
@@ -171,12 +343,21 @@ impl NewEmailer {
             updated_at: None,
             deleted_at: None,
             active: true,
+            next_run_at,
+            last_run_at: None,
+            listing_source: post_emailer.listing_source,
         }
     }
 
     pub fn insert(&self, conn: &PgConnection) -> Emailer {
         create(conn, self)
     }
+
+    pub fn try_insert(&self, conn: &PgConnection) -> QueryResult<Emailer> {
+        diesel::insert_into(emailers::table)
+            .values(self)
+            .get_result(conn)
+    }
 }
 
 pub fn create(conn: &PgConnection, new_emailer: &NewEmailer) -> Emailer {
@@ -193,25 +374,110 @@ pub fn read(conn: &PgConnection) -> Vec<Emailer> {
         .expect("Error loading emailer")
 }
 
-pub fn update_emailer(conn: &PgConnection, mut updated_emailer: PutEmailer, user_id: i32) -> usize {
+// writes the emailer only while it is still at version, moving it on to the next one; None
+// when it isn't the user's anymore or another write got there first
+pub fn update_emailer(
+    conn: &PgConnection,
+    mut updated_emailer: PutEmailer,
+    user_id: i32,
+    version: i32,
+) -> QueryResult<Option<Emailer>> {
     updated_emailer.updated_at = Some(now());
     diesel::update(emailers::table)
         .filter(emailers::id.eq(updated_emailer.id))
         .filter(emailers::user_id.eq(user_id))
         .filter(emailers::active.eq(true))
-        .set(&updated_emailer)
-        .execute(conn)
-        .expect("Error updating emailer")
+        .filter(emailers::version.eq(version))
+        .set((
+            &updated_emailer,
+            emailers::version.eq(emailers::version + 1),
+        ))
+        .get_result(conn)
+        .optional()
+}
+
+pub fn get_by_id(conn: &PgConnection, id: i32) -> Vec<Emailer> {
+    emailers::table
+        .filter(emailers::id.eq(id))
+        .filter(emailers::active.eq(true))
+        .load::<Emailer>(conn)
+        .expect("Error loading emailer")
+}
+
+pub fn get_by_id_and_user_id(conn: &PgConnection, id: i32, user_id: i32) -> Vec<Emailer> {
+    emailers::table
+        .filter(emailers::id.eq(id))
+        .filter(emailers::user_id.eq(user_id))
+        .filter(emailers::active.eq(true))
+        .load::<Emailer>(conn)
+        .expect("Error loading emailer")
+}
+
+// the emailers whose run has come, off the emailers_due index
+pub fn read_due(conn: &PgConnection, now: NaiveDateTime) -> QueryResult<Vec<Emailer>> {
+    emailers::table
+        .filter(emailers::active.eq(true))
+        .filter(emailers::next_run_at.le(now))
+        .order(emailers::id)
+        .load::<Emailer>(conn)
+}
+
+pub fn read_by_user_ids(conn: &PgConnection, user_ids: &[i32]) -> QueryResult<Vec<Emailer>> {
+    emailers::table
+        .filter(emailers::user_id.eq_any(user_ids))
+        .filter(emailers::active.eq(true))
+        .order(emailers::id)
+        .load::<Emailer>(conn)
 }
 
 pub fn read_by_user_id(conn: &PgConnection, user_id: i32) -> Vec<Emailer> {
     emailers::table
         .filter(emailers::user_id.eq(user_id))
         .filter(emailers::active.eq(true))
+        .order(emailers::id)
         .load::<Emailer>(conn)
         .expect("Error loading emailer")
 }
 
+// moves a due emailer on to its next run, so only one scheduler enqueues this one;
+// zero rows means it wasn't due anymore
+pub fn claim(
+    conn: &PgConnection,
+    id: i32,
+    now: NaiveDateTime,
+    next_run_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::update(emailers::table)
+        .filter(emailers::id.eq(id))
+        .filter(emailers::active.eq(true))
+        .filter(emailers::next_run_at.le(now))
+        .set(emailers::next_run_at.eq(next_run_at))
+        .execute(conn)
+}
+
+pub fn mark_run(conn: &PgConnection, id: i32, last_run_at: NaiveDateTime) -> QueryResult<usize> {
+    diesel::update(emailers::table)
+        .filter(emailers::id.eq(id))
+        .set(emailers::last_run_at.eq(Some(last_run_at)))
+        .execute(conn)
+}
+
+pub fn reschedule(conn: &PgConnection, id: i32, next_run_at: NaiveDateTime) -> usize {
+    diesel::update(emailers::table)
+        .filter(emailers::id.eq(id))
+        .set(emailers::next_run_at.eq(next_run_at))
+        .execute(conn)
+        .expect("Error updating emailer schedule")
+}
+
+pub fn count_by_user_id(conn: &PgConnection, user_id: i32) -> QueryResult<i64> {
+    emailers::table
+        .filter(emailers::user_id.eq(user_id))
+        .filter(emailers::active.eq(true))
+        .count()
+        .get_result(conn)
+}
+
 pub fn delete_by_id_and_user_id(conn: &PgConnection, id: i32, user_id: i32) -> Vec<Emailer> {
     diesel::update(emailers::table)
         .set((emailers::active.eq(false), emailers::deleted_at.eq(now())))
@@ -220,3 +486,11 @@ pub fn delete_by_id_and_user_id(conn: &PgConnection, id: i32, user_id: i32) -> V
         .load::<Emailer>(conn)
         .expect("Error soft deleting the email record")
 }
+
+pub fn delete_by_user_ids(conn: &PgConnection, user_ids: &[i32]) -> QueryResult<usize> {
+    diesel::update(emailers::table)
+        .filter(emailers::user_id.eq_any(user_ids))
+        .filter(emailers::active.eq(true))
+        .set((emailers::active.eq(false), emailers::deleted_at.eq(now())))
+        .execute(conn)
+}
@@ -0,0 +1,138 @@
+use crate::{schema::emailer_runs, services::listing_source::ListingEmail, utils::now};
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+
+// searching zillow, then waiting on the email job, then one of sent, failed, skipped, invalid,
+// outage or over_quota
+pub const SEARCHING: &str = "searching";
+pub const QUEUED: &str = "queued";
+pub const SENT: &str = "sent";
+pub const FAILED: &str = "failed";
+// the window went out from another run while this one was searching
+pub const SKIPPED: &str = "skipped";
+// the emailer breaks a validation rule, so it was never searched
+pub const INVALID: &str = "invalid";
+// the listing source was down, the job tries again later
+pub const OUTAGE: &str = "outage";
+// the owner used up their plan's api calls for the month, so it was never searched
+pub const OVER_QUOTA: &str = "over_quota";
+
+// one attempt at an emailer, kept so we can tell a user what happened to their email
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct EmailerRun {
+    pub id: i64,
+    pub emailer_id: i32,
+    pub run_window: NaiveDateTime,
+    pub started_at: NaiveDateTime,
+    pub finished_at: Option<NaiveDateTime>,
+    pub zillow_url: String,
+    pub total_results: Option<i64>,
+    pub properties_fetched: i32,
+    pub properties_failed: i32,
+    pub listings_written: i32,
+    pub email_status: String,
+    pub error: Option<String>,
+}
+
+#[derive(Insertable)]
+#[table_name = "emailer_runs"]
+pub struct NewEmailerRun {
+    emailer_id: i32,
+    run_window: NaiveDateTime,
+    started_at: NaiveDateTime,
+    zillow_url: String,
+    email_status: String,
+}
+
+impl NewEmailerRun {
+    pub fn new(emailer_id: i32, run_window: NaiveDateTime, zillow_url: String) -> Self {
+        NewEmailerRun {
+            emailer_id,
+            run_window,
+            started_at: now(),
+            zillow_url,
+            email_status: String::from(SEARCHING),
+        }
+    }
+
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<EmailerRun> {
+        diesel::insert_into(emailer_runs::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+// the search is done and its email is queued, error is set when zillow had nothing
+pub fn record_search(
+    conn: &PgConnection,
+    id: i64,
+    listing_email: &ListingEmail,
+    error: Option<&str>,
+) -> QueryResult<usize> {
+    diesel::update(emailer_runs::table.filter(emailer_runs::id.eq(id)))
+        .set((
+            emailer_runs::total_results.eq(listing_email.total_results),
+            emailer_runs::properties_fetched.eq(listing_email.properties_fetched),
+            emailer_runs::properties_failed.eq(listing_email.properties_failed),
+            emailer_runs::email_status.eq(QUEUED),
+            emailer_runs::error.eq(error),
+        ))
+        .execute(conn)
+}
+
+// the listings saved as sent once the email went out
+pub fn record_listings_written(
+    conn: &PgConnection,
+    id: i64,
+    listings_written: i32,
+) -> QueryResult<usize> {
+    diesel::update(emailer_runs::table.filter(emailer_runs::id.eq(id)))
+        .set(emailer_runs::listings_written.eq(listings_written))
+        .execute(conn)
+}
+
+// keeps the latest failure of a job that is going to be tried again
+pub fn record_error(conn: &PgConnection, id: i64, error: &str) -> QueryResult<usize> {
+    diesel::update(emailer_runs::table.filter(emailer_runs::id.eq(id)))
+        .set(emailer_runs::error.eq(Some(error)))
+        .execute(conn)
+}
+
+pub fn finish(
+    conn: &PgConnection,
+    id: i64,
+    email_status: &str,
+    error: Option<&str>,
+) -> QueryResult<usize> {
+    diesel::update(emailer_runs::table.filter(emailer_runs::id.eq(id)))
+        .set((
+            emailer_runs::finished_at.eq(Some(now())),
+            emailer_runs::email_status.eq(email_status),
+            emailer_runs::error.eq(error),
+        ))
+        .execute(conn)
+}
+
+// newest first
+pub fn read_by_emailer_id(
+    conn: &PgConnection,
+    emailer_id: i32,
+    limit: i64,
+    offset: i64,
+) -> QueryResult<Vec<EmailerRun>> {
+    emailer_runs::table
+        .filter(emailer_runs::emailer_id.eq(emailer_id))
+        .order((emailer_runs::started_at.desc(), emailer_runs::id.desc()))
+        .limit(limit)
+        .offset(offset)
+        .load::<EmailerRun>(conn)
+}
+
+pub fn count_by_emailer_id(conn: &PgConnection, emailer_id: i32) -> QueryResult<i64> {
+    emailer_runs::table
+        .filter(emailer_runs::emailer_id.eq(emailer_id))
+        .count()
+        .get_result(conn)
+}
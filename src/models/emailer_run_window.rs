@@ -0,0 +1,44 @@
+use crate::{schema::emailer_run_windows, utils::now};
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+
+pub fn exists(
+    conn: &PgConnection,
+    emailer_id: i32,
+    run_window: NaiveDateTime,
+) -> QueryResult<bool> {
+    diesel::select(diesel::dsl::exists(
+        emailer_run_windows::table
+            .filter(emailer_run_windows::emailer_id.eq(emailer_id))
+            .filter(emailer_run_windows::run_window.eq(run_window)),
+    ))
+    .get_result(conn)
+}
+
+// zero rows means the window was already sent, a forced run writes over it instead
+pub fn record(
+    conn: &PgConnection,
+    emailer_id: i32,
+    run_window: NaiveDateTime,
+    force: bool,
+) -> QueryResult<usize> {
+    let now = now();
+    let insert = diesel::insert_into(emailer_run_windows::table).values((
+        emailer_run_windows::emailer_id.eq(emailer_id),
+        emailer_run_windows::run_window.eq(run_window),
+        emailer_run_windows::created_at.eq(now),
+    ));
+    if force {
+        insert
+            .on_conflict((
+                emailer_run_windows::emailer_id,
+                emailer_run_windows::run_window,
+            ))
+            .do_update()
+            .set(emailer_run_windows::updated_at.eq(Some(now)))
+            .execute(conn)
+    } else {
+        insert.on_conflict_do_nothing().execute(conn)
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{schema::jobs, utils::now};
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+
+pub const QUEUED: &str = "queued";
+pub const RUNNING: &str = "running";
+pub const DONE: &str = "done";
+pub const DEAD: &str = "dead";
+
+// a unit of background work, drained by however many workers are running
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct Job {
+    pub id: i64,
+    pub kind: String,
+    pub payload: serde_json::Value,
+    pub state: String,
+    pub attempts: i32,
+    pub max_attempts: i32,
+    pub run_at: NaiveDateTime,
+    pub locked_until: Option<NaiveDateTime>,
+    pub locked_by: Option<String>,
+    pub last_error: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>,
+    pub finished_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "jobs"]
+pub struct NewJob {
+    kind: String,
+    payload: serde_json::Value,
+    state: String,
+    attempts: i32,
+    max_attempts: i32,
+    run_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+}
+
+impl NewJob {
+    pub fn new(kind: &str, payload: serde_json::Value, max_attempts: i32) -> Self {
+        let now = now();
+        NewJob {
+            kind: String::from(kind),
+            payload,
+            state: String::from(QUEUED),
+            attempts: 0,
+            max_attempts,
+            run_at: now,
+            created_at: now,
+        }
+    }
+
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<Job> {
+        diesel::insert_into(jobs::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+// hands out up to limit ready jobs to this worker, skipping any another worker is in the
+// middle of claiming; running jobs past their visibility timeout count as ready again
+pub fn claim(
+    conn: &PgConnection,
+    worker: &str,
+    now: NaiveDateTime,
+    visible_until: NaiveDateTime,
+    limit: i64,
+) -> QueryResult<Vec<Job>> {
+    conn.transaction(|| {
+        let ids = jobs::table
+            .select(jobs::id)
+            .filter(jobs::attempts.lt(jobs::max_attempts))
+            .filter(
+                jobs::state
+                    .eq(QUEUED)
+                    .and(jobs::run_at.le(now))
+                    .or(jobs::state.eq(RUNNING).and(jobs::locked_until.lt(now))),
+            )
+            .order(jobs::run_at)
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load::<i64>(conn)?;
+
+        diesel::update(jobs::table.filter(jobs::id.eq_any(ids)))
+            .set((
+                jobs::state.eq(RUNNING),
+                jobs::attempts.eq(jobs::attempts + 1),
+                jobs::locked_until.eq(Some(visible_until)),
+                jobs::locked_by.eq(Some(worker)),
+                jobs::updated_at.eq(Some(now)),
+            ))
+            .get_results(conn)
+    })
+}
+
+// the rest only touch the job while this worker still holds it, a worker that ran past
+// the visibility timeout has lost it to someone else
+pub fn complete(conn: &PgConnection, id: i64, worker: &str) -> QueryResult<usize> {
+    let now = now();
+    diesel::update(jobs::table)
+        .filter(jobs::id.eq(id))
+        .filter(jobs::state.eq(RUNNING))
+        .filter(jobs::locked_by.eq(worker))
+        .set((
+            jobs::state.eq(DONE),
+            jobs::locked_until.eq(None::<NaiveDateTime>),
+            jobs::updated_at.eq(Some(now)),
+            jobs::finished_at.eq(Some(now)),
+        ))
+        .execute(conn)
+}
+
+pub fn retry(
+    conn: &PgConnection,
+    id: i64,
+    worker: &str,
+    run_at: NaiveDateTime,
+    error: &str,
+) -> QueryResult<usize> {
+    diesel::update(jobs::table)
+        .filter(jobs::id.eq(id))
+        .filter(jobs::state.eq(RUNNING))
+        .filter(jobs::locked_by.eq(worker))
+        .set((
+            jobs::state.eq(QUEUED),
+            jobs::run_at.eq(run_at),
+            jobs::locked_until.eq(None::<NaiveDateTime>),
+            jobs::last_error.eq(Some(error)),
+            jobs::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+}
+
+pub fn bury(conn: &PgConnection, id: i64, worker: &str, error: &str) -> QueryResult<usize> {
+    let now = now();
+    diesel::update(jobs::table)
+        .filter(jobs::id.eq(id))
+        .filter(jobs::state.eq(RUNNING))
+        .filter(jobs::locked_by.eq(worker))
+        .set((
+            jobs::state.eq(DEAD),
+            jobs::locked_until.eq(None::<NaiveDateTime>),
+            jobs::last_error.eq(Some(error)),
+            jobs::updated_at.eq(Some(now)),
+            jobs::finished_at.eq(Some(now)),
+        ))
+        .execute(conn)
+}
+
+// jobs whose worker went quiet on their last attempt can't be claimed again, so they are dead
+pub fn bury_abandoned(conn: &PgConnection, now: NaiveDateTime) -> QueryResult<usize> {
+    diesel::update(jobs::table)
+        .filter(jobs::state.eq(RUNNING))
+        .filter(jobs::locked_until.lt(now))
+        .filter(jobs::attempts.ge(jobs::max_attempts))
+        .set((
+            jobs::state.eq(DEAD),
+            jobs::last_error.eq(Some("Visibility timeout on the last attempt")),
+            jobs::updated_at.eq(Some(now)),
+            jobs::finished_at.eq(Some(now)),
+        ))
+        .execute(conn)
+}
@@ -1,12 +1,13 @@
 use crate::{
     models::emailer::Emailer,
     schema::listing_data,
-    services::{cash_on_cash::calculate_coc, zillow::ZillowPropertySearchRoot},
+    services::{cash_on_cash::calculate_coc, listing_source::PropertyDetails},
     utils::{format_optional_float, format_optional_string, now},
 };
 use chrono::naive::NaiveDateTime;
 use diesel::prelude::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 
 #[derive(Queryable, Serialize)]
 pub struct ListingData {
@@ -30,12 +31,18 @@ pub struct ListingData {
     pub updated_at: Option<NaiveDateTime>,
     pub deleted_at: Option<NaiveDateTime>,
     pub active: bool,
+    // the listing's id at its source, a zpid for zillow
+    pub zpid: Option<String>,
 }
 
-#[derive(Insertable)]
+// serialized for previews, where only the property itself means anything, and carried by the
+// email job until it is sent
+#[derive(Insertable, Serialize, Deserialize)]
 #[table_name = "listing_data"]
 pub struct NewListingData {
+    #[serde(skip)]
     pub user_id: i32,
+    #[serde(skip)]
     pub emailer_id: i32,
     pub street_address: Option<String>,
     pub city: Option<String>,
@@ -50,69 +57,74 @@ pub struct NewListingData {
     pub img_src: Option<String>,
     pub url: Option<String>,
     pub cash_on_cash: Option<f64>,
+    #[serde(skip, default = "now")]
     pub created_at: NaiveDateTime,
+    #[serde(skip)]
     pub updated_at: Option<NaiveDateTime>,
+    #[serde(skip)]
     pub deleted_at: Option<NaiveDateTime>,
+    #[serde(skip, default = "active")]
     pub active: bool,
+    #[serde(default)]
+    pub zpid: Option<String>,
+}
+
+fn active() -> bool {
+    true
 }
 
 impl NewListingData {
-    pub fn new(property: ZillowPropertySearchRoot, emailer: &Emailer) -> Self {
-        let taxes = if property.propertyTaxRate.is_some()
-            && property.price.is_some()
-            && property.propertyTaxRate.unwrap() != 0.0
-        {
-            Some((property.propertyTaxRate.unwrap() * property.price.unwrap()) / 1200.0)
-        } else {
-            None
+    pub fn new(property: PropertyDetails, emailer: &Emailer) -> Self {
+        let taxes = match (property.tax_rate, property.price) {
+            (Some(tax_rate), Some(price)) if tax_rate != 0.0 => Some((tax_rate * price) / 1200.0),
+            _ => None,
+        };
+
+        let cash_on_cash = match (property.price, property.rent_estimate, taxes) {
+            (Some(price), Some(rent_estimate), Some(taxes)) => {
+                Some(calculate_coc(&emailer.into(), price, taxes, rent_estimate))
+            }
+            _ => None,
         };
 
-        let mut new_email_data = Self {
+        Self {
+            zpid: Some(property.id),
             emailer_id: emailer.id,
             user_id: emailer.user_id,
-            street_address: None,
-            city: None,
-            state: None,
-            zipcode: None,
-            bedrooms: property.bedrooms.map(|bedrooms| bedrooms as i32),
-            bathrooms: property.bathrooms.map(|bathrooms| bathrooms as i32),
+            street_address: property.street_address,
+            city: property.city,
+            state: property.state,
+            zipcode: property.zipcode,
+            bedrooms: property.bedrooms,
+            bathrooms: property.bathrooms,
             price: property.price,
-            taxes: taxes,
-            rent_estimate: property.rentZestimate,
-            time_on_zillow: property.timeOnZillow,
-            img_src: property.imgSrc,
+            taxes,
+            rent_estimate: property.rent_estimate,
+            time_on_zillow: property.time_on_market,
+            img_src: property.img_src,
             url: property.url,
-            cash_on_cash: None,
+            cash_on_cash,
             created_at: now(),
             updated_at: None,
             deleted_at: None,
             active: true,
-        };
-
-        if let Some(address) = property.address {
-            new_email_data.street_address = address.streetAddress;
-            new_email_data.city = address.city;
-            new_email_data.state = address.state;
-            new_email_data.zipcode = address.zipcode;
-        };
-
-        if property.price.is_some() && property.rentZestimate.is_some() && taxes.is_some() {
-            let coc = calculate_coc(
-                &emailer.into(),
-                property.price.unwrap(),
-                taxes.unwrap(),
-                property.rentZestimate.unwrap(),
-            );
-            new_email_data.cash_on_cash = Some(coc);
         }
-
-        new_email_data
     }
 
     pub fn insert(&self, conn: &PgConnection) -> ListingData {
         create(conn, self)
     }
 
+    // None when the emailer already sent this listing
+    pub fn try_insert(&self, conn: &PgConnection) -> QueryResult<Option<ListingData>> {
+        diesel::insert_into(listing_data::table)
+            .values(self)
+            .on_conflict((listing_data::emailer_id, listing_data::zpid))
+            .do_nothing()
+            .get_result(conn)
+            .optional()
+    }
+
     pub fn to_email(&self) -> String {
         let address = format!(
             "{} {}, {} {}",
@@ -153,7 +165,7 @@ impl NewListingData {
             <h4>Days on Market: {}</h4>
             <h4>Cash On Cash: {}</h4>
             <img src=\"{}\">
-            <a href=\"https://www.zillow.com{}\">Check it out!</a>
+            <a href=\"{}\">Check it out!</a>
             ",
             address, specs, price, taxes, rent_estimate, time_on_zillow, cash_on_cash, img_src, url
         )
@@ -172,3 +184,27 @@ pub fn read(conn: &PgConnection) -> Vec<ListingData> {
         .load::<ListingData>(conn)
         .expect("Error loading listing_data")
 }
+
+// the listings an emailer has already sent, by their id at the source
+pub fn read_zpids_by_emailer_id(
+    conn: &PgConnection,
+    emailer_id: i32,
+) -> QueryResult<HashSet<String>> {
+    listing_data::table
+        .filter(listing_data::emailer_id.eq(emailer_id))
+        .filter(listing_data::zpid.is_not_null())
+        .select(listing_data::zpid)
+        .load::<Option<String>>(conn)
+        .map(|zpids| zpids.into_iter().flatten().collect())
+}
+
+pub fn delete_by_user_ids(conn: &PgConnection, user_ids: &[i32]) -> QueryResult<usize> {
+    diesel::update(listing_data::table)
+        .filter(listing_data::user_id.eq_any(user_ids))
+        .filter(listing_data::active.eq(true))
+        .set((
+            listing_data::active.eq(false),
+            listing_data::deleted_at.eq(now()),
+        ))
+        .execute(conn)
+}
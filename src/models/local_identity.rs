@@ -0,0 +1,264 @@
+use crate::{
+    schema::{local_identities, local_revoked_tokens},
+    utils::now,
+};
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+
+// an account we sign in ourselves instead of through cognito
+#[derive(Queryable, Clone)]
+pub struct LocalIdentity {
+    pub id: i32,
+    pub username: String,
+    pub email: String,
+    pub sub: String,
+    pub password_hash: String,
+    pub confirmed: bool,
+    pub confirmation_code_hash: Option<String>,
+    pub confirmation_code_expires_at: Option<NaiveDateTime>,
+    pub reset_code_hash: Option<String>,
+    pub reset_code_expires_at: Option<NaiveDateTime>,
+    pub confirmation_code_attempts: i32,
+    pub reset_code_attempts: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>,
+    pub deleted_at: Option<NaiveDateTime>,
+    pub active: bool,
+    pub signed_out_at: Option<NaiveDateTime>,
+}
+
+#[derive(Insertable)]
+#[table_name = "local_identities"]
+pub struct NewLocalIdentity {
+    username: String,
+    email: String,
+    sub: String,
+    password_hash: String,
+    confirmed: bool,
+    confirmation_code_hash: Option<String>,
+    confirmation_code_expires_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+    updated_at: Option<NaiveDateTime>,
+    deleted_at: Option<NaiveDateTime>,
+    active: bool,
+}
+
+impl NewLocalIdentity {
+    pub fn new(
+        username: String,
+        email: String,
+        sub: String,
+        password_hash: String,
+        confirmation_code_hash: String,
+        confirmation_code_expires_at: NaiveDateTime,
+    ) -> Self {
+        NewLocalIdentity {
+            username,
+            email,
+            sub,
+            password_hash,
+            confirmed: false,
+            confirmation_code_hash: Some(confirmation_code_hash),
+            confirmation_code_expires_at: Some(confirmation_code_expires_at),
+            created_at: now(),
+            updated_at: None,
+            deleted_at: None,
+            active: true,
+        }
+    }
+
+    // None when the username was taken, even by a sign up racing this one
+    pub fn try_insert(&self, conn: &PgConnection) -> QueryResult<Option<LocalIdentity>> {
+        diesel::insert_into(local_identities::table)
+            .values(self)
+            .on_conflict(local_identities::username)
+            .do_nothing()
+            .get_result(conn)
+            .optional()
+    }
+}
+
+pub fn get_by_username(conn: &PgConnection, username: String) -> Vec<LocalIdentity> {
+    local_identities::table
+        .filter(local_identities::username.eq(username))
+        .filter(local_identities::active.eq(true))
+        .load::<LocalIdentity>(conn)
+        .expect("Error loading local identity")
+}
+
+pub fn confirm(conn: &PgConnection, id: i32) -> usize {
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::confirmed.eq(true),
+            local_identities::confirmation_code_hash.eq(None::<String>),
+            local_identities::confirmation_code_expires_at.eq(None::<NaiveDateTime>),
+            local_identities::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error confirming local identity")
+}
+
+pub fn set_confirmation_code(
+    conn: &PgConnection,
+    id: i32,
+    code_hash: String,
+    expires_at: NaiveDateTime,
+) -> usize {
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::confirmation_code_hash.eq(Some(code_hash)),
+            local_identities::confirmation_code_expires_at.eq(Some(expires_at)),
+            local_identities::confirmation_code_attempts.eq(0),
+            local_identities::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error updating local identity")
+}
+
+pub fn set_reset_code(
+    conn: &PgConnection,
+    id: i32,
+    code_hash: String,
+    expires_at: NaiveDateTime,
+) -> usize {
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::reset_code_hash.eq(Some(code_hash)),
+            local_identities::reset_code_expires_at.eq(Some(expires_at)),
+            local_identities::reset_code_attempts.eq(0),
+            local_identities::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error updating local identity")
+}
+
+// counts a wrong confirmation code, and burns the code on the last attempt allowed
+pub fn fail_confirmation_code(conn: &PgConnection, id: i32, max_attempts: i32) -> usize {
+    let attempts = diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set(
+            local_identities::confirmation_code_attempts
+                .eq(local_identities::confirmation_code_attempts + 1),
+        )
+        .returning(local_identities::confirmation_code_attempts)
+        .get_result::<i32>(conn)
+        .expect("Error updating local identity");
+    if attempts < max_attempts {
+        return 0;
+    }
+
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::confirmation_code_hash.eq(None::<String>),
+            local_identities::confirmation_code_expires_at.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)
+        .expect("Error updating local identity")
+}
+
+// counts a wrong reset code, and burns the code on the last attempt allowed
+pub fn fail_reset_code(conn: &PgConnection, id: i32, max_attempts: i32) -> usize {
+    let attempts = diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set(local_identities::reset_code_attempts.eq(local_identities::reset_code_attempts + 1))
+        .returning(local_identities::reset_code_attempts)
+        .get_result::<i32>(conn)
+        .expect("Error updating local identity");
+    if attempts < max_attempts {
+        return 0;
+    }
+
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::reset_code_hash.eq(None::<String>),
+            local_identities::reset_code_expires_at.eq(None::<NaiveDateTime>),
+        ))
+        .execute(conn)
+        .expect("Error updating local identity")
+}
+
+// swap the password and burn the reset code that allowed it
+pub fn reset_password(conn: &PgConnection, id: i32, password_hash: String) -> usize {
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::password_hash.eq(password_hash),
+            local_identities::reset_code_hash.eq(None::<String>),
+            local_identities::reset_code_expires_at.eq(None::<NaiveDateTime>),
+            local_identities::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error updating local identity")
+}
+
+pub fn update_password(conn: &PgConnection, id: i32, password_hash: String) -> usize {
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::password_hash.eq(password_hash),
+            local_identities::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error updating local identity")
+}
+
+// anything issued before now stops working
+pub fn sign_out(conn: &PgConnection, id: i32) -> usize {
+    diesel::update(local_identities::table)
+        .filter(local_identities::id.eq(id))
+        .set((
+            local_identities::signed_out_at.eq(Some(now())),
+            local_identities::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error signing out local identity")
+}
+
+// gone for good, so the username can be signed up again
+pub fn delete(conn: &PgConnection, id: i32) -> usize {
+    diesel::delete(local_identities::table.filter(local_identities::id.eq(id)))
+        .execute(conn)
+        .expect("Error deleting local identity")
+}
+
+#[derive(Insertable)]
+#[table_name = "local_revoked_tokens"]
+pub struct NewLocalRevokedToken {
+    jti: String,
+    expires_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+}
+
+impl NewLocalRevokedToken {
+    pub fn new(jti: String, expires_at: NaiveDateTime) -> Self {
+        NewLocalRevokedToken {
+            jti,
+            expires_at,
+            created_at: now(),
+        }
+    }
+
+    // revoking the same token twice is fine
+    pub fn insert(&self, conn: &PgConnection) -> usize {
+        diesel::insert_into(local_revoked_tokens::table)
+            .values(self)
+            .on_conflict(local_revoked_tokens::jti)
+            .do_nothing()
+            .execute(conn)
+            .expect("Error revoking token")
+    }
+}
+
+pub fn is_revoked(conn: &PgConnection, jti: String) -> bool {
+    diesel::select(diesel::dsl::exists(
+        local_revoked_tokens::table.filter(local_revoked_tokens::jti.eq(jti)),
+    ))
+    .get_result(conn)
+    .expect("Error loading revoked tokens")
+}
@@ -1,3 +1,12 @@
+pub mod api_key;
+pub mod api_usage;
 pub mod emailer;
+pub mod emailer_run;
+pub mod emailer_run_window;
+pub mod job;
 pub mod listing_data;
+pub mod local_identity;
+pub mod plan;
+pub mod rate_limit;
+pub mod token_bucket;
 pub mod user;
@@ -0,0 +1,50 @@
+use crate::schema::plans;
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+use serde::Serialize;
+
+// everyone lands on this plan until stripe says otherwise
+pub const FREE_PLAN: &str = "free";
+
+// what a subscription gets the user
+#[derive(Queryable, Serialize, Clone, Debug)]
+pub struct Plan {
+    pub id: i32,
+    pub name: String,
+    pub stripe_product_name: Option<String>,
+    pub max_emailers: i32,
+    pub allowed_frequencies: Vec<String>,
+    pub max_listings_per_email: i32,
+    pub monthly_api_quota: i32,
+    pub created_at: NaiveDateTime,
+    pub updated_at: Option<NaiveDateTime>,
+}
+
+pub fn read(conn: &PgConnection) -> Vec<Plan> {
+    plans::table
+        .order(plans::id)
+        .load::<Plan>(conn)
+        .expect("Error loading plans")
+}
+
+pub fn get_by_id(conn: &PgConnection, id: i32) -> Vec<Plan> {
+    plans::table
+        .filter(plans::id.eq(id))
+        .load::<Plan>(conn)
+        .expect("Error loading plan")
+}
+
+pub fn get_by_name(conn: &PgConnection, name: &str) -> Vec<Plan> {
+    plans::table
+        .filter(plans::name.eq(name))
+        .load::<Plan>(conn)
+        .expect("Error loading plan")
+}
+
+pub fn get_by_stripe_product_name(conn: &PgConnection, product_name: &str) -> Vec<Plan> {
+    plans::table
+        .filter(plans::stripe_product_name.eq(product_name))
+        .load::<Plan>(conn)
+        .expect("Error loading plan")
+}
@@ -0,0 +1,80 @@
+use crate::schema::{rate_limit_hits, rate_limit_lockouts};
+
+use chrono::naive::NaiveDateTime;
+use diesel::{prelude::*, sql_types::Text};
+
+#[derive(Insertable)]
+#[table_name = "rate_limit_hits"]
+pub struct NewRateLimitHit {
+    bucket: String,
+    hit_at: NaiveDateTime,
+}
+
+impl NewRateLimitHit {
+    pub fn new(bucket: String, hit_at: NaiveDateTime) -> Self {
+        NewRateLimitHit { bucket, hit_at }
+    }
+
+    pub fn insert(&self, conn: &PgConnection) -> QueryResult<usize> {
+        diesel::insert_into(rate_limit_hits::table)
+            .values(self)
+            .execute(conn)
+    }
+}
+
+pub fn count_hits(conn: &PgConnection, bucket: &str) -> QueryResult<i64> {
+    rate_limit_hits::table
+        .filter(rate_limit_hits::bucket.eq(bucket))
+        .count()
+        .get_result(conn)
+}
+
+// holds the bucket until the transaction ends, so concurrent hits on it take turns
+pub fn lock_bucket(conn: &PgConnection, bucket: &str) -> QueryResult<usize> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind::<Text, _>(bucket)
+        .execute(conn)
+}
+
+// hits that fell out of the bucket's window don't count towards anything anymore
+pub fn delete_bucket_hits_before(
+    conn: &PgConnection,
+    bucket: &str,
+    before: NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::delete(
+        rate_limit_hits::table
+            .filter(rate_limit_hits::bucket.eq(bucket))
+            .filter(rate_limit_hits::hit_at.lt(before)),
+    )
+    .execute(conn)
+}
+
+// the same for every bucket, so ones nobody hits anymore get cleared out too
+pub fn delete_hits_before(conn: &PgConnection, before: NaiveDateTime) -> QueryResult<usize> {
+    diesel::delete(rate_limit_hits::table.filter(rate_limit_hits::hit_at.lt(before))).execute(conn)
+}
+
+pub fn clear_hits(conn: &PgConnection, bucket: &str) -> QueryResult<usize> {
+    diesel::delete(rate_limit_hits::table.filter(rate_limit_hits::bucket.eq(bucket))).execute(conn)
+}
+
+pub fn get_locked_until(conn: &PgConnection, bucket: &str) -> QueryResult<Option<NaiveDateTime>> {
+    rate_limit_lockouts::table
+        .filter(rate_limit_lockouts::bucket.eq(bucket))
+        .select(rate_limit_lockouts::locked_until)
+        .first(conn)
+        .optional()
+}
+
+pub fn lock(conn: &PgConnection, bucket: &str, locked_until: NaiveDateTime) -> QueryResult<usize> {
+    diesel::insert_into(rate_limit_lockouts::table)
+        .values((
+            rate_limit_lockouts::bucket.eq(bucket),
+            rate_limit_lockouts::locked_until.eq(locked_until),
+        ))
+        .on_conflict(rate_limit_lockouts::bucket)
+        .do_update()
+        .set(rate_limit_lockouts::locked_until.eq(locked_until))
+        .execute(conn)
+}
@@ -0,0 +1,45 @@
+use crate::schema::token_buckets;
+
+use chrono::naive::NaiveDateTime;
+use diesel::prelude::*;
+
+// a full bucket the first time anyone asks for it
+pub fn create_if_missing(
+    conn: &PgConnection,
+    bucket: &str,
+    tokens: f64,
+    updated_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::insert_into(token_buckets::table)
+        .values((
+            token_buckets::bucket.eq(bucket),
+            token_buckets::tokens.eq(tokens),
+            token_buckets::updated_at.eq(updated_at),
+        ))
+        .on_conflict(token_buckets::bucket)
+        .do_nothing()
+        .execute(conn)
+}
+
+// the tokens left and when they were counted, locked until the transaction is done
+pub fn lock(conn: &PgConnection, bucket: &str) -> QueryResult<(f64, NaiveDateTime)> {
+    token_buckets::table
+        .filter(token_buckets::bucket.eq(bucket))
+        .select((token_buckets::tokens, token_buckets::updated_at))
+        .for_update()
+        .first(conn)
+}
+
+pub fn save(
+    conn: &PgConnection,
+    bucket: &str,
+    tokens: f64,
+    updated_at: NaiveDateTime,
+) -> QueryResult<usize> {
+    diesel::update(token_buckets::table.filter(token_buckets::bucket.eq(bucket)))
+        .set((
+            token_buckets::tokens.eq(tokens),
+            token_buckets::updated_at.eq(updated_at),
+        ))
+        .execute(conn)
+}
@@ -1,9 +1,21 @@
-use crate::{schema::users, utils::now};
+use crate::{
+    models::plan::Plan,
+    schema::{plans, users},
+    utils::now,
+};
 
 use chrono::naive::NaiveDateTime;
 use diesel::prelude::*;
 use serde::Serialize;
 
+// users with this role, or in the cognito group of the same name, can see everyone's data
+pub const ADMIN_ROLE: &str = "admin";
+pub const USER_ROLE: &str = "user";
+// the time zone a user is on until they pick one
+pub const DEFAULT_TIME_ZONE: &str = "UTC";
+// only one active user can have an email
+pub const ACTIVE_EMAIL_INDEX: &str = "users_active_email";
+
 #[derive(Queryable, Serialize, Clone)]
 pub struct User {
     pub id: i32,
@@ -14,7 +26,15 @@ pub struct User {
     pub updated_at: Option<NaiveDateTime>,
     pub deleted_at: Option<NaiveDateTime>,
     pub active: bool,
-    pub user_tier: i32,
+    pub role: String,
+    pub plan_id: i32,
+    pub time_zone: String,
+}
+
+impl User {
+    pub fn is_admin(&self) -> bool {
+        self.role == ADMIN_ROLE
+    }
 }
 
 #[derive(Insertable)]
@@ -27,11 +47,11 @@ pub struct NewUser {
     updated_at: Option<NaiveDateTime>,
     deleted_at: Option<NaiveDateTime>,
     active: bool,
-    user_tier: i32,
+    plan_id: i32,
 }
 
 impl NewUser {
-    pub fn new(email: String, billing_id: String, authentication_id: String) -> Self {
+    pub fn new(email: String, billing_id: String, authentication_id: String, plan_id: i32) -> Self {
         NewUser {
             email,
             billing_id,
@@ -40,7 +60,7 @@ impl NewUser {
             updated_at: None,
             deleted_at: None,
             active: true,
-            user_tier: 0,
+            plan_id,
         }
     }
 
@@ -56,6 +76,47 @@ pub fn create(conn: &PgConnection, new_user: &NewUser) -> User {
         .expect("Error saving new user")
 }
 
+// insert the user unless they are already there, handing back whether this call made them.
+// racing first requests end up on the same row, and a sub whose account was deleted comes back
+// as a new account on its old row. an email another active user has is a unique violation
+// on ACTIVE_EMAIL_INDEX
+pub fn upsert(conn: &PgConnection, new_user: &NewUser) -> QueryResult<(User, bool)> {
+    let inserted = diesel::insert_into(users::table)
+        .values(new_user)
+        .on_conflict(users::authentication_id)
+        .do_nothing()
+        .get_result::<User>(conn)
+        .optional()?;
+    if let Some(user) = inserted {
+        return Ok((user, true));
+    }
+
+    let reactivated = diesel::update(users::table)
+        .filter(users::authentication_id.eq(&new_user.authentication_id))
+        .filter(users::active.eq(false))
+        .set((
+            users::email.eq(&new_user.email),
+            users::billing_id.eq(&new_user.billing_id),
+            users::plan_id.eq(new_user.plan_id),
+            users::role.eq(USER_ROLE),
+            users::time_zone.eq(DEFAULT_TIME_ZONE),
+            users::updated_at.eq(Some(now())),
+            users::deleted_at.eq(None::<NaiveDateTime>),
+            users::active.eq(true),
+        ))
+        .get_result::<User>(conn)
+        .optional()?;
+
+    match reactivated {
+        Some(user) => Ok((user, true)),
+        None => users::table
+            .filter(users::authentication_id.eq(&new_user.authentication_id))
+            .filter(users::active.eq(true))
+            .first::<User>(conn)
+            .map(|user| (user, false)),
+    }
+}
+
 pub fn read(conn: &PgConnection) -> Vec<User> {
     users::table
         .filter(users::active.eq(true))
@@ -63,6 +124,15 @@ pub fn read(conn: &PgConnection) -> Vec<User> {
         .expect("Error loading user")
 }
 
+// active users along with their plan, None for those without one
+pub fn read_with_plans(conn: &PgConnection, ids: &[i32]) -> QueryResult<Vec<(User, Option<Plan>)>> {
+    users::table
+        .left_join(plans::table)
+        .filter(users::id.eq_any(ids))
+        .filter(users::active.eq(true))
+        .load::<(User, Option<Plan>)>(conn)
+}
+
 pub fn get_user_by_authentication_id(conn: &PgConnection, authentication_id: String) -> Vec<User> {
     users::table
         .filter(users::authentication_id.eq(authentication_id))
@@ -71,6 +141,23 @@ pub fn get_user_by_authentication_id(conn: &PgConnection, authentication_id: Str
         .expect("Error loading user")
 }
 
+pub fn get_user_by_id(conn: &PgConnection, id: i32) -> Vec<User> {
+    users::table
+        .filter(users::id.eq(id))
+        .filter(users::active.eq(true))
+        .load::<User>(conn)
+        .expect("Error loading user")
+}
+
+// holds the user's row until the transaction ends, so checks against their plan can't race
+pub fn lock_by_id(conn: &PgConnection, id: i32) -> QueryResult<User> {
+    users::table
+        .filter(users::id.eq(id))
+        .filter(users::active.eq(true))
+        .for_update()
+        .first::<User>(conn)
+}
+
 pub fn get_user_by_email(conn: &PgConnection, email: String) -> Vec<User> {
     users::table
         .filter(users::email.eq(email))
@@ -79,10 +166,38 @@ pub fn get_user_by_email(conn: &PgConnection, email: String) -> Vec<User> {
         .expect("Error loading user")
 }
 
-pub fn update_user(conn: &PgConnection, email: String, billing_id: String) -> usize {
+pub fn update_user(conn: &PgConnection, email: String, billing_id: String, plan_id: i32) -> usize {
     diesel::update(users::table)
         .filter(users::email.eq(email))
-        .set(users::billing_id.eq(billing_id))
+        .set((
+            users::billing_id.eq(billing_id),
+            users::plan_id.eq(plan_id),
+            users::updated_at.eq(Some(now())),
+        ))
+        .execute(conn)
+        .expect("Error updating user")
+}
+
+pub fn update_time_zone(conn: &PgConnection, id: i32, time_zone: String) -> usize {
+    diesel::update(users::table)
+        .filter(users::id.eq(id))
+        .set((
+            users::time_zone.eq(time_zone),
+            users::updated_at.eq(Some(now())),
+        ))
         .execute(conn)
         .expect("Error updating user")
 }
+
+// soft delete every row for this sub, handing back their ids
+pub fn delete_by_authentication_id(
+    conn: &PgConnection,
+    authentication_id: String,
+) -> QueryResult<Vec<i32>> {
+    diesel::update(users::table)
+        .filter(users::authentication_id.eq(authentication_id))
+        .filter(users::active.eq(true))
+        .set((users::active.eq(false), users::deleted_at.eq(now())))
+        .returning(users::id)
+        .get_results(conn)
+}
@@ -0,0 +1,58 @@
+use crate::{
+    handlers::api_key::PostApiKey,
+    models::user::User,
+    services::{jwt::JwtVerifier, user::with_token_db_and_user},
+    utils::JwtPayload,
+    with_db_conn, with_jwt, DbConn,
+};
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, Filter};
+
+fn path_prefix() -> BoxedFilter<()> {
+    warp::path("api-keys").boxed()
+}
+
+// keys are managed with an id token only, so a leaked key can't mint more of itself
+pub fn add_api_key(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, PostApiKey)> {
+    warp::post()
+        .and(path_prefix())
+        .and(warp::path::end())
+        .and(with_jwt(jwt_verifier))
+        .and(with_db_conn(db_conn))
+        .and_then(with_token_db_and_user)
+        .untuple_one()
+        .and(warp::body::json())
+        .boxed()
+}
+
+pub fn get_api_keys(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User)> {
+    warp::get()
+        .and(path_prefix())
+        .and(warp::path::end())
+        .and(with_jwt(jwt_verifier))
+        .and(with_db_conn(db_conn))
+        .and_then(with_token_db_and_user)
+        .untuple_one()
+        .boxed()
+}
+
+pub fn delete_api_key(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, i32)> {
+    warp::delete()
+        .and(path_prefix())
+        .and(with_jwt(jwt_verifier))
+        .and(with_db_conn(db_conn))
+        .and_then(with_token_db_and_user)
+        .untuple_one()
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .boxed()
+}
@@ -1,95 +1,183 @@
 use crate::{
+    db_conn::DbConn,
     handlers::auth::{
+        AssociateSoftwareTokenCredentials, ChallengeCredentials, ChangePasswordCredentials,
         ConfirmForgotPasswordCredentials, ConfirmationCredentials, LoginCredentials,
-        RefreshCredentials, UsernameCredentials,
+        RefreshCredentials, RevokeCredentials, UsernameCredentials, VerifySoftwareTokenCredentials,
     },
-    services::cognito::with_cognito,
-    with_config, Config,
+    services::{
+        identity::{with_identity_provider, IdentityProvider},
+        jwt::{AccessToken, JwtVerifier},
+        rate_limit::{with_rate_limit, RateLimiter},
+    },
+    with_access_token, with_db_conn,
 };
-use aws_sdk_cognitoidentityprovider::Client;
 use std::sync::Arc;
 use warp::{filters::BoxedFilter, Filter};
 
 pub fn login(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(LoginCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(LoginCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("login"))
-        .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_rate_limit(rate_limiter, "login"))
+        .and(with_identity_provider(identity_provider))
         .boxed()
 }
 
 pub fn sign_up(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(LoginCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(LoginCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("sign-up"))
-        .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_rate_limit(rate_limiter, "sign-up"))
+        .and(with_identity_provider(identity_provider))
         .boxed()
 }
 
 pub fn verify(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(ConfirmationCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(ConfirmationCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("verify"))
-        .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_rate_limit(rate_limiter, "verify"))
+        .and(with_identity_provider(identity_provider))
         .boxed()
 }
 
 pub fn resend_code(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(UsernameCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(UsernameCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("resend-code"))
-        .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_rate_limit(rate_limiter, "resend-code"))
+        .and(with_identity_provider(identity_provider))
         .boxed()
 }
 
 pub fn forgot_password(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(UsernameCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(UsernameCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("forgot-password"))
-        .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_rate_limit(rate_limiter, "forgot-password"))
+        .and(with_identity_provider(identity_provider))
         .boxed()
 }
 
 pub fn confirm_forgot_password(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(ConfirmForgotPasswordCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(ConfirmForgotPasswordCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("confirm-forgot-password"))
-        .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_rate_limit(rate_limiter, "confirm-forgot-password"))
+        .and(with_identity_provider(identity_provider))
         .boxed()
 }
 
 pub fn refresh_token(
-    config: Arc<Config>,
-    cognito: Arc<Client>,
-) -> BoxedFilter<(RefreshCredentials, Arc<Config>, Arc<Client>)> {
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> BoxedFilter<(RefreshCredentials, Arc<dyn IdentityProvider>)> {
     warp::post()
         .and(warp::path("refresh"))
         .and(warp::body::json())
-        .and(with_config(config))
-        .and(with_cognito(cognito))
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn respond_to_challenge(
+    identity_provider: Arc<dyn IdentityProvider>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<(ChallengeCredentials, Arc<dyn IdentityProvider>)> {
+    warp::post()
+        .and(warp::path("respond-to-challenge"))
+        .and(with_rate_limit(rate_limiter, "respond-to-challenge"))
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn associate_software_token(
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> BoxedFilter<(
+    Option<String>,
+    AssociateSoftwareTokenCredentials,
+    Arc<dyn IdentityProvider>,
+)> {
+    warp::post()
+        .and(warp::path("associate-software-token"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::json())
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn verify_software_token(
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> BoxedFilter<(
+    Option<String>,
+    VerifySoftwareTokenCredentials,
+    Arc<dyn IdentityProvider>,
+)> {
+    warp::post()
+        .and(warp::path("verify-software-token"))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::body::json())
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn change_password(
+    identity_provider: Arc<dyn IdentityProvider>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(
+    AccessToken,
+    ChangePasswordCredentials,
+    Arc<dyn IdentityProvider>,
+)> {
+    warp::post()
+        .and(warp::path("change-password"))
+        .and(with_access_token(jwt_verifier))
+        .and(warp::body::json())
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn sign_out(
+    identity_provider: Arc<dyn IdentityProvider>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(AccessToken, Arc<dyn IdentityProvider>)> {
+    warp::post()
+        .and(warp::path("sign-out"))
+        .and(with_access_token(jwt_verifier))
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn revoke(
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> BoxedFilter<(RevokeCredentials, Arc<dyn IdentityProvider>)> {
+    warp::post()
+        .and(warp::path("revoke"))
+        .and(warp::body::json())
+        .and(with_identity_provider(identity_provider))
+        .boxed()
+}
+
+pub fn delete_account(
+    identity_provider: Arc<dyn IdentityProvider>,
+    jwt_verifier: Arc<JwtVerifier>,
+    db_conn: Arc<DbConn>,
+) -> BoxedFilter<(AccessToken, Arc<dyn IdentityProvider>, Arc<DbConn>)> {
+    warp::delete()
+        .and(warp::path("account"))
+        .and(with_access_token(jwt_verifier))
+        .and(with_identity_provider(identity_provider))
+        .and(with_db_conn(db_conn))
         .boxed()
 }
@@ -1,98 +1,223 @@
 use crate::{
     config::Config,
     models::{
-        emailer::{PostEmailer, PutEmailer},
+        emailer::{PatchEmailer, PostEmailer, PutEmailer},
         user::User,
     },
-    services::{user::with_token_db_and_user, zillow::ZillowSearchParameters},
-    utils::JwtPayload,
-    with_config, with_db_conn, with_jwt, with_reqwest_client, DbConn,
+    services::{
+        api_key::{EMAILERS_READ, EMAILERS_WRITE},
+        jwt::JwtVerifier,
+        listing_source::SearchParameters,
+        rate_limit::{with_rate_limiter, RateLimiter},
+        zillow::ZillowClient,
+    },
+    utils::{JwtPayload, PageQuery},
+    with_admin, with_config, with_db_conn, with_user_auth, with_zillow_client, DbConn,
 };
 use std::sync::Arc;
 use warp::{filters::BoxedFilter, Filter};
 
+// a new emailer, with the config to check its listing source against
+type NewEmailer = (JwtPayload, Arc<DbConn>, User, Arc<Config>, PostEmailer);
+
+// who is writing, to which emailer, the If-Match they sent, the config to check the
+// listing source against and the body
+type EmailerWrite<T> = (
+    JwtPayload,
+    Arc<DbConn>,
+    User,
+    i32,
+    Option<String>,
+    Arc<Config>,
+    T,
+);
+
+// previews search zillow, so they need the config and its client as well as the user, and
+// the rate limiter that keeps them from spending the quota
+type EmailerPreview = (
+    JwtPayload,
+    Arc<DbConn>,
+    User,
+    i32,
+    Arc<Config>,
+    Arc<ZillowClient>,
+    Arc<RateLimiter>,
+);
+type NewEmailerPreview = (
+    JwtPayload,
+    Arc<DbConn>,
+    User,
+    Arc<Config>,
+    Arc<ZillowClient>,
+    Arc<RateLimiter>,
+    PostEmailer,
+);
+
+type TestSearch = (
+    JwtPayload,
+    Arc<DbConn>,
+    User,
+    Arc<Config>,
+    Arc<ZillowClient>,
+    Arc<RateLimiter>,
+    SearchParameters,
+);
+
 fn path_prefix() -> BoxedFilter<()> {
     warp::path("emailers").boxed()
 }
 
-pub fn get_all_emailers(db_conn: Arc<DbConn>) -> BoxedFilter<(Arc<DbConn>,)> {
+pub fn get_all_emailers(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>)> {
     warp::get()
         .and(path_prefix())
         .and(warp::path("all"))
         .and(warp::path::end())
+        .and(with_admin(jwt_verifier, db_conn.clone()))
         .and(with_db_conn(db_conn))
         .boxed()
 }
 
 pub fn add_emailer(
     db_conn: Arc<DbConn>,
-) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, PostEmailer)> {
+    jwt_verifier: Arc<JwtVerifier>,
+    config: Arc<Config>,
+) -> BoxedFilter<NewEmailer> {
     warp::post()
         .and(path_prefix())
         .and(warp::path::end())
-        .and(warp::header::<String>("authorization"))
-        .and_then(with_jwt)
-        .and(with_db_conn(db_conn))
-        .and_then(with_token_db_and_user)
-        .untuple_one()
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_WRITE))
+        .and(with_config(config))
         .and(warp::body::json())
         .boxed()
 }
 
 pub fn update_emailer(
     db_conn: Arc<DbConn>,
-) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, PutEmailer)> {
+    jwt_verifier: Arc<JwtVerifier>,
+    config: Arc<Config>,
+) -> BoxedFilter<EmailerWrite<PutEmailer>> {
     warp::put()
         .and(path_prefix())
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_WRITE))
+        .and(warp::path::param())
         .and(warp::path::end())
-        .and(warp::header::<String>("authorization"))
-        .and_then(with_jwt)
-        .and(with_db_conn(db_conn))
-        .and_then(with_token_db_and_user)
-        .untuple_one()
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_config(config))
+        .and(warp::body::json())
+        .boxed()
+}
+
+pub fn patch_emailer(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+    config: Arc<Config>,
+) -> BoxedFilter<EmailerWrite<PatchEmailer>> {
+    warp::patch()
+        .and(path_prefix())
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_WRITE))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .and(warp::header::optional::<String>("if-match"))
+        .and(with_config(config))
         .and(warp::body::json())
         .boxed()
 }
 
 pub fn get_emailer_by_authentication_id(
     db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
 ) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User)> {
     warp::get()
         .and(path_prefix())
         .and(warp::path::end())
-        .and(warp::header::<String>("authorization"))
-        .and_then(with_jwt)
-        .and(with_db_conn(db_conn))
-        .and_then(with_token_db_and_user)
-        .untuple_one()
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_READ))
         .boxed()
 }
 
 pub fn delete_emailer_by_authentication_id(
     db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
 ) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, i32)> {
     warp::delete()
         .and(path_prefix())
-        .and(warp::header::<String>("authorization"))
-        .and_then(with_jwt)
-        .and(with_db_conn(db_conn))
-        .and_then(with_token_db_and_user)
-        .untuple_one()
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_WRITE))
+        .and(warp::path::param())
+        .and(warp::path::end())
+        .boxed()
+}
+
+pub fn get_emailer_runs(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, i32, PageQuery)> {
+    warp::get()
+        .and(path_prefix())
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_READ))
         .and(warp::path::param())
+        .and(warp::path("runs"))
         .and(warp::path::end())
+        .and(warp::query::<PageQuery>())
         .boxed()
 }
 
+pub fn preview_emailer(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+    config: Arc<Config>,
+    client: Arc<ZillowClient>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<EmailerPreview> {
+    warp::post()
+        .and(path_prefix())
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_READ))
+        .and(warp::path::param())
+        .and(warp::path("preview"))
+        .and(warp::path::end())
+        .and(with_config(config))
+        .and(with_zillow_client(client))
+        .and(with_rate_limiter(rate_limiter))
+        .boxed()
+}
+
+pub fn preview_new_emailer(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+    config: Arc<Config>,
+    client: Arc<ZillowClient>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<NewEmailerPreview> {
+    warp::post()
+        .and(path_prefix())
+        .and(warp::path("preview"))
+        .and(warp::path::end())
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_READ))
+        .and(with_config(config))
+        .and(with_zillow_client(client))
+        .and(with_rate_limiter(rate_limiter))
+        .and(warp::body::json())
+        .boxed()
+}
+
+// searches spend the shared zillow quota, so only signed in users get to run them, and
+// only so often
 pub fn test_emailer_params(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
     config: Arc<Config>,
-    client: Arc<reqwest::Client>,
-) -> BoxedFilter<(Arc<Config>, Arc<reqwest::Client>, ZillowSearchParameters)> {
+    client: Arc<ZillowClient>,
+    rate_limiter: Arc<RateLimiter>,
+) -> BoxedFilter<TestSearch> {
     warp::get()
         .and(path_prefix())
         .and(warp::path("test-search-param"))
         .and(warp::path::end())
+        .and(with_user_auth(jwt_verifier, db_conn, EMAILERS_READ))
         .and(with_config(config))
-        .and(with_reqwest_client(client))
-        .and(warp::query::<ZillowSearchParameters>())
+        .and(with_zillow_client(client))
+        .and(with_rate_limiter(rate_limiter))
+        .and(warp::query::<SearchParameters>())
         .boxed()
 }
@@ -0,0 +1,11 @@
+use crate::services::metrics::{with_metrics, Metrics};
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, Filter};
+
+pub fn get_metrics(metrics: Arc<dyn Metrics>) -> BoxedFilter<(Arc<dyn Metrics>,)> {
+    warp::get()
+        .and(warp::path("metrics"))
+        .and(warp::path::end())
+        .and(with_metrics(metrics))
+        .boxed()
+}
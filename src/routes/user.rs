@@ -1,9 +1,12 @@
 use crate::{
+    handlers::user::PutUser,
     models::user::User,
     services::{
         email::with_email,
-        user::{create_user_from_jwt, with_user},
+        jwt::JwtVerifier,
+        user::{get_or_create_user_from_jwt, with_token_db_and_user},
     },
+    utils::JwtPayload,
     with_config, with_db_conn, with_jwt, Config, DbConn,
 };
 use std::sync::Arc;
@@ -17,23 +20,31 @@ pub fn get_user_by_authentication_id(
     config: Arc<Config>,
     email: Arc<sendgrid_async::Client>,
     db_conn: Arc<DbConn>,
-) -> BoxedFilter<(User,)> {
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(User, Arc<DbConn>)> {
     warp::get()
         .and(path_prefix())
         .and(warp::path::end())
-        .and(warp::header::<String>("authorization"))
-        .and_then(with_jwt)
+        .and(with_jwt(jwt_verifier))
         .and(with_db_conn(db_conn.clone()))
-        .and_then(with_user)
-        .or(warp::get()
-            .and(path_prefix())
-            .and(warp::path::end())
-            .and(warp::header::<String>("authorization"))
-            .and_then(with_jwt)
-            .and(with_db_conn(db_conn))
-            .and(with_config(config))
-            .and(with_email(email))
-            .and_then(create_user_from_jwt))
-        .unify()
+        .and(with_config(config))
+        .and(with_email(email))
+        .and_then(get_or_create_user_from_jwt)
+        .and(with_db_conn(db_conn))
+        .boxed()
+}
+
+pub fn update_user(
+    db_conn: Arc<DbConn>,
+    jwt_verifier: Arc<JwtVerifier>,
+) -> BoxedFilter<(JwtPayload, Arc<DbConn>, User, PutUser)> {
+    warp::put()
+        .and(path_prefix())
+        .and(warp::path::end())
+        .and(with_jwt(jwt_verifier))
+        .and(with_db_conn(db_conn))
+        .and_then(with_token_db_and_user)
+        .untuple_one()
+        .and(warp::body::json())
         .boxed()
 }
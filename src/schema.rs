@@ -1,3 +1,55 @@
+table! {
+    api_keys (id) {
+        id -> Int4,
+        user_id -> Int4,
+        name -> Varchar,
+        key_prefix -> Varchar,
+        key_hash -> Varchar,
+        scopes -> Array<Text>,
+        last_used_at -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+        deleted_at -> Nullable<Timestamp>,
+        active -> Bool,
+    }
+}
+
+table! {
+    api_usage (user_id, month) {
+        user_id -> Int4,
+        month -> Date,
+        calls -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    emailer_run_windows (emailer_id, run_window) {
+        emailer_id -> Int4,
+        run_window -> Timestamp,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    emailer_runs (id) {
+        id -> Int8,
+        emailer_id -> Int4,
+        run_window -> Timestamp,
+        started_at -> Timestamp,
+        finished_at -> Nullable<Timestamp>,
+        zillow_url -> Varchar,
+        total_results -> Nullable<Int8>,
+        properties_fetched -> Int4,
+        properties_failed -> Int4,
+        listings_written -> Int4,
+        email_status -> Varchar,
+        error -> Nullable<Text>,
+    }
+}
+
 table! {
     emailers (id) {
         id -> Int4,
@@ -25,6 +77,28 @@ table! {
         user_id -> Int4,
         no_bathrooms -> Nullable<Int4>,
         notes -> Nullable<Varchar>,
+        next_run_at -> Timestamp,
+        last_run_at -> Nullable<Timestamp>,
+        version -> Int4,
+        listing_source -> Varchar,
+    }
+}
+
+table! {
+    jobs (id) {
+        id -> Int8,
+        kind -> Varchar,
+        payload -> Jsonb,
+        state -> Varchar,
+        attempts -> Int4,
+        max_attempts -> Int4,
+        run_at -> Timestamp,
+        locked_until -> Nullable<Timestamp>,
+        locked_by -> Nullable<Varchar>,
+        last_error -> Nullable<Text>,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+        finished_at -> Nullable<Timestamp>,
     }
 }
 
@@ -50,6 +124,75 @@ table! {
         updated_at -> Nullable<Timestamp>,
         deleted_at -> Nullable<Timestamp>,
         active -> Bool,
+        zpid -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    local_identities (id) {
+        id -> Int4,
+        username -> Varchar,
+        email -> Varchar,
+        sub -> Varchar,
+        password_hash -> Varchar,
+        confirmed -> Bool,
+        confirmation_code_hash -> Nullable<Varchar>,
+        confirmation_code_expires_at -> Nullable<Timestamp>,
+        reset_code_hash -> Nullable<Varchar>,
+        reset_code_expires_at -> Nullable<Timestamp>,
+        confirmation_code_attempts -> Int4,
+        reset_code_attempts -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+        deleted_at -> Nullable<Timestamp>,
+        active -> Bool,
+        signed_out_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    local_revoked_tokens (id) {
+        id -> Int4,
+        jti -> Varchar,
+        expires_at -> Timestamp,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    plans (id) {
+        id -> Int4,
+        name -> Varchar,
+        stripe_product_name -> Nullable<Varchar>,
+        max_emailers -> Int4,
+        allowed_frequencies -> Array<Text>,
+        max_listings_per_email -> Int4,
+        monthly_api_quota -> Int4,
+        created_at -> Timestamp,
+        updated_at -> Nullable<Timestamp>,
+    }
+}
+
+table! {
+    rate_limit_hits (id) {
+        id -> Int4,
+        bucket -> Varchar,
+        hit_at -> Timestamp,
+    }
+}
+
+table! {
+    rate_limit_lockouts (bucket) {
+        bucket -> Varchar,
+        locked_until -> Timestamp,
+    }
+}
+
+table! {
+    token_buckets (bucket) {
+        bucket -> Varchar,
+        tokens -> Float8,
+        updated_at -> Timestamp,
     }
 }
 
@@ -63,8 +206,30 @@ table! {
         updated_at -> Nullable<Timestamp>,
         deleted_at -> Nullable<Timestamp>,
         active -> Bool,
-        user_tier -> Int4,
+        role -> Varchar,
+        plan_id -> Int4,
+        time_zone -> Varchar,
     }
 }
 
-allow_tables_to_appear_in_same_query!(emailers, listing_data, users,);
+joinable!(api_usage -> users (user_id));
+joinable!(emailer_run_windows -> emailers (emailer_id));
+joinable!(emailer_runs -> emailers (emailer_id));
+joinable!(users -> plans (plan_id));
+
+allow_tables_to_appear_in_same_query!(
+    api_keys,
+    api_usage,
+    emailer_run_windows,
+    emailer_runs,
+    emailers,
+    jobs,
+    listing_data,
+    local_identities,
+    local_revoked_tokens,
+    plans,
+    rate_limit_hits,
+    rate_limit_lockouts,
+    token_buckets,
+    users,
+);
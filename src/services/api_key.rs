@@ -0,0 +1,100 @@
+use crate::{
+    models::{
+        api_key::{self, ApiKey, NewApiKey},
+        user::{self, User},
+    },
+    utils::now,
+    BadApiKey, Forbidden,
+};
+use chrono::{naive::NaiveDateTime, Duration};
+use diesel::prelude::*;
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+// every key starts with this, so they are easy to spot in scripts and secret scanners
+const KEY_PREFIX: &str = "ostrich_";
+
+// how much of the key we keep in the clear, to tell keys apart when listing them
+const DISPLAY_PREFIX_LENGTH: usize = 12;
+
+pub const EMAILERS_READ: &str = "emailers:read";
+pub const EMAILERS_WRITE: &str = "emailers:write";
+pub const SCOPES: [&str; 2] = [EMAILERS_READ, EMAILERS_WRITE];
+
+fn generate_key() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    format!("{}{}", KEY_PREFIX, hex::encode(bytes))
+}
+
+// keys are long and random, so a plain sha256 is enough to keep a database dump from using them
+fn hash_key(key: &str) -> String {
+    hex::encode(Sha256::digest(key.as_bytes()))
+}
+
+// hands back the stored key along with the only copy of the key itself
+pub fn create_api_key(
+    conn: &PgConnection,
+    user_id: i32,
+    name: String,
+    scopes: Vec<String>,
+    expires_at: Option<NaiveDateTime>,
+) -> (ApiKey, String) {
+    let key = generate_key();
+    let new_api_key = NewApiKey::new(
+        user_id,
+        name,
+        String::from(&key[..DISPLAY_PREFIX_LENGTH]),
+        hash_key(&key),
+        scopes,
+        expires_at,
+    );
+    (new_api_key.insert(conn), key)
+}
+
+pub fn expires_at(expires_in_days: Option<i64>) -> Option<NaiveDateTime> {
+    expires_in_days.map(|days| now() + Duration::days(days))
+}
+
+// find the user behind a key, as long as it is live and allowed to do this
+pub fn authenticate(conn: &PgConnection, key: &str, scope: &str) -> Result<User, warp::Rejection> {
+    let api_key = api_key::get_by_hash(conn, hash_key(key))
+        .first()
+        .cloned()
+        .ok_or_else(|| warp::reject::custom(BadApiKey))?;
+
+    if !is_live(&api_key, now()) {
+        log::warn!("Rejecting expired api key {}", api_key.id);
+        return Err(warp::reject::custom(BadApiKey));
+    }
+
+    if !api_key.scopes.iter().any(|granted| granted == scope) {
+        log::warn!("Api key {} is missing the {} scope", api_key.id, scope);
+        return Err(warp::reject::custom(Forbidden));
+    }
+
+    let user = user::get_user_by_id(conn, api_key.user_id)
+        .first()
+        .cloned()
+        .ok_or_else(|| warp::reject::custom(BadApiKey))?;
+
+    api_key::touch(conn, api_key.id);
+    Ok(user)
+}
+
+fn is_live(api_key: &ApiKey, now: NaiveDateTime) -> bool {
+    match api_key.expires_at {
+        Some(expires_at) => expires_at > now,
+        None => true,
+    }
+}
+
+#[test]
+fn keys_are_random_and_hashed() {
+    let key = generate_key();
+    assert!(key.starts_with(KEY_PREFIX));
+    assert_eq!(key.len(), KEY_PREFIX.len() + 64);
+    assert_ne!(key, generate_key());
+    assert_eq!(hash_key(&key), hash_key(&key));
+    assert_ne!(hash_key(&key), key);
+}
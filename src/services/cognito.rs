@@ -1,20 +1,49 @@
-use crate::{handlers::auth::AuthenticationDetails, utils::base64_hmac};
+use crate::{
+    config::Config,
+    handlers::auth::{
+        AuthChallenge, AuthError, AuthErrorCode, AuthenticationDetails, LoginResult,
+        SoftwareTokenAssociation, SoftwareTokenVerification,
+    },
+    services::{identity::IdentityProvider, jwt::AccessToken},
+};
+use async_trait::async_trait;
+use aws_sdk_cognitoidentityprovider::{
+    model::{AuthenticationResultType, ChallengeNameType},
+    types::SdkError,
+    Client, Error as CognitoServiceError,
+};
+use std::{collections::HashMap, sync::Arc};
+use warp::{filters::BoxedFilter, Filter};
+
+#[cfg(not(feature = "mocks"))]
+use crate::utils::base64_hmac;
+#[cfg(not(feature = "mocks"))]
 use aws_config::meta::region::RegionProviderChain;
+#[cfg(not(feature = "mocks"))]
 use aws_sdk_cognitoidentityprovider::{
     error::{
-        ConfirmForgotPasswordError, ConfirmSignUpError, ForgotPasswordError, InitiateAuthError,
-        ResendConfirmationCodeError, SignUpError,
+        AssociateSoftwareTokenError, ChangePasswordError, ConfirmForgotPasswordError,
+        ConfirmSignUpError, DeleteUserError, ForgotPasswordError, GlobalSignOutError,
+        InitiateAuthError, ResendConfirmationCodeError, RespondToAuthChallengeError,
+        RevokeTokenError, SetUserMFAPreferenceError, SignUpError, VerifySoftwareTokenError,
     },
-    model::{AttributeType, AuthFlowType, AuthenticationResultType},
+    model::{AttributeType, AuthFlowType, SoftwareTokenMfaSettingsType},
     output::{
-        ConfirmForgotPasswordOutput, ConfirmSignUpOutput, ForgotPasswordOutput, InitiateAuthOutput,
-        ResendConfirmationCodeOutput, SignUpOutput,
+        AssociateSoftwareTokenOutput, ChangePasswordOutput, ConfirmForgotPasswordOutput,
+        ConfirmSignUpOutput, DeleteUserOutput, ForgotPasswordOutput, GlobalSignOutOutput,
+        InitiateAuthOutput, ResendConfirmationCodeOutput, RespondToAuthChallengeOutput,
+        RevokeTokenOutput, SetUserMfaPreferenceOutput, SignUpOutput, VerifySoftwareTokenOutput,
     },
-    types::SdkError,
-    Client, Region,
+    Region,
+};
+
+#[cfg(feature = "mocks")]
+pub use crate::services::mocks::cognito::{
+    associate_software_token, change_password, confirm_forgot_password, delete_user,
+    enable_software_token_mfa, forgot_password, get_cognito_client, global_sign_out, initiate,
+    refresh, resend_confirmation_code, respond_to_auth_challenge, revoke_token, sign_up, verify,
+    verify_software_token,
 };
-use std::{collections::HashMap, sync::Arc};
-use warp::{filters::BoxedFilter, Filter};
 
 // convert the aws type to our type
 impl From<AuthenticationResultType> for AuthenticationDetails {
@@ -29,10 +58,350 @@ impl From<AuthenticationResultType> for AuthenticationDetails {
     }
 }
 
+// fold every operation's error into the service wide enum so each
+// cognito exception maps to one of our codes in a single place
+fn handle_cognito_error<E>(error: SdkError<E>) -> AuthError
+where
+    CognitoServiceError: From<SdkError<E>>,
+{
+    auth_error(CognitoServiceError::from(error))
+}
+
+fn auth_error(error: CognitoServiceError) -> AuthError {
+    let code = match &error {
+        CognitoServiceError::UserNotConfirmedException(_) => AuthErrorCode::UserNotConfirmed,
+        CognitoServiceError::NotAuthorizedException(_) => AuthErrorCode::NotAuthorized,
+        CognitoServiceError::UserNotFoundException(_) => AuthErrorCode::UserNotFound,
+        CognitoServiceError::UsernameExistsException(_)
+        | CognitoServiceError::AliasExistsException(_) => AuthErrorCode::UsernameExists,
+        CognitoServiceError::CodeMismatchException(_) => AuthErrorCode::CodeMismatch,
+        CognitoServiceError::ExpiredCodeException(_) => AuthErrorCode::ExpiredCode,
+        CognitoServiceError::LimitExceededException(_) => AuthErrorCode::LimitExceeded,
+        CognitoServiceError::TooManyRequestsException(_) => AuthErrorCode::TooManyRequests,
+        CognitoServiceError::TooManyFailedAttemptsException(_) => {
+            AuthErrorCode::TooManyFailedAttempts
+        }
+        CognitoServiceError::InvalidPasswordException(_) => AuthErrorCode::InvalidPassword,
+        CognitoServiceError::InvalidParameterException(_) => AuthErrorCode::InvalidParameter,
+        CognitoServiceError::PasswordResetRequiredException(_) => {
+            AuthErrorCode::PasswordResetRequired
+        }
+        CognitoServiceError::CodeDeliveryFailureException(_) => AuthErrorCode::CodeDeliveryFailure,
+        CognitoServiceError::SoftwareTokenMfaNotFoundException(_)
+        | CognitoServiceError::EnableSoftwareTokenMfaException(_) => {
+            AuthErrorCode::SoftwareTokenMfaNotFound
+        }
+        // misconfigured pools, lambdas and network trouble are on us, not the user
+        CognitoServiceError::InternalErrorException(_)
+        | CognitoServiceError::InvalidEmailRoleAccessPolicyException(_)
+        | CognitoServiceError::InvalidLambdaResponseException(_)
+        | CognitoServiceError::InvalidSmsRoleAccessPolicyException(_)
+        | CognitoServiceError::InvalidSmsRoleTrustRelationshipException(_)
+        | CognitoServiceError::InvalidUserPoolConfigurationException(_)
+        | CognitoServiceError::ResourceNotFoundException(_)
+        | CognitoServiceError::UnexpectedLambdaException(_)
+        | CognitoServiceError::Unhandled(_) => AuthErrorCode::ServerError,
+        _ => AuthErrorCode::InvalidRequest,
+    };
+
+    if code == AuthErrorCode::ServerError {
+        log::error!("Cognito error: {:?}", error);
+    }
+    AuthError::new(code, format!("{}", error))
+}
+
+// tokens if cognito is done with the user, otherwise the next challenge to answer
+fn login_result(
+    authentication_result: Option<AuthenticationResultType>,
+    challenge_name: Option<ChallengeNameType>,
+    session: Option<String>,
+    challenge_parameters: Option<HashMap<String, String>>,
+) -> Result<LoginResult, AuthError> {
+    match (authentication_result, challenge_name) {
+        (Some(authentication_result), _) => Ok(LoginResult::Authenticated(
+            AuthenticationDetails::from(authentication_result),
+        )),
+        (None, Some(challenge_name)) => Ok(LoginResult::Challenge(AuthChallenge {
+            challenge_name: String::from(challenge_name.as_str()),
+            session,
+            challenge_parameters: challenge_parameters.unwrap_or_default(),
+        })),
+        (None, None) => Err(AuthError::new(
+            AuthErrorCode::ServerError,
+            String::from("No Authentication Results"),
+        )),
+    }
+}
+
+// the user pool behind the IdentityProvider trait
+pub struct CognitoIdentityProvider {
+    client: Arc<Client>,
+    client_id: String,
+    secret_key: String,
+}
+
+impl CognitoIdentityProvider {
+    pub fn new(config: Arc<Config>, client: Arc<Client>) -> Self {
+        CognitoIdentityProvider {
+            client,
+            client_id: config.cognito.client_id.clone(),
+            secret_key: config.cognito.secret_key.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for CognitoIdentityProvider {
+    async fn sign_up(
+        &self,
+        username: String,
+        password: String,
+        email: String,
+    ) -> Result<String, AuthError> {
+        sign_up(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+            password,
+            email,
+        )
+        .await
+        .map_err(handle_cognito_error)?
+        .user_sub
+        .ok_or_else(|| {
+            AuthError::new(
+                AuthErrorCode::ServerError,
+                String::from("No Sign Up Results"),
+            )
+        })
+    }
+
+    async fn verify(&self, username: String, code: String) -> Result<(), AuthError> {
+        verify(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+            code,
+        )
+        .await
+        .map_err(handle_cognito_error)
+        .map(|_| ())
+    }
+
+    async fn initiate(&self, username: String, password: String) -> Result<LoginResult, AuthError> {
+        let output = initiate(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+            password,
+        )
+        .await
+        .map_err(handle_cognito_error)?;
+
+        login_result(
+            output.authentication_result,
+            output.challenge_name,
+            output.session,
+            output.challenge_parameters,
+        )
+    }
+
+    async fn respond_to_challenge(
+        &self,
+        username: String,
+        challenge_name: String,
+        session: String,
+        responses: HashMap<String, String>,
+    ) -> Result<LoginResult, AuthError> {
+        let output = respond_to_auth_challenge(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+            challenge_name,
+            session,
+            responses,
+        )
+        .await
+        .map_err(handle_cognito_error)?;
+
+        login_result(
+            output.authentication_result,
+            output.challenge_name,
+            output.session,
+            output.challenge_parameters,
+        )
+    }
+
+    async fn refresh(
+        &self,
+        username: String,
+        refresh_token: String,
+    ) -> Result<AuthenticationDetails, AuthError> {
+        refresh(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+            refresh_token,
+        )
+        .await
+        .map_err(handle_cognito_error)?
+        .authentication_result
+        .map(AuthenticationDetails::from)
+        .ok_or_else(|| {
+            AuthError::new(
+                AuthErrorCode::ServerError,
+                String::from("No Authentication Results"),
+            )
+        })
+    }
+
+    async fn resend_confirmation_code(&self, username: String) -> Result<(), AuthError> {
+        resend_confirmation_code(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+        )
+        .await
+        .map_err(handle_cognito_error)
+        .map(|_| ())
+    }
+
+    async fn forgot_password(&self, username: String) -> Result<(), AuthError> {
+        forgot_password(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+        )
+        .await
+        .map_err(handle_cognito_error)
+        .map(|_| ())
+    }
+
+    async fn confirm_forgot_password(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<(), AuthError> {
+        confirm_forgot_password(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            username,
+            password,
+            code,
+        )
+        .await
+        .map_err(handle_cognito_error)
+        .map(|_| ())
+    }
+
+    async fn change_password(
+        &self,
+        access_token: &AccessToken,
+        previous_password: String,
+        proposed_password: String,
+    ) -> Result<(), AuthError> {
+        change_password(
+            self.client.clone(),
+            access_token.token.clone(),
+            previous_password,
+            proposed_password,
+        )
+        .await
+        .map_err(handle_cognito_error)
+        .map(|_| ())
+    }
+
+    async fn global_sign_out(&self, access_token: &AccessToken) -> Result<(), AuthError> {
+        global_sign_out(self.client.clone(), access_token.token.clone())
+            .await
+            .map_err(handle_cognito_error)
+            .map(|_| ())
+    }
+
+    async fn revoke_refresh_token(&self, refresh_token: String) -> Result<(), AuthError> {
+        revoke_token(
+            self.client.clone(),
+            self.client_id.clone(),
+            self.secret_key.clone(),
+            refresh_token,
+        )
+        .await
+        .map_err(handle_cognito_error)
+        .map(|_| ())
+    }
+
+    async fn delete_user(&self, access_token: &AccessToken) -> Result<(), AuthError> {
+        delete_user(self.client.clone(), access_token.token.clone())
+            .await
+            .map_err(handle_cognito_error)
+            .map(|_| ())
+    }
+
+    async fn associate_software_token(
+        &self,
+        access_token: Option<String>,
+        session: Option<String>,
+    ) -> Result<SoftwareTokenAssociation, AuthError> {
+        associate_software_token(self.client.clone(), access_token, session)
+            .await
+            .map_err(handle_cognito_error)
+            .map(|output| SoftwareTokenAssociation {
+                secret_code: output.secret_code,
+                session: output.session,
+            })
+    }
+
+    async fn verify_software_token(
+        &self,
+        access_token: Option<String>,
+        session: Option<String>,
+        code: String,
+        device_name: Option<String>,
+    ) -> Result<SoftwareTokenVerification, AuthError> {
+        let output = verify_software_token(
+            self.client.clone(),
+            access_token.clone(),
+            session,
+            code,
+            device_name,
+        )
+        .await
+        .map_err(handle_cognito_error)?;
+
+        let status = output
+            .status
+            .map(|status| String::from(status.as_str()))
+            .unwrap_or_default();
+
+        // a logged in user verifying their app wants mfa turned on,
+        // during MFA_SETUP cognito turns it on once the challenge is answered
+        if let (Some(access_token), "SUCCESS") = (access_token, status.as_str()) {
+            enable_software_token_mfa(self.client.clone(), access_token)
+                .await
+                .map_err(handle_cognito_error)?;
+        }
+
+        Ok(SoftwareTokenVerification {
+            status,
+            session: output.session,
+        })
+    }
+}
+
 pub fn with_cognito(cognito: Arc<Client>) -> BoxedFilter<(Arc<Client>,)> {
     warp::any().map(move || cognito.clone()).boxed()
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_cognito_client(region: String) -> Client {
     let region_provider = RegionProviderChain::first_try(Region::new(region))
         .or_default_provider()
@@ -42,6 +411,7 @@ pub async fn get_cognito_client(region: String) -> Client {
     client
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn sign_up(
     client: Arc<Client>,
     client_id: String,
@@ -71,6 +441,7 @@ pub async fn sign_up(
     sign_up
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn verify(
     client: Arc<Client>,
     client_id: String,
@@ -93,6 +464,7 @@ pub async fn verify(
     verification
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn resend_confirmation_code(
     client: Arc<Client>,
     client_id: String,
@@ -113,6 +485,7 @@ pub async fn resend_confirmation_code(
     resend_code
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn initiate(
     client: Arc<Client>,
     client_id: String,
@@ -140,6 +513,7 @@ pub async fn initiate(
     auth
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn forgot_password(
     client: Arc<Client>,
     client_id: String,
@@ -160,6 +534,7 @@ pub async fn forgot_password(
     forgot_password
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn confirm_forgot_password(
     client: Arc<Client>,
     client_id: String,
@@ -184,6 +559,7 @@ pub async fn confirm_forgot_password(
     confirm_forgot_password
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn refresh(
     client: Arc<Client>,
     client_id: String,
@@ -209,3 +585,191 @@ pub async fn refresh(
 
     auth
 }
+
+#[cfg(not(feature = "mocks"))]
+pub async fn respond_to_auth_challenge(
+    client: Arc<Client>,
+    client_id: String,
+    secret_key: String,
+    username: String,
+    challenge_name: String,
+    session: String,
+    responses: HashMap<String, String>,
+) -> Result<RespondToAuthChallengeOutput, SdkError<RespondToAuthChallengeError>> {
+    let message = format!("{}{}", username, client_id);
+    let secret_hash = base64_hmac(secret_key, message).expect("Could not accept secret key");
+
+    let mut challenge_responses = responses;
+    challenge_responses.insert("USERNAME".to_string(), username);
+    challenge_responses.insert("SECRET_HASH".to_string(), secret_hash);
+
+    let respond = client
+        .respond_to_auth_challenge()
+        .client_id(client_id)
+        .challenge_name(ChallengeNameType::from(challenge_name.as_str()))
+        .session(session)
+        .set_challenge_responses(Some(challenge_responses))
+        .send()
+        .await;
+
+    respond
+}
+
+#[cfg(not(feature = "mocks"))]
+pub async fn change_password(
+    client: Arc<Client>,
+    access_token: String,
+    previous_password: String,
+    proposed_password: String,
+) -> Result<ChangePasswordOutput, SdkError<ChangePasswordError>> {
+    let change_password = client
+        .change_password()
+        .access_token(access_token)
+        .previous_password(previous_password)
+        .proposed_password(proposed_password)
+        .send()
+        .await;
+
+    change_password
+}
+
+#[cfg(not(feature = "mocks"))]
+pub async fn global_sign_out(
+    client: Arc<Client>,
+    access_token: String,
+) -> Result<GlobalSignOutOutput, SdkError<GlobalSignOutError>> {
+    let sign_out = client
+        .global_sign_out()
+        .access_token(access_token)
+        .send()
+        .await;
+
+    sign_out
+}
+
+#[cfg(not(feature = "mocks"))]
+pub async fn revoke_token(
+    client: Arc<Client>,
+    client_id: String,
+    secret_key: String,
+    refresh_token: String,
+) -> Result<RevokeTokenOutput, SdkError<RevokeTokenError>> {
+    let revoke = client
+        .revoke_token()
+        .token(refresh_token)
+        .client_id(client_id)
+        .client_secret(secret_key)
+        .send()
+        .await;
+
+    revoke
+}
+
+#[cfg(not(feature = "mocks"))]
+pub async fn delete_user(
+    client: Arc<Client>,
+    access_token: String,
+) -> Result<DeleteUserOutput, SdkError<DeleteUserError>> {
+    let delete = client.delete_user().access_token(access_token).send().await;
+
+    delete
+}
+
+#[cfg(not(feature = "mocks"))]
+pub async fn associate_software_token(
+    client: Arc<Client>,
+    access_token: Option<String>,
+    session: Option<String>,
+) -> Result<AssociateSoftwareTokenOutput, SdkError<AssociateSoftwareTokenError>> {
+    let associate = client
+        .associate_software_token()
+        .set_access_token(access_token)
+        .set_session(session)
+        .send()
+        .await;
+
+    associate
+}
+
+#[cfg(not(feature = "mocks"))]
+pub async fn verify_software_token(
+    client: Arc<Client>,
+    access_token: Option<String>,
+    session: Option<String>,
+    code: String,
+    device_name: Option<String>,
+) -> Result<VerifySoftwareTokenOutput, SdkError<VerifySoftwareTokenError>> {
+    let verify = client
+        .verify_software_token()
+        .set_access_token(access_token)
+        .set_session(session)
+        .user_code(code)
+        .set_friendly_device_name(device_name)
+        .send()
+        .await;
+
+    verify
+}
+
+// make the authenticator app the user's preferred second factor
+#[cfg(not(feature = "mocks"))]
+pub async fn enable_software_token_mfa(
+    client: Arc<Client>,
+    access_token: String,
+) -> Result<SetUserMfaPreferenceOutput, SdkError<SetUserMFAPreferenceError>> {
+    let software_token_mfa_settings = SoftwareTokenMfaSettingsType::builder()
+        .enabled(true)
+        .preferred_mfa(true)
+        .build();
+
+    let preference = client
+        .set_user_mfa_preference()
+        .access_token(access_token)
+        .software_token_mfa_settings(software_token_mfa_settings)
+        .send()
+        .await;
+
+    preference
+}
+
+#[test]
+fn challenges_are_passed_back() {
+    let challenge = login_result(
+        None,
+        Some(ChallengeNameType::SoftwareTokenMfa),
+        Some(String::from("session")),
+        None,
+    )
+    .unwrap();
+    let json = serde_json::to_value(&challenge).unwrap();
+    assert_eq!(json["challenge_name"], "SOFTWARE_TOKEN_MFA");
+    assert_eq!(json["session"], "session");
+    assert!(json.get("id_token").is_none());
+
+    assert!(login_result(None, None, None, None).is_err());
+}
+
+#[test]
+fn cognito_errors_map_to_codes() {
+    use aws_sdk_cognitoidentityprovider::error::{
+        UserNotConfirmedException, UsernameExistsException,
+    };
+
+    let error = auth_error(CognitoServiceError::UserNotConfirmedException(
+        UserNotConfirmedException::builder()
+            .message("User is not confirmed.")
+            .build(),
+    ));
+    assert_eq!(error.code, AuthErrorCode::UserNotConfirmed);
+    assert_eq!(error.code.status().as_u16(), 403);
+    assert_eq!(
+        error.cause,
+        "UserNotConfirmedException: User is not confirmed."
+    );
+
+    let error = auth_error(CognitoServiceError::UsernameExistsException(
+        UsernameExistsException::builder().build(),
+    ));
+    assert_eq!(error.code.as_str(), "USERNAME_EXISTS");
+    assert_eq!(error.code.status().as_u16(), 409);
+}
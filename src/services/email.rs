@@ -1,13 +1,21 @@
 use crate::{
     config::Config,
-    error::{OstrichError, OstrichErrorType},
+    error::OstrichError,
     models::emailer::Emailer,
     utils::{format_optional_float, format_optional_string},
 };
-use sendgrid_async::{Address, Client, Content, Message, Personalization};
+use sendgrid_async::Client;
 use std::sync::Arc;
 use warp::{filters::BoxedFilter, Filter};
 
+#[cfg(not(feature = "mocks"))]
+use crate::error::OstrichErrorType;
+#[cfg(not(feature = "mocks"))]
+use sendgrid_async::{Address, Content, Message, Personalization};
+
+#[cfg(feature = "mocks")]
+pub use crate::services::mocks::email::send_email;
+
 pub fn get_email_client(config: Arc<Config>) -> Client {
     let api_key = config.email.api_key.clone();
 
@@ -19,6 +27,7 @@ pub fn with_email(email: Arc<Client>) -> BoxedFilter<(Arc<Client>,)> {
     warp::any().map(move || email.clone()).boxed()
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn send_email(
     client: &Client,
     from: &str,
@@ -55,6 +64,12 @@ pub async fn send_email(
     Ok(())
 }
 
+pub const EMPTY_LISTINGS_BODY: &str = "Looks like not much showed on the market yesterday!";
+
+pub fn listings_email_subject(search_param: &str) -> String {
+    format!("New Ostrich Listings: {}", search_param)
+}
+
 pub async fn send_zillow_listings_email(
     client: &Client,
     config: Arc<Config>,
@@ -63,7 +78,7 @@ pub async fn send_zillow_listings_email(
     search_param: &str,
 ) -> Result<(), OstrichError> {
     let from = config.email.from.clone();
-    let subject = format!("New Ostrich Listings: {}", search_param);
+    let subject = listings_email_subject(search_param);
     send_email(client, &from, to, &subject, body).await
 }
 
@@ -74,9 +89,8 @@ pub async fn send_empty_zillow_listings_email(
     search_param: &str,
 ) -> Result<(), OstrichError> {
     let from = config.email.from.clone();
-    let subject = format!("New Ostrich Listings: {}", search_param);
-    let body = "Looks like not much showed on the market yesterday!";
-    send_email(client, &from, to, &subject, body).await
+    let subject = listings_email_subject(search_param);
+    send_email(client, &from, to, &subject, EMPTY_LISTINGS_BODY).await
 }
 
 pub fn get_ostrich_email_body(emailer: &Emailer) -> String {
@@ -116,3 +130,27 @@ pub async fn email_admin_on_tier_change(
     );
     send_email(client, &from, &to, &subject, &body).await
 }
+
+pub async fn send_confirmation_code_email(
+    client: &Client,
+    config: Arc<Config>,
+    to: &str,
+    code: &str,
+) -> Result<(), OstrichError> {
+    let from = config.email.from.clone();
+    let subject = "Your Ostrich verification code";
+    let body = format!("<p>Your verification code is <b>{}</b></p>", code);
+    send_email(client, &from, to, subject, &body).await
+}
+
+pub async fn send_password_reset_code_email(
+    client: &Client,
+    config: Arc<Config>,
+    to: &str,
+    code: &str,
+) -> Result<(), OstrichError> {
+    let from = config.email.from.clone();
+    let subject = "Reset your Ostrich password";
+    let body = format!("<p>Your password reset code is <b>{}</b></p>", code);
+    send_email(client, &from, to, subject, &body).await
+}
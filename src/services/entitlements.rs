@@ -0,0 +1,315 @@
+use crate::{
+    handlers::auth::{reject_with_auth_error, AuthErrorCode},
+    models::{
+        api_usage,
+        emailer::{self, Emailer, NewEmailer},
+        plan::{self, Plan, FREE_PLAN},
+        user::{self, User},
+    },
+    services::listing_source::ListingSource,
+    utils::now,
+};
+use chrono::{naive::NaiveDateTime, Datelike, NaiveDate};
+use diesel::prelude::*;
+use serde::Serialize;
+
+pub const MAX_EMAILERS: &str = "max_emailers";
+pub const MONTHLY_API_QUOTA: &str = "monthly_api_quota";
+
+// the rejection for going over what the plan pays for, turned into a 402 by handle_rejection
+#[derive(Debug, Serialize)]
+pub struct PlanLimitExceeded {
+    pub plan: String,
+    pub limit: &'static str,
+    pub allowed: i64,
+    pub used: i64,
+}
+
+impl warp::reject::Reject for PlanLimitExceeded {}
+
+// the rejection for a frequency the plan doesn't include, turned into a 403 by handle_rejection
+#[derive(Debug)]
+pub struct FrequencyNotAllowed {
+    pub plan: String,
+    pub frequency: String,
+    pub allowed: Vec<String>,
+}
+
+impl warp::reject::Reject for FrequencyNotAllowed {}
+
+pub fn get_free_plan(conn: &PgConnection) -> Plan {
+    plan::get_by_name(conn, FREE_PLAN)
+        .first()
+        .cloned()
+        .expect("The free plan is missing, did the plans migration run?")
+}
+
+pub fn get_plan(conn: &PgConnection, user: &User) -> Plan {
+    plan::get_by_id(conn, user.plan_id)
+        .first()
+        .cloned()
+        .unwrap_or_else(|| get_free_plan(conn))
+}
+
+// the plan a stripe product buys, products we don't know about get the free plan
+pub fn get_plan_for_product(conn: &PgConnection, product_name: &str) -> Plan {
+    plan::get_by_stripe_product_name(conn, product_name)
+        .first()
+        .cloned()
+        .unwrap_or_else(|| {
+            log::warn!("No plan for stripe product {}", product_name);
+            get_free_plan(conn)
+        })
+}
+
+// frequency has always been free text, so "Daily" still counts as daily
+pub fn allows_frequency(plan: &Plan, frequency: &str) -> bool {
+    plan.allowed_frequencies
+        .iter()
+        .any(|allowed| allowed.eq_ignore_ascii_case(frequency.trim()))
+}
+
+pub fn emailer_limit(plan: &Plan) -> usize {
+    plan.max_emailers.max(0) as usize
+}
+
+pub fn max_listings_per_email(plan: &Plan) -> usize {
+    plan.max_listings_per_email.max(0) as usize
+}
+
+// api usage is counted by the calendar month in utc
+pub fn usage_month(at: NaiveDateTime) -> NaiveDate {
+    NaiveDate::from_ymd(at.year(), at.month(), 1)
+}
+
+pub fn api_calls_used(conn: &PgConnection, user_id: i32) -> QueryResult<i64> {
+    api_usage::calls_in_month(conn, user_id, usage_month(now()))
+}
+
+// whether another search fits in the plan's quota, given the calls already made this month
+pub fn check_api_quota(plan: &Plan, used: i64) -> Result<(), PlanLimitExceeded> {
+    let allowed = plan.monthly_api_quota.max(0) as i64;
+    if used < allowed {
+        Ok(())
+    } else {
+        Err(PlanLimitExceeded {
+            plan: plan.name.clone(),
+            limit: MONTHLY_API_QUOTA,
+            allowed,
+            used,
+        })
+    }
+}
+
+// sources that call a paid api only search while the user has quota left this month
+pub fn check_source_quota(
+    conn: &PgConnection,
+    user_id: i32,
+    plan: &Plan,
+    source: &dyn ListingSource,
+) -> QueryResult<Result<(), PlanLimitExceeded>> {
+    if source.api_calls().is_none() {
+        return Ok(Ok(()));
+    }
+    api_calls_used(conn, user_id).map(|used| check_api_quota(plan, used))
+}
+
+// adds what a search cost to the user's month, a failure to is only logged so the search
+// it was for still counts
+pub fn record_api_calls(conn: &PgConnection, user_id: i32, source: &dyn ListingSource) {
+    let calls = match source.api_calls() {
+        Some(calls) if calls > 0 => calls,
+        _ => return,
+    };
+    if let Err(e) = api_usage::record(conn, user_id, usage_month(now()), calls as i32) {
+        log::error!(
+            "Could not record {} api calls for user {}: {}",
+            calls,
+            user_id,
+            e
+        );
+    }
+}
+
+pub fn check_frequency(plan: &Plan, frequency: &str) -> Result<(), FrequencyNotAllowed> {
+    if allows_frequency(plan, frequency) {
+        Ok(())
+    } else {
+        Err(FrequencyNotAllowed {
+            plan: plan.name.clone(),
+            frequency: String::from(frequency),
+            allowed: plan.allowed_frequencies.clone(),
+        })
+    }
+}
+
+// whether one more emailer fits, given how many are already in use
+pub fn check_emailer_limit(plan: &Plan, used: i64) -> Result<(), PlanLimitExceeded> {
+    let allowed = emailer_limit(plan) as i64;
+    if used < allowed {
+        Ok(())
+    } else {
+        Err(PlanLimitExceeded {
+            plan: plan.name.clone(),
+            limit: MAX_EMAILERS,
+            allowed,
+            used,
+        })
+    }
+}
+
+// inserts the emailer if the plan has room for it, the user's row stays locked while we
+// count so two requests can't both take the last slot
+pub fn create_emailer(
+    conn: &PgConnection,
+    user_id: i32,
+    frequency: &str,
+    new_emailer: &NewEmailer,
+) -> Result<Emailer, warp::Rejection> {
+    let result = conn.transaction::<_, diesel::result::Error, _>(|| {
+        let user = user::lock_by_id(conn, user_id)?;
+        let plan = get_plan(conn, &user);
+        if let Err(e) = check_frequency(&plan, frequency) {
+            return Ok(Err(warp::reject::custom(e)));
+        }
+
+        let used = emailer::count_by_user_id(conn, user.id)?;
+        if let Err(e) = check_emailer_limit(&plan, used) {
+            log::warn!(
+                "User {} is at their limit of {} emailers",
+                user.id,
+                e.allowed
+            );
+            return Ok(Err(warp::reject::custom(e)));
+        }
+
+        new_emailer.try_insert(conn).map(Ok)
+    });
+
+    result.unwrap_or_else(|e| {
+        log::error!("Could not create emailer for user {}: {}", user_id, e);
+        Err(reject_with_auth_error(
+            AuthErrorCode::ServerError,
+            "Could not create emailer",
+        ))
+    })
+}
+
+// edits have to stay inside the plan too, and after a downgrade only the emailers that
+// still run can be changed
+pub fn check_emailer_update(
+    conn: &PgConnection,
+    user: &User,
+    emailer_id: i32,
+    frequency: &str,
+) -> Result<(), warp::Rejection> {
+    let plan = get_plan(conn, user);
+    check_frequency(&plan, frequency).map_err(warp::reject::custom)?;
+
+    let emailers = emailer::read_by_user_id(conn, user.id);
+    let used = emailers.len() as i64;
+    let is_entitled = take_entitled(emailers, &plan)
+        .iter()
+        .any(|emailer| emailer.id == emailer_id);
+    if is_entitled || used <= emailer_limit(&plan) as i64 {
+        return Ok(());
+    }
+
+    Err(warp::reject::custom(PlanLimitExceeded {
+        plan: plan.name.clone(),
+        limit: MAX_EMAILERS,
+        allowed: emailer_limit(&plan) as i64,
+        used,
+    }))
+}
+
+// the user's oldest emailers, up to what their plan pays for
+pub fn entitled_emailers(conn: &PgConnection, user: &User, plan: &Plan) -> Vec<Emailer> {
+    take_entitled(emailer::read_by_user_id(conn, user.id), plan)
+}
+
+pub fn take_entitled(emailers: Vec<Emailer>, plan: &Plan) -> Vec<Emailer> {
+    emailers
+        .into_iter()
+        .filter(|emailer| allows_frequency(plan, &emailer.frequency))
+        .take(emailer_limit(plan))
+        .collect()
+}
+
+#[test]
+fn entitled_emailers_never_exceed_the_plan() {
+    let plan = |max_emailers| Plan {
+        id: 0,
+        name: String::from("test"),
+        stripe_product_name: None,
+        max_emailers,
+        allowed_frequencies: vec![String::from("daily")],
+        max_listings_per_email: 10,
+        monthly_api_quota: 100,
+        created_at: crate::utils::now(),
+        updated_at: None,
+    };
+    let emailer = |id, frequency: &str| Emailer {
+        id,
+        search_param: String::from("Astoria, NY"),
+        email: String::from("mocks@ostrich.so"),
+        frequency: String::from(frequency),
+        max_price: None,
+        min_price: None,
+        no_bedrooms: None,
+        insurance: 0.0,
+        vacancy: 0.0,
+        property_management: 0.0,
+        capex: 0.0,
+        repairs: 0.0,
+        utilities: 0.0,
+        down_payment: 0.0,
+        closing_cost: 0.0,
+        loan_interest: 0.0,
+        loan_months: 0.0,
+        additional_monthly_expenses: 0.0,
+        created_at: crate::utils::now(),
+        updated_at: None,
+        deleted_at: None,
+        active: true,
+        user_id: 0,
+        notes: None,
+        no_bathrooms: None,
+        next_run_at: crate::utils::now(),
+        last_run_at: None,
+        version: 1,
+        listing_source: String::from("zillow"),
+    };
+
+    // fewer emailers than the plan allows used to panic
+    assert_eq!(take_entitled(vec![emailer(1, "daily")], &plan(3)).len(), 1);
+
+    let emailers = vec![
+        emailer(1, "Daily"),
+        emailer(2, "hourly"),
+        emailer(3, "daily"),
+        emailer(4, "daily"),
+    ];
+    let ids = take_entitled(emailers, &plan(2))
+        .iter()
+        .map(|emailer| emailer.id)
+        .collect::<Vec<i32>>();
+    assert_eq!(ids, vec![1, 3]);
+
+    assert!(take_entitled(vec![emailer(1, "daily")], &plan(0)).is_empty());
+
+    assert!(check_emailer_limit(&plan(2), 1).is_ok());
+    let exceeded = check_emailer_limit(&plan(2), 2).unwrap_err();
+    assert_eq!((exceeded.allowed, exceeded.used), (2, 2));
+    assert!(check_frequency(&plan(2), " DAILY").is_ok());
+    assert!(check_frequency(&plan(2), "hourly").is_err());
+
+    // the plan allows 100 calls a month
+    assert!(check_api_quota(&plan(2), 99).is_ok());
+    let exceeded = check_api_quota(&plan(2), 100).unwrap_err();
+    assert_eq!(exceeded.limit, MONTHLY_API_QUOTA);
+    assert_eq!(
+        usage_month(NaiveDate::from_ymd(2026, 10, 18).and_hms(23, 59, 0)),
+        NaiveDate::from_ymd(2026, 10, 1)
+    );
+}
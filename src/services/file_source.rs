@@ -0,0 +1,243 @@
+use crate::{
+    error::{OstrichError, OstrichErrorType},
+    services::listing_source::{
+        Listing, ListingPages, ListingSource, PropertyDetails, SearchParameters,
+    },
+};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+// one property in an export, a JSON array of these or a CSV with them as its header
+#[derive(Deserialize, Debug, Clone, Default)]
+struct FileListing {
+    id: String,
+    street_address: Option<String>,
+    city: Option<String>,
+    state: Option<String>,
+    zipcode: Option<String>,
+    bedrooms: Option<i32>,
+    bathrooms: Option<i32>,
+    price: Option<f64>,
+    tax_rate: Option<f64>,
+    rent_estimate: Option<f64>,
+    days_on_market: Option<i32>,
+    img_src: Option<String>,
+    url: Option<String>,
+}
+
+impl FileListing {
+    fn address(&self) -> String {
+        let part = |part: &Option<String>| part.clone().unwrap_or_default();
+        format!(
+            "{}, {}, {} {}",
+            part(&self.street_address),
+            part(&self.city),
+            part(&self.state),
+            part(&self.zipcode)
+        )
+    }
+
+    // the filters zillow would apply, a listing missing what is filtered on is left out
+    fn matches(&self, search: &SearchParameters) -> bool {
+        let address = self.address().to_lowercase();
+        let within = |value: Option<f64>, min: Option<f64>, max: Option<f64>| match value {
+            _ if min.is_none() && max.is_none() => true,
+            Some(value) => min.unwrap_or(f64::MIN) <= value && value <= max.unwrap_or(f64::MAX),
+            None => false,
+        };
+        search
+            .search_param
+            .split(',')
+            .map(|part| part.trim().to_lowercase())
+            .all(|part| address.contains(&part))
+            && within(self.price, search.min_price, search.max_price)
+            && within(
+                self.bedrooms.map(f64::from),
+                search.no_bedrooms.map(f64::from),
+                None,
+            )
+            && within(
+                self.bathrooms.map(f64::from),
+                search.no_bathrooms.map(f64::from),
+                None,
+            )
+            && match (self.days_on_market, search.days_on_market) {
+                (Some(days), Some(max_days)) => days <= max_days,
+                _ => true,
+            }
+    }
+
+    fn listing(&self) -> Listing {
+        Listing {
+            id: self.id.clone(),
+            address: Some(self.address()),
+            price: self.price,
+        }
+    }
+
+    fn details(&self) -> PropertyDetails {
+        PropertyDetails {
+            id: self.id.clone(),
+            street_address: self.street_address.clone(),
+            city: self.city.clone(),
+            state: self.state.clone(),
+            zipcode: self.zipcode.clone(),
+            bedrooms: self.bedrooms,
+            bathrooms: self.bathrooms,
+            price: self.price,
+            tax_rate: self.tax_rate,
+            rent_estimate: self.rent_estimate,
+            time_on_market: self.days_on_market.map(|days| match days {
+                1 => String::from("1 day"),
+                days => format!("{} days", days),
+            }),
+            img_src: self.img_src.clone(),
+            url: self.url.clone(),
+        }
+    }
+}
+
+fn csv_listings(text: &str) -> Result<Vec<FileListing>, String> {
+    csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(text.as_bytes())
+        .deserialize::<FileListing>()
+        .map(|listing| {
+            let listing = listing.map_err(|e| e.to_string())?;
+            match listing.id.is_empty() {
+                true => Err(String::from("a listing has no id")),
+                false => Ok(listing),
+            }
+        })
+        .collect()
+}
+
+// an export on disk, read again on every search so it can be swapped out between runs
+pub struct FileSource {
+    name: String,
+    path: PathBuf,
+    // what the last search found, so details don't have to read the file again
+    found: Mutex<HashMap<String, FileListing>>,
+}
+
+impl FileSource {
+    pub fn new(dir: &Path, name: &str) -> Self {
+        FileSource {
+            name: String::from(name),
+            path: dir.join(name),
+            found: Mutex::new(HashMap::new()),
+        }
+    }
+
+    async fn read(&self) -> Result<Vec<FileListing>, OstrichError> {
+        let unreadable = |e: String| {
+            OstrichError::new(
+                format!("Could not read listings from {}: {}", self.name, e),
+                OstrichErrorType::ApiError,
+            )
+        };
+        let text = tokio::fs::read_to_string(&self.path)
+            .await
+            .map_err(|e| unreadable(e.to_string()))?;
+        if self.name.ends_with(".csv") {
+            csv_listings(&text).map_err(unreadable)
+        } else {
+            serde_json::from_str(&text).map_err(|e| unreadable(e.to_string()))
+        }
+    }
+}
+
+#[async_trait]
+impl ListingSource for FileSource {
+    fn search_url(&self, search: &SearchParameters) -> String {
+        format!("file:{}?location={}", self.name, search.search_param)
+    }
+
+    async fn search(&self, search: &SearchParameters) -> Result<ListingPages, OstrichError> {
+        let found = self
+            .read()
+            .await?
+            .into_iter()
+            .filter(|listing| listing.matches(search))
+            .collect::<Vec<FileListing>>();
+        if found.is_empty() {
+            return Err(OstrichError::new(
+                format!("Nothing in {} matches {}", self.name, search.search_param),
+                OstrichErrorType::ListingResultError,
+            ));
+        }
+
+        let listings = found
+            .iter()
+            .map(|listing| Ok(listing.listing()))
+            .collect::<Vec<_>>();
+        let mut found_by_id = self.found.lock().unwrap();
+        *found_by_id = found
+            .into_iter()
+            .map(|listing| (listing.id.clone(), listing))
+            .collect();
+
+        Ok(ListingPages {
+            total_results: Some(listings.len() as i64),
+            listings: Box::pin(tokio_stream::iter(listings)),
+        })
+    }
+
+    async fn details(&self, listing: &Listing) -> Result<PropertyDetails, OstrichError> {
+        self.found
+            .lock()
+            .unwrap()
+            .get(&listing.id)
+            .map(FileListing::details)
+            .ok_or_else(|| {
+                OstrichError::new(
+                    format!("{} has no property {}", self.name, listing.id),
+                    OstrichErrorType::PropertyResultError,
+                )
+            })
+    }
+}
+
+#[test]
+fn csv_exports_are_searched_like_zillow() {
+    let listings = csv_listings(
+        "id,street_address,city,state,zipcode,price,bedrooms,days_on_market\n\
+         1,\"23-15 31st St, Unit 2\",Astoria,NY,11105,899000,3,1\n\
+         2,30-42 36th St,Astoria,NY,11103,650000,2,12\n\
+         \n\
+         3,\"The \"\"Big\"\" House\",Austin,TX,78701,,4,\n",
+    )
+    .unwrap();
+    assert_eq!(listings.len(), 3);
+    assert_eq!(
+        listings[0].street_address.as_deref(),
+        Some("23-15 31st St, Unit 2")
+    );
+    assert_eq!(
+        listings[2].street_address.as_deref(),
+        Some("The \"Big\" House")
+    );
+    assert_eq!(listings[2].price, None);
+
+    let search = SearchParameters {
+        search_param: String::from("astoria, ny"),
+        max_price: Some(900000.0),
+        min_price: None,
+        no_bedrooms: Some(2),
+        no_bathrooms: None,
+        days_on_market: Some(7),
+    };
+    let ids = listings
+        .iter()
+        .filter(|listing| listing.matches(&search))
+        .map(|listing| listing.id.as_str())
+        .collect::<Vec<&str>>();
+    assert_eq!(ids, vec!["1"]);
+
+    assert!(csv_listings("id,price\n1,cheap\n").is_err());
+}
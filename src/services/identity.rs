@@ -0,0 +1,111 @@
+use crate::{
+    config::{Config, IdentityProviderKind},
+    db_conn::DbConn,
+    handlers::auth::{
+        AuthError, AuthenticationDetails, LoginResult, SoftwareTokenAssociation,
+        SoftwareTokenVerification,
+    },
+    services::{
+        cognito::{self, CognitoIdentityProvider},
+        jwt::AccessToken,
+        local_identity::LocalIdentityProvider,
+    },
+};
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Arc};
+use warp::{filters::BoxedFilter, Filter};
+
+// everything the auth service needs from whoever owns our user accounts
+#[async_trait]
+pub trait IdentityProvider: Send + Sync {
+    // returns the sub of the new account
+    async fn sign_up(
+        &self,
+        username: String,
+        password: String,
+        email: String,
+    ) -> Result<String, AuthError>;
+
+    async fn verify(&self, username: String, code: String) -> Result<(), AuthError>;
+
+    async fn initiate(&self, username: String, password: String) -> Result<LoginResult, AuthError>;
+
+    async fn respond_to_challenge(
+        &self,
+        username: String,
+        challenge_name: String,
+        session: String,
+        responses: HashMap<String, String>,
+    ) -> Result<LoginResult, AuthError>;
+
+    async fn refresh(
+        &self,
+        username: String,
+        refresh_token: String,
+    ) -> Result<AuthenticationDetails, AuthError>;
+
+    async fn resend_confirmation_code(&self, username: String) -> Result<(), AuthError>;
+
+    async fn forgot_password(&self, username: String) -> Result<(), AuthError>;
+
+    async fn confirm_forgot_password(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<(), AuthError>;
+
+    async fn change_password(
+        &self,
+        access_token: &AccessToken,
+        previous_password: String,
+        proposed_password: String,
+    ) -> Result<(), AuthError>;
+
+    // signs the user out of every device by invalidating their refresh tokens
+    async fn global_sign_out(&self, access_token: &AccessToken) -> Result<(), AuthError>;
+
+    async fn revoke_refresh_token(&self, refresh_token: String) -> Result<(), AuthError>;
+
+    async fn delete_user(&self, access_token: &AccessToken) -> Result<(), AuthError>;
+
+    // totp enrollment, with either a logged in user's access token
+    // or the session from an MFA_SETUP challenge
+    async fn associate_software_token(
+        &self,
+        access_token: Option<String>,
+        session: Option<String>,
+    ) -> Result<SoftwareTokenAssociation, AuthError>;
+
+    async fn verify_software_token(
+        &self,
+        access_token: Option<String>,
+        session: Option<String>,
+        code: String,
+        device_name: Option<String>,
+    ) -> Result<SoftwareTokenVerification, AuthError>;
+}
+
+pub fn with_identity_provider(
+    identity_provider: Arc<dyn IdentityProvider>,
+) -> BoxedFilter<(Arc<dyn IdentityProvider>,)> {
+    warp::any().map(move || identity_provider.clone()).boxed()
+}
+
+// pick the provider from IDENTITY_PROVIDER
+pub async fn get_identity_provider(
+    config: Arc<Config>,
+    db_conn: Arc<DbConn>,
+) -> Arc<dyn IdentityProvider> {
+    match config.identity_provider {
+        IdentityProviderKind::Cognito => {
+            log::info!("🔑 Using Cognito for identity");
+            let client = Arc::new(cognito::get_cognito_client(config.aws_region.clone()).await);
+            Arc::new(CognitoIdentityProvider::new(config, client))
+        }
+        IdentityProviderKind::Local => {
+            log::info!("🔑 Using the local database for identity");
+            Arc::new(LocalIdentityProvider::new(config, db_conn))
+        }
+    }
+}
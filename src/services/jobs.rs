@@ -0,0 +1,574 @@
+use crate::{
+    config::{Config, JobsConfig},
+    db_conn::DbConn,
+    error::{OstrichError, OstrichErrorType},
+    models::{
+        emailer,
+        emailer_run::{self, NewEmailerRun},
+        emailer_run_window,
+        job::{self, Job, NewJob},
+        listing_data::NewListingData,
+        user,
+    },
+    services::{
+        email, entitlements,
+        listing_source::{self, ListingEmail, SearchParameters},
+        metrics::{self, Metrics},
+        schedule, scheduler, validation,
+        zillow::ZillowClient,
+    },
+    utils::now,
+};
+use chrono::{naive::NaiveDateTime, Duration};
+use diesel::prelude::*;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::watch;
+use uuid::Uuid;
+
+pub const RUN_EMAILER: &str = "run_emailer";
+pub const SEND_EMAIL: &str = "send_email";
+
+// the longest a failing job waits between attempts
+const MAX_BACKOFF_SECONDS: i64 = 3600;
+
+// search zillow for one emailer and queue up the email it makes, once per run window
+// unless forced, in which case listings it already sent go out again too. jobs queued
+// before run windows existed don't have one, so it is worked out when they run
+#[derive(Serialize, Deserialize)]
+pub struct RunEmailer {
+    pub emailer_id: i32,
+    #[serde(default)]
+    pub run_window: Option<NaiveDateTime>,
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SendEmail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+    // the run this email came out of, if any
+    #[serde(default)]
+    pub emailer_run_id: Option<i64>,
+    // the listings in it, only saved as sent once it goes out
+    #[serde(default)]
+    pub listings: Option<EmailedListings>,
+}
+
+// listings leave out who they belong to when they are serialized
+#[derive(Serialize, Deserialize)]
+pub struct EmailedListings {
+    pub emailer_id: i32,
+    pub user_id: i32,
+    pub listings: Vec<NewListingData>,
+}
+
+pub fn enqueue<T: Serialize>(
+    conn: &PgConnection,
+    config: &JobsConfig,
+    kind: &str,
+    payload: &T,
+) -> QueryResult<Job> {
+    let payload = serde_json::to_value(payload).expect("Error serializing job payload");
+    NewJob::new(kind, payload, config.max_attempts as i32).insert(conn)
+}
+
+// doubles after every failed attempt, up to an hour
+pub fn backoff(base_seconds: i64, attempts: i32) -> Duration {
+    let exponent = (attempts.max(1) - 1).min(16) as u32;
+    let seconds = base_seconds.max(1).saturating_mul(2i64.pow(exponent));
+    Duration::seconds(seconds.min(MAX_BACKOFF_SECONDS))
+}
+
+// why a job didn't go through, and whether trying it again could help
+enum JobError {
+    Retry(String),
+    Fatal(String),
+}
+
+fn parse_payload<T: DeserializeOwned>(job: &Job) -> Result<T, JobError> {
+    serde_json::from_value(job.payload.clone())
+        .map_err(|e| JobError::Fatal(format!("Bad {} payload: {}", job.kind, e)))
+}
+
+fn describe(e: &OstrichError) -> String {
+    format!("{:?}: {}", e.etype, e.details)
+}
+
+#[derive(Default)]
+pub struct WorkerMetrics {
+    jobs_claimed: AtomicU64,
+    jobs_done: AtomicU64,
+    jobs_retried: AtomicU64,
+    jobs_dead: AtomicU64,
+    emails_sent: AtomicU64,
+    empty_results: AtomicU64,
+    invalid_emailers: AtomicU64,
+    outages: AtomicU64,
+    over_quota: AtomicU64,
+    jobs_running: AtomicI64,
+}
+
+impl Metrics for WorkerMetrics {
+    fn render(&self) -> String {
+        metrics::render(
+            "ostrich_worker",
+            &[
+                (
+                    "jobs_claimed_total",
+                    self.jobs_claimed.load(Ordering::Relaxed),
+                ),
+                ("jobs_done_total", self.jobs_done.load(Ordering::Relaxed)),
+                (
+                    "jobs_retried_total",
+                    self.jobs_retried.load(Ordering::Relaxed),
+                ),
+                ("jobs_dead_total", self.jobs_dead.load(Ordering::Relaxed)),
+                (
+                    "emails_sent_total",
+                    self.emails_sent.load(Ordering::Relaxed),
+                ),
+                (
+                    "empty_results_total",
+                    self.empty_results.load(Ordering::Relaxed),
+                ),
+                (
+                    "invalid_emailers_total",
+                    self.invalid_emailers.load(Ordering::Relaxed),
+                ),
+                ("outages_total", self.outages.load(Ordering::Relaxed)),
+                ("over_quota_total", self.over_quota.load(Ordering::Relaxed)),
+            ],
+            &[("jobs_running", self.jobs_running.load(Ordering::Relaxed))],
+        )
+    }
+}
+
+// takes jobs off the queue one at a time, run as many as you like side by side
+pub struct Worker {
+    name: String,
+    config: Arc<Config>,
+    db_conn: Arc<DbConn>,
+    zillow_client: Arc<ZillowClient>,
+    email_client: Arc<sendgrid_async::Client>,
+    metrics: Arc<WorkerMetrics>,
+}
+
+impl Worker {
+    pub fn new(
+        config: Arc<Config>,
+        db_conn: Arc<DbConn>,
+        zillow_client: Arc<ZillowClient>,
+        email_client: Arc<sendgrid_async::Client>,
+    ) -> Self {
+        Worker {
+            name: format!("worker-{}", Uuid::new_v4()),
+            config,
+            db_conn,
+            zillow_client,
+            email_client,
+            metrics: Arc::new(WorkerMetrics::default()),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<WorkerMetrics> {
+        self.metrics.clone()
+    }
+
+    // works until shutdown is asked for, which is only checked between jobs so an email
+    // that is being put together always makes it onto the queue
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) {
+        log::info!("{} is taking jobs", self.name);
+        let poll = std::time::Duration::from_secs(self.config.jobs.poll_seconds.max(1) as u64);
+        while !*shutdown.borrow() {
+            if !self.work_one().await {
+                tokio::select! {
+                    _ = tokio::time::sleep(poll) => {}
+                    _ = shutdown.changed() => {}
+                }
+            }
+        }
+        log::info!("{} stopped", self.name);
+    }
+
+    // works until nothing is ready, for one off runs
+    pub async fn drain(&self) -> usize {
+        let mut worked = 0;
+        while self.work_one().await {
+            worked += 1;
+        }
+        worked
+    }
+
+    // claims and runs a single job, false when there was nothing ready
+    pub async fn work_one(&self) -> bool {
+        let now = now();
+        let visible_until = now + Duration::seconds(self.config.jobs.visibility_timeout_seconds);
+        let claimed = {
+            let conn = self.db_conn.get_conn();
+            match job::bury_abandoned(&conn, now) {
+                Ok(0) => (),
+                Ok(buried) => {
+                    log::error!("{} jobs timed out on their last attempt", buried);
+                    self.metrics
+                        .jobs_dead
+                        .fetch_add(buried as u64, Ordering::Relaxed);
+                }
+                Err(e) => log::error!("Could not sweep abandoned jobs: {}", e),
+            }
+            job::claim(&conn, &self.name, now, visible_until, 1)
+        };
+
+        let job = match claimed {
+            Ok(jobs) => match jobs.into_iter().next() {
+                Some(job) => job,
+                None => return false,
+            },
+            Err(e) => {
+                log::error!("Could not claim a job: {}", e);
+                return false;
+            }
+        };
+
+        self.metrics.jobs_claimed.fetch_add(1, Ordering::Relaxed);
+        self.metrics.jobs_running.fetch_add(1, Ordering::Relaxed);
+        log::info!(
+            "Running {} job {}, attempt {} of {}",
+            job.kind,
+            job.id,
+            job.attempts,
+            job.max_attempts
+        );
+
+        let result = self.perform(&job).await;
+        let conn = self.db_conn.get_conn();
+        let finished = match result {
+            Ok(()) => {
+                self.metrics.jobs_done.fetch_add(1, Ordering::Relaxed);
+                job::complete(&conn, job.id, &self.name)
+            }
+            Err(JobError::Retry(e)) if job.attempts < job.max_attempts => {
+                let run_at =
+                    crate::utils::now() + backoff(self.config.jobs.backoff_seconds, job.attempts);
+                log::warn!("Job {} failed, retrying at {}: {}", job.id, run_at, e);
+                self.metrics.jobs_retried.fetch_add(1, Ordering::Relaxed);
+                job::retry(&conn, job.id, &self.name, run_at, &e)
+            }
+            Err(JobError::Retry(e)) | Err(JobError::Fatal(e)) => {
+                log::error!("Job {} is dead: {}", job.id, e);
+                self.metrics.jobs_dead.fetch_add(1, Ordering::Relaxed);
+                self.fail_run(&conn, &job, &e);
+                job::bury(&conn, job.id, &self.name, &e)
+            }
+        };
+
+        match finished {
+            Ok(0) => log::warn!("Job {} timed out and went to another worker", job.id),
+            Ok(_) => (),
+            Err(e) => log::error!("Could not finish job {}: {}", job.id, e),
+        }
+        self.metrics.jobs_running.fetch_sub(1, Ordering::Relaxed);
+        true
+    }
+
+    // an email that will never go out fails the run it came from
+    fn fail_run(&self, conn: &PgConnection, job: &Job, error: &str) {
+        if job.kind != SEND_EMAIL {
+            return;
+        }
+        let run_id = match parse_payload::<SendEmail>(job) {
+            Ok(SendEmail {
+                emailer_run_id: Some(run_id),
+                ..
+            }) => run_id,
+            _ => return,
+        };
+        if let Err(e) = emailer_run::finish(conn, run_id, emailer_run::FAILED, Some(error)) {
+            log::error!("Could not fail emailer run {}: {}", run_id, e);
+        }
+    }
+
+    async fn perform(&self, job: &Job) -> Result<(), JobError> {
+        match job.kind.as_str() {
+            RUN_EMAILER => self.run_emailer(parse_payload(job)?, job.created_at).await,
+            SEND_EMAIL => self.send_email(parse_payload(job)?).await,
+            other => Err(JobError::Fatal(format!("Unknown job kind {}", other))),
+        }
+    }
+
+    async fn run_emailer(&self, run: RunEmailer, queued_at: NaiveDateTime) -> Result<(), JobError> {
+        let (emailer, plan, run_window) = {
+            let conn = self.db_conn.get_conn();
+            let emailer = match emailer::get_by_id(&conn, run.emailer_id).first() {
+                Some(emailer) => emailer.clone(),
+                None => {
+                    log::info!("Emailer {} was deleted after it was queued", run.emailer_id);
+                    return Ok(());
+                }
+            };
+            let user = match user::get_user_by_id(&conn, emailer.user_id).first() {
+                Some(user) => user.clone(),
+                None => {
+                    log::info!("The owner of emailer {} is gone", emailer.id);
+                    return Ok(());
+                }
+            };
+            // retries move run_at, so an old job goes by when it was queued
+            let run_window = run.run_window.unwrap_or_else(|| {
+                scheduler::frequency(&emailer)
+                    .run_window(queued_at, schedule::time_zone(&user.time_zone))
+            });
+            let sent = emailer_run_window::exists(&conn, emailer.id, run_window)
+                .map_err(|e| JobError::Retry(e.to_string()))?;
+            if sent && !run.force {
+                log::info!(
+                    "Emailer {} already went out for {}, skipping",
+                    emailer.id,
+                    run_window
+                );
+                return Ok(());
+            }
+            (emailer, entitlements::get_plan(&conn, &user), run_window)
+        };
+
+        let now = now();
+        let frequency = scheduler::frequency(&emailer);
+        let days_on_market = schedule::days_on_market(frequency, emailer.last_run_at, now);
+        log::info!(
+            "Running {} search on {} for {} over the last {} days",
+            frequency.as_str(),
+            emailer.search_param,
+            emailer.email,
+            days_on_market
+        );
+
+        let mut search = SearchParameters::from(&emailer);
+        search.days_on_market = Some(days_on_market);
+        let source = listing_source::get_listing_source(
+            self.config.clone(),
+            self.zillow_client.clone(),
+            &emailer.listing_source,
+            emailer.user_id,
+        );
+        let search_url = match &source {
+            Ok(source) => source.search_url(&search),
+            Err(_) => emailer.listing_source.clone(),
+        };
+        let run_id = NewEmailerRun::new(emailer.id, run_window, search_url)
+            .insert(&self.db_conn.get_conn())
+            .map_err(|e| JobError::Retry(e.to_string()))?
+            .id;
+
+        // rows from before validation, or edited by hand, are flagged instead of run
+        let errors = validation::validate_emailer(&(&emailer).into());
+        if !errors.is_empty() {
+            let error = errors
+                .iter()
+                .map(|e| e.message.clone())
+                .collect::<Vec<String>>()
+                .join("; ");
+            log::warn!("Emailer {} is invalid: {}", emailer.id, error);
+            self.metrics
+                .invalid_emailers
+                .fetch_add(1, Ordering::Relaxed);
+            return emailer_run::finish(
+                &self.db_conn.get_conn(),
+                run_id,
+                emailer_run::INVALID,
+                Some(&error),
+            )
+            .map(|_| ())
+            .map_err(|e| JobError::Retry(e.to_string()));
+        }
+
+        // a user out of quota waits for next month, trying again sooner won't help
+        if let Ok(source) = &source {
+            let conn = self.db_conn.get_conn();
+            let quota =
+                entitlements::check_source_quota(&conn, emailer.user_id, &plan, source.as_ref())
+                    .map_err(|e| JobError::Retry(e.to_string()))?;
+            if let Err(e) = quota {
+                let error = format!(
+                    "{} of the {} plan's {} api calls used this month",
+                    e.used, e.plan, e.allowed
+                );
+                log::warn!("Emailer {} is over quota: {}", emailer.id, error);
+                self.metrics.over_quota.fetch_add(1, Ordering::Relaxed);
+                return emailer_run::finish(&conn, run_id, emailer_run::OVER_QUOTA, Some(&error))
+                    .map(|_| ())
+                    .map_err(|e| JobError::Retry(e.to_string()));
+            }
+        }
+
+        let already_sent = if run.force {
+            Ok(HashSet::new())
+        } else {
+            listing_source::sent_listings(&self.db_conn, &emailer)
+        };
+        let result = match (source, already_sent) {
+            (Ok(source), Ok(already_sent)) => {
+                let result = listing_source::get_listing_email_for_search_params(
+                    source.as_ref(),
+                    &emailer,
+                    email::get_ostrich_email_body(&emailer),
+                    &search,
+                    Some(entitlements::max_listings_per_email(&plan)),
+                    &already_sent,
+                )
+                .await;
+                entitlements::record_api_calls(
+                    &self.db_conn.get_conn(),
+                    emailer.user_id,
+                    source.as_ref(),
+                );
+                result
+            }
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        };
+
+        let (mut listing_email, search_error) = match result {
+            Ok(listing_email) => (listing_email, None),
+            // nothing new is still a run
+            Err(e) => match e.etype {
+                OstrichErrorType::ListingResultError => {
+                    self.metrics.empty_results.fetch_add(1, Ordering::Relaxed);
+                    let body = String::from(email::EMPTY_LISTINGS_BODY);
+                    (ListingEmail::new(body, Some(0)), Some(describe(&e)))
+                }
+                _ => {
+                    let error = describe(&e);
+                    let email_status = match e.etype {
+                        OstrichErrorType::OutageError => {
+                            self.metrics.outages.fetch_add(1, Ordering::Relaxed);
+                            emailer_run::OUTAGE
+                        }
+                        _ => emailer_run::FAILED,
+                    };
+                    let conn = self.db_conn.get_conn();
+                    if let Err(e) = emailer_run::finish(&conn, run_id, email_status, Some(&error)) {
+                        log::error!("Could not fail emailer run {}: {}", run_id, e);
+                    }
+                    return Err(JobError::Retry(error));
+                }
+            },
+        };
+
+        // the run window and the run are recorded along with the email, so neither a retry
+        // of this job nor another job for the same window can send it twice
+        let send_email = SendEmail {
+            to: emailer.email.clone(),
+            subject: email::listings_email_subject(&emailer.search_param),
+            body: listing_email.body.clone(),
+            emailer_run_id: Some(run_id),
+            listings: Some(EmailedListings {
+                emailer_id: emailer.id,
+                user_id: emailer.user_id,
+                listings: std::mem::take(&mut listing_email.listings),
+            }),
+        };
+        let conn = self.db_conn.get_conn();
+        let queued = conn
+            .transaction::<_, diesel::result::Error, _>(|| {
+                if emailer_run_window::record(&conn, emailer.id, run_window, run.force)? == 0 {
+                    return Ok(false);
+                }
+                emailer::mark_run(&conn, emailer.id, now)?;
+                enqueue(&conn, &self.config.jobs, SEND_EMAIL, &send_email)?;
+                emailer_run::record_search(&conn, run_id, &listing_email, search_error.as_deref())?;
+                Ok(true)
+            })
+            .map_err(|e| JobError::Retry(e.to_string()))?;
+        if !queued {
+            log::info!(
+                "Emailer {} went out for {} while this was running",
+                emailer.id,
+                run_window
+            );
+            emailer_run::finish(&conn, run_id, emailer_run::SKIPPED, None)
+                .map_err(|e| JobError::Retry(e.to_string()))?;
+        }
+        Ok(())
+    }
+
+    async fn send_email(&self, send_email: SendEmail) -> Result<(), JobError> {
+        let sent = email::send_email(
+            &self.email_client,
+            &self.config.email.from,
+            &send_email.to,
+            &send_email.subject,
+            &send_email.body,
+        )
+        .await;
+
+        let conn = self.db_conn.get_conn();
+        let error = match sent {
+            Ok(_) => {
+                self.metrics.emails_sent.fetch_add(1, Ordering::Relaxed);
+                let listings_written = send_email
+                    .listings
+                    .map_or(0, |listings| save_sent_listings(&conn, listings));
+                if let Some(run_id) = send_email.emailer_run_id {
+                    if let Err(e) =
+                        emailer_run::record_listings_written(&conn, run_id, listings_written)
+                    {
+                        log::error!("Could not update emailer run {}: {}", run_id, e);
+                    }
+                    // it went out, a failure here is no reason to send it again
+                    if let Err(e) = emailer_run::finish(&conn, run_id, emailer_run::SENT, None) {
+                        log::error!("Could not finish emailer run {}: {}", run_id, e);
+                    }
+                }
+                return Ok(());
+            }
+            Err(e) => describe(&e),
+        };
+        if let Some(run_id) = send_email.emailer_run_id {
+            if let Err(e) = emailer_run::record_error(&conn, run_id, &error) {
+                log::error!("Could not update emailer run {}: {}", run_id, e);
+            }
+        }
+        Err(JobError::Retry(error))
+    }
+}
+
+// a listing that is saved is never sent by its emailer again, so only once it has been
+fn save_sent_listings(conn: &PgConnection, emailed: EmailedListings) -> i32 {
+    let mut listings_written = 0;
+    for mut listing in emailed.listings {
+        listing.emailer_id = emailed.emailer_id;
+        listing.user_id = emailed.user_id;
+        match listing.try_insert(conn) {
+            Ok(Some(_)) => listings_written += 1,
+            // a forced run, or another run that got to it first
+            Ok(None) => log::info!("Listing {:?} was already saved", listing.zpid),
+            Err(e) => log::error!("Error saving listing_data: {}", e),
+        }
+    }
+    listings_written
+}
+
+#[test]
+fn backoff_doubles_up_to_an_hour() {
+    assert_eq!(backoff(30, 1), Duration::seconds(30));
+    assert_eq!(backoff(30, 2), Duration::seconds(60));
+    assert_eq!(backoff(30, 4), Duration::seconds(240));
+    assert_eq!(backoff(30, 40), Duration::seconds(MAX_BACKOFF_SECONDS));
+    assert_eq!(backoff(30, 0), Duration::seconds(30));
+}
+
+#[test]
+fn old_run_emailer_payloads_still_parse() {
+    let run =
+        serde_json::from_value::<RunEmailer>(serde_json::json!({ "emailer_id": 12 })).unwrap();
+    assert_eq!(run.emailer_id, 12);
+    assert_eq!(run.run_window, None);
+    assert!(!run.force);
+}
@@ -0,0 +1,429 @@
+use crate::{config::Config, utils::JwtPayload};
+use jsonwebtoken::{
+    decode, decode_header, encode, errors::ErrorKind, jwk::JwkSet, Algorithm, DecodingKey,
+    EncodingKey, Header, Validation,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::RwLock;
+use warp::{filters::BoxedFilter, Filter};
+
+// don't hammer the user pool when someone sends us tokens with made up kids
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+// where the user pool's signing keys come from
+#[derive(Clone, Debug)]
+pub enum JwksSource {
+    Url(String),
+    File(String),
+}
+
+// why a token was turned away
+#[derive(Debug, PartialEq)]
+pub enum JwtError {
+    Expired,
+    InvalidAudience,
+    Invalid(String),
+}
+
+// the claims we check on a cognito id token
+#[derive(Deserialize)]
+struct CognitoClaims {
+    sub: String,
+    email: String,
+    token_use: String,
+    #[serde(rename = "cognito:groups", default)]
+    groups: Vec<String>,
+}
+
+// access tokens carry the app client instead of an audience
+#[derive(Deserialize)]
+struct CognitoAccessClaims {
+    sub: String,
+    username: String,
+    client_id: String,
+    token_use: String,
+    iat: i64,
+}
+
+// a verified access token, which the user pool wants back for account changes
+#[derive(Clone, Debug)]
+pub struct AccessToken {
+    pub token: String,
+    pub sub: String,
+    pub username: String,
+    pub issued_at: i64,
+}
+
+struct KeyCache {
+    keys: HashMap<String, DecodingKey>,
+    fetched_at: Option<Instant>,
+}
+
+pub struct JwtVerifier {
+    source: JwksSource,
+    client: Arc<reqwest::Client>,
+    issuer: String,
+    audience: String,
+    leeway: u64,
+    cache: RwLock<KeyCache>,
+}
+
+pub fn with_jwt_verifier(verifier: Arc<JwtVerifier>) -> BoxedFilter<(Arc<JwtVerifier>,)> {
+    warp::any().map(move || verifier.clone()).boxed()
+}
+
+// build a verifier for the configured user pool and load its keys up front
+pub async fn get_jwt_verifier(config: Arc<Config>, client: Arc<reqwest::Client>) -> JwtVerifier {
+    let issuer = config.cognito_issuer();
+
+    let source = match config.cognito.jwks_path.clone() {
+        Some(path) => JwksSource::File(path),
+        None => JwksSource::Url(format!("{}/.well-known/jwks.json", issuer)),
+    };
+
+    let verifier = JwtVerifier::new(
+        source,
+        client,
+        issuer,
+        config.cognito.client_id.clone(),
+        config.cognito.jwt_leeway,
+    );
+
+    if let Err(e) = verifier.refresh().await {
+        log::error!("Could not load the JWKS: {}", e);
+    }
+
+    verifier
+}
+
+impl JwtVerifier {
+    pub fn new(
+        source: JwksSource,
+        client: Arc<reqwest::Client>,
+        issuer: String,
+        audience: String,
+        leeway: u64,
+    ) -> Self {
+        JwtVerifier {
+            source,
+            client,
+            issuer,
+            audience,
+            leeway,
+            cache: RwLock::new(KeyCache {
+                keys: HashMap::new(),
+                fetched_at: None,
+            }),
+        }
+    }
+
+    // reload the key set from its source, replacing whatever we had cached
+    pub async fn refresh(&self) -> Result<usize, String> {
+        let jwk_set = match &self.source {
+            JwksSource::Url(url) => {
+                log::info!("🔑 Fetching JWKS from {}", url);
+                self.client
+                    .get(url)
+                    .send()
+                    .await
+                    .map_err(|e| format!("{:?}", e))?
+                    .json::<JwkSet>()
+                    .await
+                    .map_err(|e| format!("{:?}", e))?
+            }
+            JwksSource::File(path) => {
+                log::info!("🔑 Reading JWKS from {}", path);
+                let contents = std::fs::read_to_string(path).map_err(|e| format!("{:?}", e))?;
+                serde_json::from_str::<JwkSet>(&contents).map_err(|e| format!("{:?}", e))?
+            }
+        };
+
+        let keys = jwk_set
+            .keys
+            .iter()
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                DecodingKey::from_jwk(jwk).ok().map(|key| (kid, key))
+            })
+            .collect::<HashMap<String, DecodingKey>>();
+
+        let mut cache = self.cache.write().await;
+        cache.keys = keys;
+        cache.fetched_at = Some(Instant::now());
+        Ok(cache.keys.len())
+    }
+
+    // look up a key by id, refreshing once if it is one we haven't seen
+    async fn get_key(&self, kid: &str) -> Option<DecodingKey> {
+        {
+            let cache = self.cache.read().await;
+            if let Some(key) = cache.keys.get(kid) {
+                return Some(key.clone());
+            }
+            let recently_fetched = matches!(
+                cache.fetched_at,
+                Some(fetched_at) if fetched_at.elapsed() < MIN_REFRESH_INTERVAL
+            );
+            if recently_fetched {
+                return None;
+            }
+        }
+
+        if let Err(e) = self.refresh().await {
+            log::error!("Could not refresh the JWKS: {}", e);
+        }
+
+        self.cache.read().await.keys.get(kid).cloned()
+    }
+
+    pub async fn verify(&self, token: &str) -> Result<JwtPayload, JwtError> {
+        let mut validation = self.validation();
+        validation.set_audience(&[&self.audience]);
+
+        let claims = self
+            .decode_claims::<CognitoClaims>(token, &validation)
+            .await?;
+
+        if claims.token_use != "id" {
+            return Err(JwtError::Invalid(format!(
+                "Expected an id token, got {}",
+                claims.token_use
+            )));
+        }
+
+        Ok(JwtPayload {
+            sub: claims.sub,
+            email: claims.email,
+            groups: claims.groups,
+        })
+    }
+
+    pub async fn verify_access(&self, token: &str) -> Result<AccessToken, JwtError> {
+        let claims = self
+            .decode_claims::<CognitoAccessClaims>(token, &self.validation())
+            .await?;
+
+        if claims.token_use != "access" {
+            return Err(JwtError::Invalid(format!(
+                "Expected an access token, got {}",
+                claims.token_use
+            )));
+        }
+        if claims.client_id != self.audience {
+            return Err(JwtError::InvalidAudience);
+        }
+
+        Ok(AccessToken {
+            token: String::from(token),
+            sub: claims.sub,
+            username: claims.username,
+            issued_at: claims.iat,
+        })
+    }
+
+    fn validation(&self) -> Validation {
+        let mut validation = Validation::new(Algorithm::RS256);
+        validation.leeway = self.leeway;
+        validation.set_issuer(&[&self.issuer]);
+        validation
+    }
+
+    async fn decode_claims<T: DeserializeOwned>(
+        &self,
+        token: &str,
+        validation: &Validation,
+    ) -> Result<T, JwtError> {
+        let header = decode_header(token).map_err(|e| JwtError::Invalid(format!("{:?}", e)))?;
+        let kid = header
+            .kid
+            .ok_or_else(|| JwtError::Invalid(String::from("Missing kid")))?;
+        let key = self
+            .get_key(&kid)
+            .await
+            .ok_or_else(|| JwtError::Invalid(format!("Unknown kid {}", kid)))?;
+
+        decode::<T>(token, &key, validation)
+            .map(|data| data.claims)
+            .map_err(|e| match e.kind() {
+                ErrorKind::ExpiredSignature => JwtError::Expired,
+                ErrorKind::InvalidAudience => JwtError::InvalidAudience,
+                _ => JwtError::Invalid(format!("{:?}", e)),
+            })
+    }
+}
+
+// signs id and access tokens shaped like the ones the user pool hands out,
+// for when we are the ones signing people in
+pub struct TokenIssuer {
+    key: EncodingKey,
+    key_id: String,
+    issuer: String,
+    client_id: String,
+}
+
+impl TokenIssuer {
+    pub fn new(
+        private_key_pem: &[u8],
+        key_id: String,
+        issuer: String,
+        client_id: String,
+    ) -> Result<Self, String> {
+        let key = EncodingKey::from_rsa_pem(private_key_pem).map_err(|e| format!("{:?}", e))?;
+        Ok(TokenIssuer {
+            key,
+            key_id,
+            issuer,
+            client_id,
+        })
+    }
+
+    pub fn sign(
+        &self,
+        sub: &str,
+        username: &str,
+        email: &str,
+        token_use: &str,
+        expires_in: i64,
+    ) -> Result<String, String> {
+        let now = chrono::Utc::now().timestamp();
+        let mut claims = serde_json::json!({
+            "sub": sub,
+            "email": email,
+            "email_verified": true,
+            "token_use": token_use,
+            "iss": self.issuer,
+            "iat": now,
+            "auth_time": now,
+            "exp": now + expires_in,
+        });
+
+        // cognito only puts the audience on id tokens
+        if token_use == "id" {
+            claims["aud"] = serde_json::json!(self.client_id);
+            claims["cognito:username"] = serde_json::json!(username);
+        } else {
+            claims["client_id"] = serde_json::json!(self.client_id);
+            claims["username"] = serde_json::json!(username);
+        }
+
+        let mut header = Header::new(Algorithm::RS256);
+        header.kid = Some(self.key_id.clone());
+        encode(&header, &claims, &self.key).map_err(|e| format!("{:?}", e))
+    }
+}
+
+#[cfg(test)]
+fn sign_test_token(claims: serde_json::Value) -> String {
+    let mut header = jsonwebtoken::Header::new(Algorithm::RS256);
+    header.kid = Some(String::from("ostrich-local-key"));
+    let key = jsonwebtoken::EncodingKey::from_rsa_pem(include_bytes!(
+        "../../fixtures/jwks_private_key.pem"
+    ))
+    .unwrap();
+    jsonwebtoken::encode(&header, &claims, &key).unwrap()
+}
+
+#[cfg(test)]
+fn test_verifier() -> JwtVerifier {
+    JwtVerifier::new(
+        JwksSource::File(String::from("fixtures/jwks.json")),
+        Arc::new(reqwest::Client::new()),
+        String::from("https://cognito-idp.us-east-2.amazonaws.com/us-east-2_local"),
+        String::from("local-client-id"),
+        60,
+    )
+}
+
+#[tokio::test]
+async fn verifies_signed_id_token() {
+    let token = sign_test_token(serde_json::json!({
+        "sub": "61b976a5-807a-4257-ba5e-4cdea6c39d18",
+        "email": "hgmaxwellking@gmail.com",
+        "token_use": "id",
+        "iss": "https://cognito-idp.us-east-2.amazonaws.com/us-east-2_local",
+        "aud": "local-client-id",
+        "exp": chrono::Utc::now().timestamp() + 3600,
+    }));
+    let payload = test_verifier().verify(&token).await.unwrap();
+    assert_eq!(payload.sub, "61b976a5-807a-4257-ba5e-4cdea6c39d18");
+}
+
+#[tokio::test]
+async fn rejects_bad_tokens() {
+    let verifier = test_verifier();
+    let claims = serde_json::json!({
+        "sub": "61b976a5-807a-4257-ba5e-4cdea6c39d18",
+        "email": "hgmaxwellking@gmail.com",
+        "token_use": "id",
+        "iss": "https://cognito-idp.us-east-2.amazonaws.com/us-east-2_local",
+        "aud": "local-client-id",
+        "exp": chrono::Utc::now().timestamp() + 3600,
+    });
+
+    let mut expired = claims.clone();
+    expired["exp"] = serde_json::json!(chrono::Utc::now().timestamp() - 120);
+    assert_eq!(
+        verifier.verify(&sign_test_token(expired)).await.err(),
+        Some(JwtError::Expired)
+    );
+
+    let mut wrong_audience = claims.clone();
+    wrong_audience["aud"] = serde_json::json!("someone-else");
+    assert_eq!(
+        verifier
+            .verify(&sign_test_token(wrong_audience))
+            .await
+            .err(),
+        Some(JwtError::InvalidAudience)
+    );
+
+    let mut access_token = claims.clone();
+    access_token["token_use"] = serde_json::json!("access");
+    assert!(verifier
+        .verify(&sign_test_token(access_token))
+        .await
+        .is_err());
+
+    // swapping someone else's claims into a signed token has to break the signature
+    let mut impersonated = claims.clone();
+    impersonated["sub"] = serde_json::json!("someone-else");
+    let token = sign_test_token(claims);
+    let impersonated = sign_test_token(impersonated);
+    let parts = token.split('.').collect::<Vec<&str>>();
+    let impersonated_parts = impersonated.split('.').collect::<Vec<&str>>();
+    let forged = format!("{}.{}.{}", parts[0], impersonated_parts[1], parts[2]);
+    assert!(verifier.verify(&forged).await.is_err());
+}
+
+#[tokio::test]
+async fn issued_tokens_pass_verification() {
+    let issuer = TokenIssuer::new(
+        include_bytes!("../../fixtures/jwks_private_key.pem"),
+        String::from("ostrich-local-key"),
+        String::from("https://cognito-idp.us-east-2.amazonaws.com/us-east-2_local"),
+        String::from("local-client-id"),
+    )
+    .unwrap();
+    let verifier = test_verifier();
+
+    let id_token = issuer
+        .sign("local-sub", "local", "local@ostrich.so", "id", 3600)
+        .unwrap();
+    let payload = verifier.verify(&id_token).await.unwrap();
+    assert_eq!(payload.sub, "local-sub");
+    assert_eq!(payload.email, "local@ostrich.so");
+
+    let access_token = issuer
+        .sign("local-sub", "local", "local@ostrich.so", "access", 3600)
+        .unwrap();
+    assert!(verifier.verify(&access_token).await.is_err());
+    let access = verifier.verify_access(&access_token).await.unwrap();
+    assert_eq!(access.sub, "local-sub");
+    assert_eq!(access.username, "local");
+    assert!(verifier.verify_access(&id_token).await.is_err());
+}
@@ -0,0 +1,474 @@
+use crate::{
+    config::Config,
+    db_conn::DbConn,
+    error::{OstrichError, OstrichErrorType},
+    models::{
+        emailer::Emailer,
+        listing_data::{self, NewListingData},
+    },
+    services::{
+        file_source::FileSource,
+        zillow::{ZillowClient, ZillowSource},
+    },
+};
+use async_trait::async_trait;
+use futures::{future, Stream, StreamExt};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    pin::Pin,
+    str::FromStr,
+    sync::Arc,
+};
+
+pub const ZILLOW: &str = "zillow";
+const FILE_PREFIX: &str = "file:";
+
+// what a search asks for, whoever ends up answering it
+#[derive(Deserialize, Debug, Clone)]
+pub struct SearchParameters {
+    pub search_param: String,
+    pub max_price: Option<f64>,
+    pub min_price: Option<f64>,
+    pub no_bedrooms: Option<i32>,
+    pub no_bathrooms: Option<i32>,
+    // only listings that came on the market in the last this many days
+    #[serde(default)]
+    pub days_on_market: Option<i32>,
+}
+
+// a property as a search lists it, enough to go and get the rest
+#[derive(Debug, Clone)]
+pub struct Listing {
+    pub id: String,
+    pub address: Option<String>,
+    pub price: Option<f64>,
+}
+
+// everything about a property that goes into an email
+#[derive(Debug, Clone, Default)]
+pub struct PropertyDetails {
+    pub id: String,
+    pub street_address: Option<String>,
+    pub city: Option<String>,
+    pub state: Option<String>,
+    pub zipcode: Option<String>,
+    pub bedrooms: Option<i32>,
+    pub bathrooms: Option<i32>,
+    pub price: Option<f64>,
+    // yearly, as a percentage of the price
+    pub tax_rate: Option<f64>,
+    pub rent_estimate: Option<f64>,
+    pub time_on_market: Option<String>,
+    pub img_src: Option<String>,
+    pub url: Option<String>,
+}
+
+// every listing of a search, as they are wanted; a page that can't be read is an error,
+// and the last item
+pub type Listings = Pin<Box<dyn Stream<Item = Result<Listing, OstrichError>> + Send>>;
+
+pub struct ListingPages {
+    pub total_results: Option<i64>,
+    pub listings: Listings,
+}
+
+// somewhere listings come from; a search that finds nothing is a ListingResultError and a
+// property that can't be had is a PropertyResultError
+#[async_trait]
+pub trait ListingSource: Send + Sync {
+    // where a search goes, kept on the emailer's runs
+    fn search_url(&self, search: &SearchParameters) -> String;
+
+    async fn search(&self, search: &SearchParameters) -> Result<ListingPages, OstrichError>;
+
+    async fn details(&self, listing: &Listing) -> Result<PropertyDetails, OstrichError>;
+
+    // how many details to fetch at once
+    fn concurrency(&self) -> usize {
+        1
+    }
+
+    // the calls made to a paid api so far, held against the owner's monthly quota; None for
+    // sources that cost us nothing
+    fn api_calls(&self) -> Option<i64> {
+        None
+    }
+}
+
+// zillow, or one of the owner's exports in LISTING_FILES_DIR named like file:listings.csv
+#[derive(Debug, Clone, PartialEq)]
+pub enum SourceName {
+    Zillow,
+    File(String),
+}
+
+impl FromStr for SourceName {
+    type Err = String;
+
+    fn from_str(source: &str) -> Result<Self, Self::Err> {
+        let source = source.trim();
+        if source.eq_ignore_ascii_case(ZILLOW) {
+            return Ok(SourceName::Zillow);
+        }
+        let name = match source.get(..FILE_PREFIX.len()) {
+            Some(prefix) if prefix.eq_ignore_ascii_case(FILE_PREFIX) => {
+                Some(&source[FILE_PREFIX.len()..])
+            }
+            _ => None,
+        };
+        // just a file name, so nobody can read their way out of the directory
+        match name {
+            Some(name)
+                if !name.starts_with('.')
+                    && !name.contains(['/', '\\'])
+                    && (name.ends_with(".json") || name.ends_with(".csv")) =>
+            {
+                Ok(SourceName::File(String::from(name)))
+            }
+            _ => Err(format!("UNKNOWN_LISTING_SOURCE: {}", source)),
+        }
+    }
+}
+
+impl SourceName {
+    pub fn as_string(&self) -> String {
+        match self {
+            SourceName::Zillow => String::from(ZILLOW),
+            SourceName::File(name) => format!("{}{}", FILE_PREFIX, name),
+        }
+    }
+}
+
+// every user's exports sit under their id, so nobody can search someone else's feed
+pub fn owner_files_dir(files_dir: &str, user_id: i32) -> PathBuf {
+    Path::new(files_dir).join(user_id.to_string())
+}
+
+// the owner is the user the emailer belongs to
+pub fn get_listing_source(
+    config: Arc<Config>,
+    zillow_client: Arc<ZillowClient>,
+    source: &str,
+    owner_id: i32,
+) -> Result<Box<dyn ListingSource>, OstrichError> {
+    let source = source
+        .parse::<SourceName>()
+        .map_err(|e| OstrichError::new(e, OstrichErrorType::ApiError))?;
+    match source {
+        SourceName::Zillow => Ok(Box::new(ZillowSource::new(config, zillow_client))),
+        SourceName::File(name) => match &config.listing_files_dir {
+            Some(dir) => Ok(Box::new(FileSource::new(
+                &owner_files_dir(dir, owner_id),
+                &name,
+            ))),
+            None => Err(OstrichError::new(
+                format!("LISTING_FILES_DIR isn't set, so there is no {}", name),
+                OstrichErrorType::ApiError,
+            )),
+        },
+    }
+}
+
+// the properties a search turned up with their cash on cash worked out, nothing is saved
+pub struct ListingSearch {
+    pub listings: Vec<NewListingData>,
+    pub total_results: Option<i64>,
+    pub properties_failed: i32,
+    pub already_sent: i32,
+}
+
+// the ids of the listings an emailer has emailed before
+pub fn sent_listings(
+    db_conn: &DbConn,
+    emailer_record: &Emailer,
+) -> Result<HashSet<String>, OstrichError> {
+    listing_data::read_zpids_by_emailer_id(&db_conn.get_conn(), emailer_record.id).map_err(|e| {
+        OstrichError::new(
+            format!("Could not read the listings already sent: {}", e),
+            OstrichErrorType::ApiError,
+        )
+    })
+}
+
+// listings in already_sent are passed over before their details are fetched
+pub async fn search_listings(
+    source: &dyn ListingSource,
+    emailer_record: &Emailer,
+    search: &SearchParameters,
+    max_listings: Option<usize>,
+    already_sent: &HashSet<String>,
+) -> Result<ListingSearch, OstrichError> {
+    let listing_pages = source.search(search).await?;
+
+    let total_results = listing_pages.total_results;
+    log::info!("Found {} properties", total_results.unwrap_or(0));
+
+    let mut skipped = 0;
+    let mut property_results = listing_pages
+        .listings
+        .filter(|listing| {
+            let sent = match listing {
+                Ok(listing) if already_sent.contains(&listing.id) => {
+                    log::debug!("Already sent {}", listing.id);
+                    skipped += 1;
+                    true
+                }
+                _ => false,
+            };
+            future::ready(!sent)
+        })
+        .take(max_listings.unwrap_or(usize::MAX))
+        .map(|listing| async move {
+            match listing {
+                Ok(listing) => Ok(source.details(&listing).await),
+                Err(e) => Err(e),
+            }
+        })
+        // in the order they were listed, however they finish
+        .buffered(source.concurrency().max(1));
+
+    let mut listing_search = ListingSearch {
+        listings: Vec::new(),
+        total_results,
+        properties_failed: 0,
+        already_sent: 0,
+    };
+    while let Some(property_result) = property_results.next().await {
+        match property_result {
+            // the source is down, so the rest would fail too and half an email is no good
+            Ok(Err(e)) | Err(e) if matches!(e.etype, OstrichErrorType::OutageError) => {
+                return Err(e)
+            }
+            Ok(Ok(property_result)) => {
+                log::debug!("{:?}", property_result);
+                listing_search
+                    .listings
+                    .push(NewListingData::new(property_result, emailer_record));
+            }
+            Ok(Err(e)) => {
+                log::error!("{:?}", e);
+                listing_search.properties_failed += 1;
+            }
+            // the pages already read are still worth sending
+            Err(e) => log::error!("Stopped reading listings: {:?}", e),
+        }
+    }
+    drop(property_results);
+
+    listing_search.already_sent = skipped;
+    if skipped > 0 {
+        log::info!("Skipped {} properties that were already sent", skipped);
+    }
+    // the same as a search that found nothing, rather than an email with no listings in it
+    if listing_search.listings.is_empty() && listing_search.properties_failed == 0 && skipped > 0 {
+        return Err(OstrichError::new(
+            format!("All {} properties were already sent", skipped),
+            OstrichErrorType::ListingResultError,
+        ));
+    }
+
+    Ok(listing_search)
+}
+
+// the listings one after another under the top of the email
+pub fn listings_email_body(body: String, listings: &[NewListingData]) -> String {
+    listings.iter().fold(body, |body, listing| {
+        let formatted_property_string = listing.to_email();
+        log::debug!("{:?}", formatted_property_string);
+        format!(
+            "{}<div style=\"border-top:1px solid black;\">{}</div>",
+            body, formatted_property_string
+        )
+    })
+}
+
+// the email a search made and what went into it, the listings are only saved once it is sent
+pub struct ListingEmail {
+    pub body: String,
+    pub total_results: Option<i64>,
+    pub properties_fetched: i32,
+    pub properties_failed: i32,
+    pub listings: Vec<NewListingData>,
+}
+
+impl ListingEmail {
+    pub fn new(body: String, total_results: Option<i64>) -> Self {
+        ListingEmail {
+            body,
+            total_results,
+            properties_fetched: 0,
+            properties_failed: 0,
+            listings: Vec::new(),
+        }
+    }
+}
+
+pub async fn get_listing_email_for_search_params(
+    source: &dyn ListingSource,
+    emailer_record: &Emailer,
+    body: String,
+    search: &SearchParameters,
+    max_listings: Option<usize>,
+    already_sent: &HashSet<String>,
+) -> Result<ListingEmail, OstrichError> {
+    let listing_search =
+        search_listings(source, emailer_record, search, max_listings, already_sent).await?;
+
+    let mut listing_email = ListingEmail::new(
+        listings_email_body(body, &listing_search.listings),
+        listing_search.total_results,
+    );
+    listing_email.properties_fetched = listing_search.listings.len() as i32;
+    listing_email.properties_failed = listing_search.properties_failed;
+    listing_email.listings = listing_search.listings;
+
+    Ok(listing_email)
+}
+
+#[test]
+fn sources_are_zillow_or_a_file_name() {
+    assert_eq!("Zillow".parse::<SourceName>(), Ok(SourceName::Zillow));
+    assert_eq!(
+        "File:acme_mls.csv".parse::<SourceName>(),
+        Ok(SourceName::File(String::from("acme_mls.csv")))
+    );
+    assert!("file:../secrets.json".parse::<SourceName>().is_err());
+    assert!("file:/etc/passwd".parse::<SourceName>().is_err());
+    assert!("file:listings.txt".parse::<SourceName>().is_err());
+    assert!("redfin".parse::<SourceName>().is_err());
+}
+
+// hands out the listings it was given, then the error of a page that couldn't be read if
+// there is one, and remembers which ones it was asked about
+#[cfg(test)]
+pub(crate) struct FakeSource {
+    pub ids: Vec<&'static str>,
+    pub page_error: Option<OstrichErrorType>,
+    pub fetched: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+#[async_trait]
+impl ListingSource for FakeSource {
+    fn search_url(&self, _search: &SearchParameters) -> String {
+        String::from("fake")
+    }
+
+    async fn search(&self, _search: &SearchParameters) -> Result<ListingPages, OstrichError> {
+        let mut listings = self
+            .ids
+            .iter()
+            .map(|id| {
+                Ok(Listing {
+                    id: String::from(*id),
+                    address: None,
+                    price: None,
+                })
+            })
+            .collect::<Vec<Result<Listing, OstrichError>>>();
+        if let Some(etype) = self.page_error {
+            listings.push(Err(OstrichError::new(String::from("page 2"), etype)));
+        }
+        Ok(ListingPages {
+            total_results: Some(listings.len() as i64),
+            listings: Box::pin(futures::stream::iter(listings)),
+        })
+    }
+
+    async fn details(&self, listing: &Listing) -> Result<PropertyDetails, OstrichError> {
+        self.fetched.lock().unwrap().push(listing.id.clone());
+        Ok(PropertyDetails {
+            id: listing.id.clone(),
+            ..PropertyDetails::default()
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_emailer() -> Emailer {
+    serde_json::from_value::<crate::models::emailer::PostEmailer>(serde_json::json!({
+        "search_param": "Austin, TX",
+        "frequency": "daily",
+        "max_price": null,
+        "min_price": null,
+        "no_bedrooms": null,
+        "no_bathrooms": null,
+        "insurance": 100,
+        "vacancy": 5,
+        "property_management": 8,
+        "capex": 5,
+        "repairs": 5,
+        "utilities": 0,
+        "down_payment": 20,
+        "closing_cost": 3,
+        "loan_interest": 6.5,
+        "loan_months": 360,
+        "additional_monthly_expenses": 0,
+        "notes": null,
+    }))
+    .unwrap()
+    .unsaved(1, String::from("a@b.com"))
+}
+
+#[tokio::test]
+async fn sent_listings_are_skipped_before_their_details() {
+    let emailer = test_emailer();
+    let search = SearchParameters::from(&emailer);
+    let mut source = FakeSource {
+        ids: vec!["1", "2", "3", "4", "5"],
+        page_error: None,
+        fetched: std::sync::Mutex::new(Vec::new()),
+    };
+    let already_sent = ["1", "3"]
+        .iter()
+        .map(|id| String::from(*id))
+        .collect::<HashSet<String>>();
+
+    // the two new listings fill the email, the sent ones in between don't count towards it
+    let listing_search = search_listings(&source, &emailer, &search, Some(2), &already_sent)
+        .await
+        .unwrap();
+    let zpids = listing_search
+        .listings
+        .iter()
+        .map(|listing| listing.zpid.clone().unwrap())
+        .collect::<Vec<String>>();
+    assert_eq!(zpids, vec!["2", "4"]);
+    assert_eq!(listing_search.already_sent, 2);
+    assert_eq!(*source.fetched.lock().unwrap(), vec!["2", "4"]);
+
+    // nothing new is the same as nothing found, and nothing is fetched for it
+    source.fetched.lock().unwrap().clear();
+    let already_sent = source
+        .ids
+        .iter()
+        .map(|id| String::from(*id))
+        .collect::<HashSet<String>>();
+    let all_sent = search_listings(&source, &emailer, &search, Some(2), &already_sent).await;
+    assert!(matches!(
+        all_sent,
+        Err(OstrichError {
+            etype: OstrichErrorType::ListingResultError,
+            ..
+        })
+    ));
+    assert!(source.fetched.lock().unwrap().is_empty());
+
+    // a later page that fails keeps what was read before it, unless the source is down
+    source.page_error = Some(OstrichErrorType::ApiError);
+    let partial = search_listings(&source, &emailer, &search, None, &HashSet::new())
+        .await
+        .unwrap();
+    assert_eq!(partial.listings.len(), 5);
+    source.page_error = Some(OstrichErrorType::OutageError);
+    let outage = search_listings(&source, &emailer, &search, None, &HashSet::new()).await;
+    assert!(matches!(
+        outage,
+        Err(OstrichError {
+            etype: OstrichErrorType::OutageError,
+            ..
+        })
+    ));
+}
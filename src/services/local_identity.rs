@@ -0,0 +1,628 @@
+use crate::{
+    config::Config,
+    db_conn::DbConn,
+    handlers::auth::{
+        AuthError, AuthErrorCode, AuthenticationDetails, LoginResult, SoftwareTokenAssociation,
+        SoftwareTokenVerification,
+    },
+    models::local_identity::{self, LocalIdentity, NewLocalIdentity, NewLocalRevokedToken},
+    services::{
+        email,
+        identity::IdentityProvider,
+        jwt::{AccessToken, TokenIssuer},
+    },
+    utils::now,
+};
+use argon2::{
+    password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use async_trait::async_trait;
+use chrono::{naive::NaiveDateTime, Duration};
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rand::{rngs::OsRng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::{collections::HashMap, sync::Arc};
+
+// how long id and access tokens are good for, same as the user pool
+const EXPIRES_IN: i32 = 3600;
+// how long a refresh token can be traded in for new tokens
+const REFRESH_EXPIRES_IN: i64 = 60 * 60 * 24 * 30;
+// how long an emailed confirmation or reset code stays valid
+const CODE_EXPIRES_IN_MINUTES: i64 = 60 * 24;
+// wrong guesses before an emailed code is burnt and a new one has to be sent
+const MAX_CODE_ATTEMPTS: i32 = 5;
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+// refresh tokens only ever come back to us, so they are signed with our secret
+#[derive(Serialize, Deserialize)]
+struct RefreshClaims {
+    jti: String,
+    sub: String,
+    username: String,
+    token_use: String,
+    iat: i64,
+    exp: i64,
+}
+
+// argon2 hashed passwords in our own database, for running without cognito.
+// errors read like cognito's so clients can't tell the difference
+pub struct LocalIdentityProvider {
+    config: Arc<Config>,
+    db_conn: Arc<DbConn>,
+    email: Arc<sendgrid_async::Client>,
+    tokens: TokenIssuer,
+    secret: String,
+}
+
+impl LocalIdentityProvider {
+    pub fn new(config: Arc<Config>, db_conn: Arc<DbConn>) -> Self {
+        let local_auth = config
+            .local_auth
+            .clone()
+            .expect("LOCAL_AUTH_PRIVATE_KEY_PATH must be set");
+        let private_key = std::fs::read(&local_auth.private_key_path)
+            .expect("Could not read LOCAL_AUTH_PRIVATE_KEY_PATH");
+        let tokens = TokenIssuer::new(
+            &private_key,
+            local_auth.key_id,
+            config.cognito_issuer(),
+            config.cognito.client_id.clone(),
+        )
+        .expect("LOCAL_AUTH_PRIVATE_KEY_PATH is not a valid RSA key");
+
+        LocalIdentityProvider {
+            db_conn,
+            email: Arc::new(email::get_email_client(config.clone())),
+            config,
+            tokens,
+            secret: local_auth.secret,
+        }
+    }
+
+    // the server secret, so a database dump alone can't work a code back out of its hash
+    fn code_key(&self) -> &[u8] {
+        self.secret.as_bytes()
+    }
+
+    fn get_identity(&self, username: String) -> Option<LocalIdentity> {
+        let conn = self.db_conn.get_conn();
+        local_identity::get_by_username(&conn, username)
+            .into_iter()
+            .next()
+    }
+
+    fn get_existing_identity(&self, username: String) -> Result<LocalIdentity, AuthError> {
+        self.get_identity(username).ok_or_else(|| {
+            AuthError::new(
+                AuthErrorCode::UserNotFound,
+                String::from("UserNotFoundException: Username/client id combination not found."),
+            )
+        })
+    }
+
+    fn authentication_details(
+        &self,
+        identity: &LocalIdentity,
+        refresh_token: Option<String>,
+    ) -> Result<AuthenticationDetails, AuthError> {
+        let sign = |token_use| {
+            self.tokens
+                .sign(
+                    &identity.sub,
+                    &identity.username,
+                    &identity.email,
+                    token_use,
+                    EXPIRES_IN as i64,
+                )
+                .map_err(|e| {
+                    log::error!("Could not sign token: {}", e);
+                    AuthError::new(AuthErrorCode::ServerError, String::from("SERVER_ERROR"))
+                })
+        };
+
+        Ok(AuthenticationDetails {
+            access_token: Some(sign("access")?),
+            expires_in: EXPIRES_IN,
+            token_type: Some(String::from("Bearer")),
+            refresh_token,
+            id_token: Some(sign("id")?),
+        })
+    }
+
+    fn refresh_token(&self, identity: &LocalIdentity) -> Result<String, AuthError> {
+        let iat = chrono::Utc::now().timestamp();
+        let claims = RefreshClaims {
+            jti: uuid::Uuid::new_v4().to_string(),
+            sub: identity.sub.clone(),
+            username: identity.username.clone(),
+            token_use: String::from("refresh"),
+            iat,
+            exp: iat + REFRESH_EXPIRES_IN,
+        };
+        encode(
+            &Header::new(Algorithm::HS256),
+            &claims,
+            &EncodingKey::from_secret(self.secret.as_bytes()),
+        )
+        .map_err(|e| {
+            log::error!("Could not sign refresh token: {}", e);
+            AuthError::new(AuthErrorCode::ServerError, String::from("SERVER_ERROR"))
+        })
+    }
+
+    fn decode_refresh_token(&self, refresh_token: &str) -> Result<RefreshClaims, AuthError> {
+        let claims = decode::<RefreshClaims>(
+            refresh_token,
+            &DecodingKey::from_secret(self.secret.as_bytes()),
+            &Validation::new(Algorithm::HS256),
+        )
+        .map_err(|_| invalid_refresh_token())?
+        .claims;
+
+        let conn = self.db_conn.get_conn();
+        if claims.token_use != "refresh" || local_identity::is_revoked(&conn, claims.jti.clone()) {
+            return Err(invalid_refresh_token());
+        }
+        Ok(claims)
+    }
+
+    // the identity behind an access token, as long as it hasn't signed out since
+    fn get_signed_in_identity(
+        &self,
+        access_token: &AccessToken,
+    ) -> Result<LocalIdentity, AuthError> {
+        self.get_identity(access_token.username.clone())
+            .filter(|identity| identity.sub == access_token.sub)
+            .filter(|identity| issued_after_sign_out(identity, access_token.issued_at))
+            .ok_or_else(|| {
+                AuthError::new(
+                    AuthErrorCode::NotAuthorized,
+                    String::from("NotAuthorizedException: Access Token has been revoked"),
+                )
+            })
+    }
+
+    async fn send_confirmation_code(&self, identity: &LocalIdentity, code: &str) {
+        if let Err(e) = email::send_confirmation_code_email(
+            &self.email,
+            self.config.clone(),
+            &identity.email,
+            code,
+        )
+        .await
+        {
+            log::error!("{:?}", e);
+        }
+    }
+}
+
+#[async_trait]
+impl IdentityProvider for LocalIdentityProvider {
+    async fn sign_up(
+        &self,
+        username: String,
+        password: String,
+        email: String,
+    ) -> Result<String, AuthError> {
+        let username_exists = || {
+            AuthError::new(
+                AuthErrorCode::UsernameExists,
+                String::from(
+                    "UsernameExistsException: An account with the given email already exists.",
+                ),
+            )
+        };
+        if self.get_identity(username.clone()).is_some() {
+            return Err(username_exists());
+        }
+
+        let password_hash = hash_password(&password)?;
+        let code = generate_code();
+        let sub = uuid::Uuid::new_v4().to_string();
+        let code_hash = hash_code(self.code_key(), &sub, &code);
+        let identity = NewLocalIdentity::new(
+            username,
+            email,
+            sub,
+            password_hash,
+            code_hash,
+            code_expires_at(),
+        )
+        .try_insert(&self.db_conn.get_conn())
+        .map_err(|e| {
+            log::error!("Could not save local identity: {}", e);
+            AuthError::new(AuthErrorCode::ServerError, String::from("SERVER_ERROR"))
+        })?
+        .ok_or_else(username_exists)?;
+
+        self.send_confirmation_code(&identity, &code).await;
+        Ok(identity.sub)
+    }
+
+    async fn verify(&self, username: String, code: String) -> Result<(), AuthError> {
+        let identity = self.get_existing_identity(username)?;
+        if identity.confirmed {
+            return Err(AuthError::new(
+                AuthErrorCode::NotAuthorized,
+                String::from(
+                    "NotAuthorizedException: User cannot be confirmed. Current status is CONFIRMED",
+                ),
+            ));
+        }
+
+        let conn = self.db_conn.get_conn();
+        if let Err(e) = check_code(
+            self.code_key(),
+            &identity.sub,
+            &identity.confirmation_code_hash,
+            &identity.confirmation_code_expires_at,
+            identity.confirmation_code_attempts,
+            &code,
+        ) {
+            if e.code == AuthErrorCode::CodeMismatch {
+                local_identity::fail_confirmation_code(&conn, identity.id, MAX_CODE_ATTEMPTS);
+            }
+            return Err(e);
+        }
+        local_identity::confirm(&conn, identity.id);
+        Ok(())
+    }
+
+    async fn initiate(&self, username: String, password: String) -> Result<LoginResult, AuthError> {
+        let identity = self
+            .get_identity(username)
+            .filter(|identity| verify_password(&password, &identity.password_hash))
+            .ok_or_else(|| {
+                AuthError::new(
+                    AuthErrorCode::NotAuthorized,
+                    String::from("NotAuthorizedException: Incorrect username or password."),
+                )
+            })?;
+
+        if !identity.confirmed {
+            return Err(AuthError::new(
+                AuthErrorCode::UserNotConfirmed,
+                String::from("UserNotConfirmedException: User is not confirmed."),
+            ));
+        }
+
+        let refresh_token = self.refresh_token(&identity)?;
+        self.authentication_details(&identity, Some(refresh_token))
+            .map(LoginResult::Authenticated)
+    }
+
+    // we never hand out challenges, so there is nothing to respond to
+    async fn respond_to_challenge(
+        &self,
+        _username: String,
+        challenge_name: String,
+        _session: String,
+        _responses: HashMap<String, String>,
+    ) -> Result<LoginResult, AuthError> {
+        Err(AuthError::new(
+            AuthErrorCode::InvalidParameter,
+            format!(
+                "InvalidParameterException: Unsupported challenge {}",
+                challenge_name
+            ),
+        ))
+    }
+
+    async fn refresh(
+        &self,
+        username: String,
+        refresh_token: String,
+    ) -> Result<AuthenticationDetails, AuthError> {
+        let claims = self.decode_refresh_token(&refresh_token)?;
+        if claims.username != username {
+            return Err(invalid_refresh_token());
+        }
+
+        // the account has to still be around, and not signed out since, for its refresh token to work
+        let identity = self
+            .get_identity(username)
+            .filter(|identity| identity.sub == claims.sub && identity.confirmed)
+            .filter(|identity| issued_after_sign_out(identity, claims.iat))
+            .ok_or_else(invalid_refresh_token)?;
+
+        // like cognito, refreshing doesn't hand out a new refresh token
+        self.authentication_details(&identity, None)
+    }
+
+    async fn resend_confirmation_code(&self, username: String) -> Result<(), AuthError> {
+        let identity = self.get_existing_identity(username)?;
+        if identity.confirmed {
+            return Err(AuthError::new(
+                AuthErrorCode::InvalidParameter,
+                String::from("InvalidParameterException: User is already confirmed."),
+            ));
+        }
+
+        let code = generate_code();
+        local_identity::set_confirmation_code(
+            &self.db_conn.get_conn(),
+            identity.id,
+            hash_code(self.code_key(), &identity.sub, &code),
+            code_expires_at(),
+        );
+        self.send_confirmation_code(&identity, &code).await;
+        Ok(())
+    }
+
+    async fn forgot_password(&self, username: String) -> Result<(), AuthError> {
+        let identity = self.get_existing_identity(username)?;
+
+        let code = generate_code();
+        local_identity::set_reset_code(
+            &self.db_conn.get_conn(),
+            identity.id,
+            hash_code(self.code_key(), &identity.sub, &code),
+            code_expires_at(),
+        );
+
+        email::send_password_reset_code_email(
+            &self.email,
+            self.config.clone(),
+            &identity.email,
+            &code,
+        )
+        .await
+        .map_err(|e| {
+            log::error!("{:?}", e);
+            AuthError::new(
+                AuthErrorCode::CodeDeliveryFailure,
+                String::from("CodeDeliveryFailureException: Could not send the reset code."),
+            )
+        })
+    }
+
+    async fn confirm_forgot_password(
+        &self,
+        username: String,
+        password: String,
+        code: String,
+    ) -> Result<(), AuthError> {
+        let identity = self.get_existing_identity(username)?;
+        let conn = self.db_conn.get_conn();
+        if let Err(e) = check_code(
+            self.code_key(),
+            &identity.sub,
+            &identity.reset_code_hash,
+            &identity.reset_code_expires_at,
+            identity.reset_code_attempts,
+            &code,
+        ) {
+            if e.code == AuthErrorCode::CodeMismatch {
+                local_identity::fail_reset_code(&conn, identity.id, MAX_CODE_ATTEMPTS);
+            }
+            return Err(e);
+        }
+
+        let password_hash = hash_password(&password)?;
+        local_identity::reset_password(&conn, identity.id, password_hash);
+        Ok(())
+    }
+
+    async fn change_password(
+        &self,
+        access_token: &AccessToken,
+        previous_password: String,
+        proposed_password: String,
+    ) -> Result<(), AuthError> {
+        let identity = self.get_signed_in_identity(access_token)?;
+        if !verify_password(&previous_password, &identity.password_hash) {
+            return Err(AuthError::new(
+                AuthErrorCode::NotAuthorized,
+                String::from("NotAuthorizedException: Incorrect username or password."),
+            ));
+        }
+
+        let password_hash = hash_password(&proposed_password)?;
+        local_identity::update_password(&self.db_conn.get_conn(), identity.id, password_hash);
+        Ok(())
+    }
+
+    async fn global_sign_out(&self, access_token: &AccessToken) -> Result<(), AuthError> {
+        let identity = self.get_signed_in_identity(access_token)?;
+        local_identity::sign_out(&self.db_conn.get_conn(), identity.id);
+        Ok(())
+    }
+
+    async fn revoke_refresh_token(&self, refresh_token: String) -> Result<(), AuthError> {
+        let claims = self.decode_refresh_token(&refresh_token)?;
+        let expires_at = NaiveDateTime::from_timestamp(claims.exp, 0);
+        NewLocalRevokedToken::new(claims.jti, expires_at).insert(&self.db_conn.get_conn());
+        Ok(())
+    }
+
+    async fn delete_user(&self, access_token: &AccessToken) -> Result<(), AuthError> {
+        let identity = self.get_signed_in_identity(access_token)?;
+        local_identity::delete(&self.db_conn.get_conn(), identity.id);
+        Ok(())
+    }
+
+    async fn associate_software_token(
+        &self,
+        _access_token: Option<String>,
+        _session: Option<String>,
+    ) -> Result<SoftwareTokenAssociation, AuthError> {
+        Err(mfa_not_supported())
+    }
+
+    async fn verify_software_token(
+        &self,
+        _access_token: Option<String>,
+        _session: Option<String>,
+        _code: String,
+        _device_name: Option<String>,
+    ) -> Result<SoftwareTokenVerification, AuthError> {
+        Err(mfa_not_supported())
+    }
+}
+
+fn invalid_refresh_token() -> AuthError {
+    AuthError::new(
+        AuthErrorCode::NotAuthorized,
+        String::from("NotAuthorizedException: Invalid Refresh Token"),
+    )
+}
+
+// tokens from before a global sign out don't count anymore, iat only has
+// whole seconds so anything from the second of the sign out goes too
+fn issued_after_sign_out(identity: &LocalIdentity, issued_at: i64) -> bool {
+    match identity.signed_out_at {
+        Some(signed_out_at) => issued_at > signed_out_at.timestamp(),
+        None => true,
+    }
+}
+
+fn mfa_not_supported() -> AuthError {
+    AuthError::new(
+        AuthErrorCode::InvalidParameter,
+        String::from(
+            "InvalidParameterException: MFA is not supported by the local identity provider",
+        ),
+    )
+}
+
+fn hash_password(password: &str) -> Result<String, AuthError> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(AuthError::new(
+            AuthErrorCode::InvalidPassword,
+            String::from("InvalidPasswordException: Password not long enough"),
+        ));
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            log::error!("Could not hash password: {}", e);
+            AuthError::new(AuthErrorCode::ServerError, String::from("SERVER_ERROR"))
+        })
+}
+
+fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+// six digits, like the codes cognito emails out
+fn generate_code() -> String {
+    format!("{:06}", OsRng.gen_range(0..1_000_000))
+}
+
+fn code_mac(key: &[u8], sub: &str, code: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC can take a key of any size");
+    mac.update(sub.as_bytes());
+    mac.update(b":");
+    mac.update(code.as_bytes());
+    mac
+}
+
+// codes are stored keyed hashed so a database dump can't confirm accounts, and tied to the sub
+// so the same code for two accounts doesn't hash the same
+fn hash_code(key: &[u8], sub: &str, code: &str) -> String {
+    hex::encode(code_mac(key, sub, code).finalize().into_bytes())
+}
+
+fn code_expires_at() -> NaiveDateTime {
+    now() + Duration::minutes(CODE_EXPIRES_IN_MINUTES)
+}
+
+fn check_code(
+    key: &[u8],
+    sub: &str,
+    code_hash: &Option<String>,
+    expires_at: &Option<NaiveDateTime>,
+    attempts: i32,
+    code: &str,
+) -> Result<(), AuthError> {
+    let mismatch = || {
+        AuthError::new(
+            AuthErrorCode::CodeMismatch,
+            String::from(
+                "CodeMismatchException: Invalid verification code provided, please try again.",
+            ),
+        )
+    };
+
+    if attempts >= MAX_CODE_ATTEMPTS {
+        return Err(AuthError::new(
+            AuthErrorCode::TooManyFailedAttempts,
+            String::from(
+                "TooManyFailedAttemptsException: Too many failed attempts, please request a code again.",
+            ),
+        ));
+    }
+
+    let code_hash = code_hash
+        .as_ref()
+        .and_then(|code_hash| hex::decode(code_hash).ok())
+        .ok_or_else(mismatch)?;
+    // verify_slice compares in constant time
+    code_mac(key, sub, code.trim())
+        .verify_slice(&code_hash)
+        .map_err(|_| mismatch())?;
+
+    match expires_at {
+        Some(expires_at) if *expires_at > now() => Ok(()),
+        _ => Err(AuthError::new(
+            AuthErrorCode::ExpiredCode,
+            String::from(
+                "ExpiredCodeException: Invalid code provided, please request a code again.",
+            ),
+        )),
+    }
+}
+
+#[test]
+fn passwords_round_trip() {
+    let password_hash = hash_password("Password123!").unwrap();
+    assert!(verify_password("Password123!", &password_hash));
+    assert!(!verify_password("Password124!", &password_hash));
+    assert!(!verify_password("Password123!", "not-a-hash"));
+    assert!(hash_password("short").is_err());
+}
+
+#[test]
+fn codes_expire_and_mismatch() {
+    let code = generate_code();
+    assert_eq!(code.len(), 6);
+
+    let (key, sub) = (b"server-secret", "a-sub");
+    let code_hash = Some(hash_code(key, sub, &code));
+    let expires_at = Some(code_expires_at());
+    assert!(check_code(key, sub, &code_hash, &expires_at, 0, &code).is_ok());
+    assert!(check_code(key, sub, &code_hash, &expires_at, 0, "not-it").is_err());
+    assert!(check_code(key, sub, &None, &expires_at, 0, &code).is_err());
+
+    // the hash is no good without the secret, or for another account
+    assert!(check_code(b"other-secret", sub, &code_hash, &expires_at, 0, &code).is_err());
+    assert!(check_code(key, "other-sub", &code_hash, &expires_at, 0, &code).is_err());
+
+    let expired = check_code(
+        key,
+        sub,
+        &code_hash,
+        &Some(now() - Duration::minutes(1)),
+        0,
+        &code,
+    );
+    assert_eq!(expired.unwrap_err().code, AuthErrorCode::ExpiredCode);
+
+    let burnt = check_code(key, sub, &code_hash, &expires_at, MAX_CODE_ATTEMPTS, &code);
+    assert_eq!(
+        burnt.unwrap_err().code,
+        AuthErrorCode::TooManyFailedAttempts
+    );
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use warp::{filters::BoxedFilter, Filter};
+
+// anything that can show its counters at /metrics
+pub trait Metrics: Send + Sync {
+    fn render(&self) -> String;
+}
+
+// the prometheus text format, every name gets the prefix
+pub fn render(prefix: &str, counters: &[(&str, u64)], gauges: &[(&str, i64)]) -> String {
+    let mut metrics = String::new();
+    for (name, value) in counters {
+        metrics += &format!("# TYPE {0}_{1} counter\n{0}_{1} {2}\n", prefix, name, value);
+    }
+    for (name, value) in gauges {
+        metrics += &format!("# TYPE {0}_{1} gauge\n{0}_{1} {2}\n", prefix, name, value);
+    }
+    metrics
+}
+
+pub fn with_metrics(metrics: Arc<dyn Metrics>) -> BoxedFilter<(Arc<dyn Metrics>,)> {
+    warp::any().map(move || metrics.clone()).boxed()
+}
+
+#[test]
+fn metrics_render_as_prometheus_text() {
+    let rendered = render("ostrich_test", &[("runs_total", 2)], &[("running", -1)]);
+    assert_eq!(
+        rendered,
+        "# TYPE ostrich_test_runs_total counter\nostrich_test_runs_total 2\n\
+         # TYPE ostrich_test_running gauge\nostrich_test_running -1\n"
+    );
+}
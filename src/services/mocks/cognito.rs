@@ -0,0 +1,266 @@
+use crate::{
+    config::{MOCK_AWS_REGION, MOCK_USER_POOL_ID},
+    services::jwt::TokenIssuer,
+};
+use aws_sdk_cognitoidentityprovider::{
+    error::{
+        AssociateSoftwareTokenError, ChangePasswordError, ConfirmForgotPasswordError,
+        ConfirmSignUpError, DeleteUserError, ForgotPasswordError, GlobalSignOutError,
+        InitiateAuthError, ResendConfirmationCodeError, RespondToAuthChallengeError,
+        RevokeTokenError, SetUserMFAPreferenceError, SignUpError, VerifySoftwareTokenError,
+    },
+    model::{AuthenticationResultType, VerifySoftwareTokenResponseType},
+    output::{
+        AssociateSoftwareTokenOutput, ChangePasswordOutput, ConfirmForgotPasswordOutput,
+        ConfirmSignUpOutput, DeleteUserOutput, ForgotPasswordOutput, GlobalSignOutOutput,
+        InitiateAuthOutput, ResendConfirmationCodeOutput, RespondToAuthChallengeOutput,
+        RevokeTokenOutput, SetUserMfaPreferenceOutput, SignUpOutput, VerifySoftwareTokenOutput,
+    },
+    types::SdkError,
+    Client, Config, Region,
+};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Arc};
+
+// what every authenticator app gets enrolled with
+const MOCK_TOTP_SECRET: &str = "MOCKOSTRICHTOTPSECRETMOCKOSTRICH";
+
+// how long the fake tokens are good for
+const EXPIRES_IN: i32 = 3600;
+
+// the same username always maps to the same sub, so users rows survive restarts
+pub fn mock_sub(username: &str) -> String {
+    let digest = hex::encode(Sha256::digest(username.as_bytes()));
+    format!(
+        "{}-{}-{}-{}-{}",
+        &digest[0..8],
+        &digest[8..12],
+        &digest[12..16],
+        &digest[16..20],
+        &digest[20..32]
+    )
+}
+
+// sign a token the way the user pool would, with the key from fixtures/
+pub fn mock_token(client_id: &str, username: &str, token_use: &str) -> String {
+    TokenIssuer::new(
+        include_bytes!("../../../fixtures/jwks_private_key.pem"),
+        String::from("ostrich-local-key"),
+        format!(
+            "https://cognito-idp.{}.amazonaws.com/{}",
+            MOCK_AWS_REGION, MOCK_USER_POOL_ID
+        ),
+        String::from(client_id),
+    )
+    .and_then(|issuer| {
+        issuer.sign(
+            &mock_sub(username),
+            username,
+            username,
+            token_use,
+            EXPIRES_IN as i64,
+        )
+    })
+    .expect("Could not sign mock token")
+}
+
+fn mock_authentication_result(client_id: &str, username: &str) -> AuthenticationResultType {
+    AuthenticationResultType::builder()
+        .access_token(mock_token(client_id, username, "access"))
+        .id_token(mock_token(client_id, username, "id"))
+        .refresh_token(format!("mock-refresh-token:{}", username))
+        .token_type("Bearer")
+        .expires_in(EXPIRES_IN)
+        .build()
+}
+
+pub async fn get_cognito_client(region: String) -> Client {
+    log::info!("🤡 Using the mock Cognito user pool");
+    let config = Config::builder().region(Region::new(region)).build();
+    Client::from_conf(config)
+}
+
+pub async fn sign_up(
+    _client: Arc<Client>,
+    _client_id: String,
+    _secret_key: String,
+    username: String,
+    _password: String,
+    _email: String,
+) -> Result<SignUpOutput, SdkError<SignUpError>> {
+    Ok(SignUpOutput::builder()
+        .user_confirmed(false)
+        .user_sub(mock_sub(&username))
+        .build())
+}
+
+// every confirmation code is the right one
+pub async fn verify(
+    _client: Arc<Client>,
+    _client_id: String,
+    _secret_key: String,
+    _username: String,
+    _verification_input: String,
+) -> Result<ConfirmSignUpOutput, SdkError<ConfirmSignUpError>> {
+    Ok(ConfirmSignUpOutput::builder().build())
+}
+
+pub async fn resend_confirmation_code(
+    _client: Arc<Client>,
+    _client_id: String,
+    _secret_key: String,
+    username: String,
+) -> Result<ResendConfirmationCodeOutput, SdkError<ResendConfirmationCodeError>> {
+    log::info!(
+        "🤡 Pretending to resend a confirmation code to {}",
+        username
+    );
+    Ok(ResendConfirmationCodeOutput::builder().build())
+}
+
+// every password is the right one
+pub async fn initiate(
+    _client: Arc<Client>,
+    client_id: String,
+    _secret_key: String,
+    username: String,
+    _password: String,
+) -> Result<InitiateAuthOutput, SdkError<InitiateAuthError>> {
+    Ok(InitiateAuthOutput::builder()
+        .authentication_result(mock_authentication_result(&client_id, &username))
+        .build())
+}
+
+pub async fn forgot_password(
+    _client: Arc<Client>,
+    _client_id: String,
+    _secret_key: String,
+    username: String,
+) -> Result<ForgotPasswordOutput, SdkError<ForgotPasswordError>> {
+    log::info!(
+        "🤡 Pretending to send a password reset code to {}",
+        username
+    );
+    Ok(ForgotPasswordOutput::builder().build())
+}
+
+pub async fn confirm_forgot_password(
+    _client: Arc<Client>,
+    _client_id: String,
+    _secret_key: String,
+    _username: String,
+    _password: String,
+    _code: String,
+) -> Result<ConfirmForgotPasswordOutput, SdkError<ConfirmForgotPasswordError>> {
+    Ok(ConfirmForgotPasswordOutput::builder().build())
+}
+
+pub async fn refresh(
+    _client: Arc<Client>,
+    client_id: String,
+    _secret_key: String,
+    username: String,
+    _refresh_token: String,
+) -> Result<InitiateAuthOutput, SdkError<InitiateAuthError>> {
+    Ok(InitiateAuthOutput::builder()
+        .authentication_result(mock_authentication_result(&client_id, &username))
+        .build())
+}
+
+// every challenge answer is the right one
+pub async fn respond_to_auth_challenge(
+    _client: Arc<Client>,
+    client_id: String,
+    _secret_key: String,
+    username: String,
+    _challenge_name: String,
+    _session: String,
+    _responses: HashMap<String, String>,
+) -> Result<RespondToAuthChallengeOutput, SdkError<RespondToAuthChallengeError>> {
+    Ok(RespondToAuthChallengeOutput::builder()
+        .authentication_result(mock_authentication_result(&client_id, &username))
+        .build())
+}
+
+pub async fn change_password(
+    _client: Arc<Client>,
+    _access_token: String,
+    _previous_password: String,
+    _proposed_password: String,
+) -> Result<ChangePasswordOutput, SdkError<ChangePasswordError>> {
+    Ok(ChangePasswordOutput::builder().build())
+}
+
+// mock tokens can't be taken back, they just expire
+pub async fn global_sign_out(
+    _client: Arc<Client>,
+    _access_token: String,
+) -> Result<GlobalSignOutOutput, SdkError<GlobalSignOutError>> {
+    Ok(GlobalSignOutOutput::builder().build())
+}
+
+pub async fn revoke_token(
+    _client: Arc<Client>,
+    _client_id: String,
+    _secret_key: String,
+    _refresh_token: String,
+) -> Result<RevokeTokenOutput, SdkError<RevokeTokenError>> {
+    Ok(RevokeTokenOutput::builder().build())
+}
+
+pub async fn delete_user(
+    _client: Arc<Client>,
+    _access_token: String,
+) -> Result<DeleteUserOutput, SdkError<DeleteUserError>> {
+    Ok(DeleteUserOutput::builder().build())
+}
+
+pub async fn associate_software_token(
+    _client: Arc<Client>,
+    _access_token: Option<String>,
+    session: Option<String>,
+) -> Result<AssociateSoftwareTokenOutput, SdkError<AssociateSoftwareTokenError>> {
+    Ok(AssociateSoftwareTokenOutput::builder()
+        .secret_code(MOCK_TOTP_SECRET)
+        .set_session(session)
+        .build())
+}
+
+// every totp code is the right one
+pub async fn verify_software_token(
+    _client: Arc<Client>,
+    _access_token: Option<String>,
+    session: Option<String>,
+    _code: String,
+    _device_name: Option<String>,
+) -> Result<VerifySoftwareTokenOutput, SdkError<VerifySoftwareTokenError>> {
+    Ok(VerifySoftwareTokenOutput::builder()
+        .status(VerifySoftwareTokenResponseType::Success)
+        .set_session(session)
+        .build())
+}
+
+pub async fn enable_software_token_mfa(
+    _client: Arc<Client>,
+    _access_token: String,
+) -> Result<SetUserMfaPreferenceOutput, SdkError<SetUserMFAPreferenceError>> {
+    Ok(SetUserMfaPreferenceOutput::builder().build())
+}
+
+#[tokio::test]
+async fn mock_tokens_pass_verification() {
+    let verifier = crate::services::jwt::JwtVerifier::new(
+        crate::services::jwt::JwksSource::File(String::from("fixtures/jwks.json")),
+        Arc::new(reqwest::Client::new()),
+        format!(
+            "https://cognito-idp.{}.amazonaws.com/{}",
+            MOCK_AWS_REGION, MOCK_USER_POOL_ID
+        ),
+        String::from("mock-client-id"),
+        60,
+    );
+    let token = mock_token("mock-client-id", "mocks@ostrich.so", "id");
+    let payload = verifier.verify(&token).await.unwrap();
+    assert_eq!(payload.sub, mock_sub("mocks@ostrich.so"));
+    assert_eq!(payload.email, "mocks@ostrich.so");
+}
@@ -0,0 +1,6 @@
+// Deterministic stand-ins for everything we talk to over the network,
+// compiled in with `--features mocks`.
+pub mod cognito;
+pub mod email;
+pub mod stripe;
+pub mod zillow;
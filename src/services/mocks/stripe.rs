@@ -0,0 +1,86 @@
+use crate::Config;
+use serde::de::DeserializeOwned;
+use std::sync::Arc;
+use stripe::{Client, Customer, Product, Subscription, WebhookEvent};
+
+const SUBSCRIPTION: &str = include_str!("../../../fixtures/stripe/subscription.json");
+const CUSTOMER: &str = include_str!("../../../fixtures/stripe/customer.json");
+const PRODUCT: &str = include_str!("../../../fixtures/stripe/product.json");
+
+// load a fixture, swapping in the fields the caller asked about
+fn fixture<T: DeserializeOwned>(
+    fixture: &str,
+    overrides: &[(&str, String)],
+) -> Result<T, stripe::StripeError> {
+    let mut value: serde_json::Value = serde_json::from_str(fixture)?;
+    for (key, override_value) in overrides {
+        value[*key] = serde_json::json!(override_value);
+    }
+    Ok(serde_json::from_value(value)?)
+}
+
+// there is no one to sign the payload locally, so take it as is
+pub async fn with_webhook(
+    _signature: String,
+    payload: String,
+    config: Arc<Config>,
+) -> Result<(Arc<Config>, WebhookEvent), warp::Rejection> {
+    let webhook_event = serde_json::from_str::<WebhookEvent>(&payload).map_err(|e| {
+        log::error!("{:?}", e);
+        warp::reject::reject()
+    })?;
+    Ok((config, webhook_event))
+}
+
+pub async fn get_customer_from_email(
+    _client: Arc<Client>,
+    email: String,
+) -> Result<Vec<Customer>, stripe::StripeError> {
+    Ok(vec![fixture(CUSTOMER, &[("email", email)])?])
+}
+
+pub async fn get_subscription_from_customer(
+    _client: Arc<Client>,
+    customer_id: stripe::CustomerId,
+) -> Result<Vec<Subscription>, stripe::StripeError> {
+    Ok(vec![fixture(
+        SUBSCRIPTION,
+        &[("customer", customer_id.to_string())],
+    )?])
+}
+
+pub async fn get_subscription_from_id(
+    _client: Arc<Client>,
+    subscription_id: stripe::SubscriptionId,
+) -> Result<Subscription, stripe::StripeError> {
+    fixture(SUBSCRIPTION, &[("id", subscription_id.to_string())])
+}
+
+pub async fn get_customer_from_id(
+    _client: Arc<Client>,
+    customer_id: stripe::CustomerId,
+) -> Result<Customer, stripe::StripeError> {
+    fixture(CUSTOMER, &[("id", customer_id.to_string())])
+}
+
+pub async fn get_product_from_id(
+    _client: Arc<Client>,
+    product_id: stripe::ProductId,
+) -> Result<Product, stripe::StripeError> {
+    fixture(PRODUCT, &[("id", product_id.to_string())])
+}
+
+#[tokio::test]
+async fn fixtures_deserialize() {
+    let client = Arc::new(Client::new("sk_test_mock"));
+    let subscription = get_subscription_from_id(client.clone(), "sub_mock".parse().unwrap()).await;
+    assert!(subscription.is_ok());
+    let customer = get_customer_from_id(client.clone(), "cus_mock".parse().unwrap()).await;
+    assert_eq!(customer.unwrap().email.unwrap(), "mocks@ostrich.so");
+    let product = get_product_from_id(client, "prod_mock".parse().unwrap()).await;
+    assert_eq!(product.unwrap().name.unwrap(), "Tier 1");
+    let event = serde_json::from_str::<WebhookEvent>(include_str!(
+        "../../../fixtures/stripe/webhook_subscription_updated.json"
+    ));
+    assert!(event.is_ok());
+}
@@ -0,0 +1,99 @@
+use crate::{
+    config::Config,
+    error::{OstrichError, OstrichErrorType},
+    services::{
+        resilience::Failure,
+        zillow::{
+            ListingResultsProp, ZillowClient, ZillowListingsSearchRoot, ZillowPropertySearchRoot,
+        },
+    },
+};
+use std::sync::Arc;
+
+const LISTINGS: &str = include_str!("../../../fixtures/zillow/property_extended_search.json");
+const PROPERTY: &str = include_str!("../../../fixtures/zillow/property.json");
+
+fn mock_listings() -> ZillowListingsSearchRoot {
+    serde_json::from_str(LISTINGS)
+        .expect("fixtures/zillow/property_extended_search.json is not a valid search result")
+}
+
+// every search returns the same page of fixture listings
+pub async fn get_zillow_listing_results(
+    _config: Arc<Config>,
+    _zillow_client: Arc<ZillowClient>,
+    api_url: String,
+) -> Result<ZillowListingsSearchRoot, Failure> {
+    log::info!("🤡 Getting mock listings for {}", api_url);
+    Ok(mock_listings())
+}
+
+pub async fn get_zillow_property_results_by_zpid(
+    _config: Arc<Config>,
+    _zillow_client: Arc<ZillowClient>,
+    zpid: String,
+) -> Result<ZillowPropertySearchRoot, Failure> {
+    log::info!("🤡 Getting mock property {}", zpid);
+    mock_property(&zpid).map_err(Failure::Fatal)
+}
+
+// the fixture property, dressed up with whatever the search said about this zpid
+fn mock_property(zpid: &str) -> Result<ZillowPropertySearchRoot, OstrichError> {
+    let mut property: serde_json::Value =
+        serde_json::from_str(PROPERTY).expect("fixtures/zillow/property.json is not valid json");
+    property["zpid"] = serde_json::json!(zpid.parse::<i64>().ok());
+
+    if let Some(listing) = mock_listings()
+        .props
+        .into_iter()
+        .find(|listing| listing.zpid.as_deref() == Some(zpid))
+    {
+        apply_listing(&mut property, listing);
+    }
+
+    serde_json::from_value(property).map_err(|e| {
+        OstrichError::new(
+            format!(
+                "Zillow Property {} did not result in a valid response {:?}",
+                zpid, e
+            ),
+            OstrichErrorType::PropertyResultError,
+        )
+    })
+}
+
+fn apply_listing(property: &mut serde_json::Value, listing: ListingResultsProp) {
+    property["price"] = serde_json::json!(listing.price);
+    property["bedrooms"] = serde_json::json!(listing.bedrooms.map(|x| x as i64));
+    property["bathrooms"] = serde_json::json!(listing.bathrooms.map(|x| x as i64));
+    property["imgSrc"] = serde_json::json!(listing.imgSrc);
+    property["url"] = serde_json::json!(format!(
+        "/homedetails/{}_zpid/",
+        listing.zpid.unwrap_or_default()
+    ));
+
+    // "23-15 31st St, Astoria, NY 11105"
+    if let Some(address) = listing.address {
+        let parts = address.split(", ").collect::<Vec<&str>>();
+        if let [street, city, state_zip] = parts[..] {
+            let mut state_zip = state_zip.split(' ');
+            property["address"] = serde_json::json!({
+                "streetAddress": street,
+                "city": city,
+                "state": state_zip.next(),
+                "zipcode": state_zip.next(),
+            });
+        }
+    }
+}
+
+#[test]
+fn mock_property_matches_listing() {
+    let property = mock_property("31088612").unwrap();
+    assert_eq!(property.zpid, Some(31088612));
+    assert_eq!(property.price, Some(1249000.0));
+    assert_eq!(
+        property.address.unwrap().streetAddress.unwrap(),
+        "30-42 36th St"
+    );
+}
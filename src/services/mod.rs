@@ -1,6 +1,25 @@
+pub mod api_key;
 pub mod cash_on_cash;
 pub mod cognito;
 pub mod email;
+pub mod entitlements;
+pub mod file_source;
+pub mod identity;
+pub mod jobs;
+pub mod jwt;
+pub mod listing_source;
+pub mod local_identity;
+pub mod metrics;
+#[cfg(feature = "mocks")]
+pub mod mocks;
+pub mod rate_limit;
+pub mod resilience;
+pub mod run_lock;
+pub mod schedule;
+pub mod scheduler;
+pub mod server;
 pub mod stripe;
+pub mod throttle;
 pub mod user;
+pub mod validation;
 pub mod zillow;
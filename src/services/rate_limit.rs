@@ -0,0 +1,333 @@
+use crate::{
+    config::{RateLimitConfig, RateLimitStoreKind},
+    db_conn::DbConn,
+    models::rate_limit::{self, NewRateLimitHit},
+    utils::now,
+};
+use chrono::{naive::NaiveDateTime, Duration};
+use diesel::prelude::*;
+use serde::de::DeserializeOwned;
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use warp::{filters::BoxedFilter, Filter};
+
+// past this many buckets the memory store sweeps out the idle ones
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+// how much a bucket may be hit before it gets locked out
+pub struct RateLimitRule {
+    pub max_hits: i64,
+    pub window: Duration,
+    pub lockout: Duration,
+}
+
+// the rejection for a locked out bucket, turned into a 429 by handle_rejection
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after: i64,
+}
+
+impl warp::reject::Reject for RateLimited {}
+
+// somewhere to keep count of hits and lockouts
+pub trait RateLimitStore: Send + Sync {
+    // counts a hit against the bucket, or says how many seconds until it can try again
+    fn hit(&self, bucket: &str, rule: &RateLimitRule, now: NaiveDateTime) -> Result<(), i64>;
+}
+
+fn seconds_until(until: NaiveDateTime, now: NaiveDateTime) -> i64 {
+    // round up, so clients never retry a moment too early
+    let milliseconds = (until - now).num_milliseconds();
+    (milliseconds + 999) / 1000
+}
+
+#[derive(Default)]
+struct Bucket {
+    hits: VecDeque<NaiveDateTime>,
+    locked_until: Option<NaiveDateTime>,
+}
+
+impl Bucket {
+    fn is_idle(&self, window_start: NaiveDateTime, now: NaiveDateTime) -> bool {
+        let locked = matches!(self.locked_until, Some(locked_until) if locked_until > now);
+        let recent = matches!(self.hits.back(), Some(hit_at) if *hit_at >= window_start);
+        !locked && !recent
+    }
+}
+
+// good for a single instance, forgets everything on restart
+#[derive(Default)]
+pub struct MemoryRateLimitStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimitStore for MemoryRateLimitStore {
+    fn hit(&self, bucket: &str, rule: &RateLimitRule, now: NaiveDateTime) -> Result<(), i64> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let window_start = now - rule.window;
+
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|_, bucket| !bucket.is_idle(window_start, now));
+        }
+
+        let entry = buckets.entry(String::from(bucket)).or_default();
+        if let Some(locked_until) = entry.locked_until {
+            if locked_until > now {
+                return Err(seconds_until(locked_until, now));
+            }
+            entry.locked_until = None;
+        }
+
+        while matches!(entry.hits.front(), Some(hit_at) if *hit_at < window_start) {
+            entry.hits.pop_front();
+        }
+
+        if entry.hits.len() as i64 >= rule.max_hits {
+            let locked_until = now + rule.lockout;
+            entry.hits.clear();
+            entry.locked_until = Some(locked_until);
+            return Err(seconds_until(locked_until, now));
+        }
+
+        entry.hits.push_back(now);
+        Ok(())
+    }
+}
+
+// shared by every instance and survives restarts
+pub struct PostgresRateLimitStore {
+    db_conn: Arc<DbConn>,
+}
+
+impl PostgresRateLimitStore {
+    pub fn new(db_conn: Arc<DbConn>) -> Self {
+        PostgresRateLimitStore { db_conn }
+    }
+}
+
+// clears out the hits of idle buckets every window, away from the requests
+async fn sweep_hits(db_conn: Arc<DbConn>, window: Duration) {
+    // an interval can't be zero
+    let period = window.to_std().unwrap_or_default();
+    let mut interval = tokio::time::interval(period.max(std::time::Duration::from_secs(1)));
+    loop {
+        interval.tick().await;
+        let db_conn = db_conn.clone();
+        let swept = tokio::task::spawn_blocking(move || {
+            rate_limit::delete_hits_before(&db_conn.get_conn(), now() - window)
+        })
+        .await;
+        match swept {
+            Ok(Ok(_)) => (),
+            Ok(Err(e)) => log::error!("Could not sweep rate limit hits: {}", e),
+            Err(e) => log::error!("Could not sweep rate limit hits: {}", e),
+        }
+    }
+}
+
+impl RateLimitStore for PostgresRateLimitStore {
+    fn hit(&self, bucket: &str, rule: &RateLimitRule, now: NaiveDateTime) -> Result<(), i64> {
+        let conn = self.db_conn.get_conn();
+        let result = conn.transaction::<_, diesel::result::Error, _>(|| {
+            rate_limit::lock_bucket(&conn, bucket)?;
+            if let Some(locked_until) = rate_limit::get_locked_until(&conn, bucket)? {
+                if locked_until > now {
+                    return Ok(Err(seconds_until(locked_until, now)));
+                }
+            }
+
+            rate_limit::delete_bucket_hits_before(&conn, bucket, now - rule.window)?;
+            if rate_limit::count_hits(&conn, bucket)? >= rule.max_hits {
+                let locked_until = now + rule.lockout;
+                rate_limit::clear_hits(&conn, bucket)?;
+                rate_limit::lock(&conn, bucket, locked_until)?;
+                return Ok(Err(seconds_until(locked_until, now)));
+            }
+
+            NewRateLimitHit::new(String::from(bucket), now).insert(&conn)?;
+            Ok(Ok(()))
+        });
+
+        // better to let a request through than to lock everybody out while the db is down
+        result.unwrap_or_else(|e| {
+            log::error!("Could not check rate limit for {}: {}", bucket, e);
+            Ok(())
+        })
+    }
+}
+
+pub struct RateLimiter {
+    store: Box<dyn RateLimitStore>,
+    username_rule: RateLimitRule,
+    ip_rule: RateLimitRule,
+    user_rule: RateLimitRule,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig, store: Box<dyn RateLimitStore>) -> Self {
+        let window = Duration::seconds(config.window_seconds);
+        let lockout = Duration::seconds(config.lockout_seconds);
+        RateLimiter {
+            store,
+            username_rule: RateLimitRule {
+                max_hits: config.max_per_username,
+                window,
+                lockout,
+            },
+            ip_rule: RateLimitRule {
+                max_hits: config.max_per_ip,
+                window,
+                lockout,
+            },
+            user_rule: RateLimitRule {
+                max_hits: config.max_per_user,
+                window,
+                lockout,
+            },
+        }
+    }
+
+    // each route gets its own buckets, so resending codes doesn't use up logins
+    pub fn check(&self, scope: &str, username: &str, ip: Option<&str>) -> Result<(), RateLimited> {
+        let now = now();
+        if let Some(ip) = ip {
+            let bucket = format!("{}:ip:{}", scope, ip);
+            self.store
+                .hit(&bucket, &self.ip_rule, now)
+                .map_err(|retry_after| RateLimited { retry_after })?;
+        }
+
+        let bucket = format!("{}:username:{}", scope, username.trim().to_lowercase());
+        self.store
+            .hit(&bucket, &self.username_rule, now)
+            .map_err(|retry_after| RateLimited { retry_after })
+    }
+
+    // for calls made by a user we already know, counted by their id
+    pub fn check_user(&self, scope: &str, user_id: i32) -> Result<(), RateLimited> {
+        let bucket = format!("{}:user:{}", scope, user_id);
+        self.store
+            .hit(&bucket, &self.user_rule, now())
+            .map_err(|retry_after| RateLimited { retry_after })
+    }
+}
+
+pub fn get_rate_limiter(config: &RateLimitConfig, db_conn: Arc<DbConn>) -> RateLimiter {
+    let store: Box<dyn RateLimitStore> = match config.store {
+        RateLimitStoreKind::Memory => {
+            log::info!("🚦 Keeping rate limits in memory");
+            Box::new(MemoryRateLimitStore::default())
+        }
+        RateLimitStoreKind::Postgres => {
+            log::info!("🚦 Keeping rate limits in the database");
+            tokio::spawn(sweep_hits(
+                db_conn.clone(),
+                Duration::seconds(config.window_seconds),
+            ));
+            Box::new(PostgresRateLimitStore::new(db_conn))
+        }
+    };
+    RateLimiter::new(config, store)
+}
+
+// request bodies that say which username they are for
+pub trait RateLimitKey {
+    fn username(&self) -> &str;
+}
+
+pub fn with_rate_limiter(rate_limiter: Arc<RateLimiter>) -> BoxedFilter<(Arc<RateLimiter>,)> {
+    warp::any().map(move || rate_limiter.clone()).boxed()
+}
+
+// the api gateway appends the address it saw to X-Forwarded-For, so the last entry
+// is the one we can trust, anything before it came from the client
+pub fn client_ip(forwarded_for: Option<String>, remote: Option<SocketAddr>) -> Option<String> {
+    forwarded_for
+        .and_then(|forwarded_for| {
+            forwarded_for
+                .rsplit(',')
+                .map(|ip| ip.trim())
+                .find(|ip| !ip.is_empty())
+                .map(String::from)
+        })
+        .or_else(|| remote.map(|remote| remote.ip().to_string()))
+}
+
+// parses the json body and counts it against both its username and the client ip
+pub fn with_rate_limit<T>(rate_limiter: Arc<RateLimiter>, scope: &'static str) -> BoxedFilter<(T,)>
+where
+    T: DeserializeOwned + RateLimitKey + Send + 'static,
+{
+    warp::body::json::<T>()
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::addr::remote())
+        .and(with_rate_limiter(rate_limiter))
+        .and_then(
+            move |body: T,
+                  forwarded_for: Option<String>,
+                  remote: Option<SocketAddr>,
+                  rate_limiter: Arc<RateLimiter>| async move {
+                let ip = client_ip(forwarded_for, remote);
+                match rate_limiter.check(scope, body.username(), ip.as_deref()) {
+                    Ok(()) => Ok(body),
+                    Err(rate_limited) => {
+                        log::warn!(
+                            "Rate limiting {} for {} from {:?}",
+                            scope,
+                            body.username(),
+                            ip
+                        );
+                        Err(warp::reject::custom(rate_limited))
+                    }
+                }
+            },
+        )
+        .boxed()
+}
+
+#[test]
+fn buckets_lock_out_and_recover() {
+    let store = MemoryRateLimitStore::default();
+    let rule = RateLimitRule {
+        max_hits: 2,
+        window: Duration::seconds(60),
+        lockout: Duration::seconds(300),
+    };
+    let start = now();
+
+    assert!(store.hit("login:ip:1.2.3.4", &rule, start).is_ok());
+    assert!(store.hit("login:ip:1.2.3.4", &rule, start).is_ok());
+    assert_eq!(store.hit("login:ip:1.2.3.4", &rule, start), Err(300));
+    assert_eq!(
+        store.hit("login:ip:1.2.3.4", &rule, start + Duration::seconds(100)),
+        Err(200)
+    );
+
+    // other buckets are on their own
+    assert!(store.hit("login:ip:5.6.7.8", &rule, start).is_ok());
+
+    // the lockout wipes the window, so it starts over afterwards
+    assert!(store
+        .hit("login:ip:1.2.3.4", &rule, start + Duration::seconds(300))
+        .is_ok());
+
+    // hits slide out of the window
+    assert!(store.hit("login:ip:5.6.7.8", &rule, start).is_ok());
+    assert!(store
+        .hit("login:ip:5.6.7.8", &rule, start + Duration::seconds(61))
+        .is_ok());
+}
+
+#[test]
+fn client_ip_trusts_the_last_hop() {
+    let remote = "10.0.0.1:443".parse().ok();
+    assert_eq!(
+        client_ip(Some(String::from("6.6.6.6, 1.2.3.4")), remote),
+        Some(String::from("1.2.3.4"))
+    );
+    assert_eq!(client_ip(None, remote), Some(String::from("10.0.0.1")));
+    assert_eq!(client_ip(None, None), None);
+}
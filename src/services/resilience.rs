@@ -0,0 +1,271 @@
+use crate::{
+    error::{OstrichError, OstrichErrorType},
+    services::throttle::Throttle,
+    utils::now,
+};
+use chrono::{naive::NaiveDateTime, DateTime, Duration};
+use rand::Rng;
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Response, StatusCode,
+};
+use std::{future::Future, sync::Mutex};
+
+// how one attempt at a request went wrong, and whether another could go better
+#[derive(Debug)]
+pub enum Failure {
+    Retry {
+        error: OstrichError,
+        retry_after: Option<Duration>,
+    },
+    Fatal(OstrichError),
+}
+
+impl From<reqwest::Error> for Failure {
+    fn from(e: reqwest::Error) -> Failure {
+        let retry = e.is_timeout() || e.is_connect();
+        let error = OstrichError::from(e);
+        if retry {
+            Failure::Retry {
+                error,
+                retry_after: None,
+            }
+        } else {
+            Failure::Fatal(error)
+        }
+    }
+}
+
+// Retry-After is either seconds or a date
+fn retry_after(headers: &HeaderMap, now: NaiveDateTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    let wait = match value.parse::<i64>() {
+        Ok(seconds) => Duration::seconds(seconds),
+        Err(_) => DateTime::parse_from_rfc2822(value).ok()?.naive_utc() - now,
+    };
+    Some(wait.max(Duration::zero()))
+}
+
+// a 429 or a 5xx is worth another go, any other error status isn't
+pub fn check_status(response: Response) -> Result<Response, Failure> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let error = OstrichError::new(
+        format!("{} answered {}", response.url(), status),
+        OstrichErrorType::ApiError,
+    );
+    if status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error() {
+        Err(Failure::Retry {
+            error,
+            retry_after: retry_after(response.headers(), now()),
+        })
+    } else {
+        Err(Failure::Fatal(error))
+    }
+}
+
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base: Duration,
+    // a Retry-After longer than this isn't worth waiting on
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    // the wait before retry number retry, or None to give up; somewhere between half and all
+    // of the doubled delay, picked by jitter, so requests that failed together spread out
+    fn delay(&self, retry: u32, retry_after: Option<Duration>, jitter: f64) -> Option<Duration> {
+        if retry > self.max_retries {
+            return None;
+        }
+        if matches!(retry_after, Some(retry_after) if retry_after > self.max_delay) {
+            return None;
+        }
+
+        let doubled = self.base.num_milliseconds() as f64 * 2f64.powi(retry as i32 - 1);
+        let capped = doubled.min(self.max_delay.num_milliseconds() as f64);
+        let delay = Duration::milliseconds((capped * (0.5 + jitter / 2.0)) as i64);
+        Some(delay.max(retry_after.unwrap_or_else(Duration::zero)))
+    }
+}
+
+#[derive(Default)]
+struct BreakerState {
+    failures: i64,
+    open_until: Option<NaiveDateTime>,
+}
+
+// stops asking a service that keeps failing, so a run gives up instead of spending its quota;
+// once the cooldown is over a single failure opens it again, a success closes it
+pub struct CircuitBreaker {
+    name: String,
+    failures_to_open: i64,
+    cooldown: Duration,
+    state: Mutex<BreakerState>,
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str, failures_to_open: i64, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            name: String::from(name),
+            failures_to_open,
+            cooldown,
+            state: Mutex::new(BreakerState::default()),
+        }
+    }
+
+    fn outage(&self, until: NaiveDateTime, now: NaiveDateTime, cause: &str) -> OstrichError {
+        OstrichError::new(
+            format!(
+                "{} is unavailable, not trying again for {} seconds: {}",
+                self.name,
+                (until - now).num_seconds(),
+                cause
+            ),
+            OstrichErrorType::OutageError,
+        )
+    }
+
+    pub fn check(&self, now: NaiveDateTime) -> Result<(), OstrichError> {
+        match self.state.lock().unwrap().open_until {
+            Some(until) if until > now => Err(self.outage(until, now, "the circuit is open")),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn succeeded(&self) {
+        *self.state.lock().unwrap() = BreakerState::default();
+    }
+
+    // counts a failure, and says so if that was the one that opened it
+    pub fn failed(&self, now: NaiveDateTime, error: &OstrichError) -> Option<OstrichError> {
+        let mut state = self.state.lock().unwrap();
+        state.failures += 1;
+        let open = matches!(state.open_until, Some(until) if until > now);
+        if open || state.failures < self.failures_to_open {
+            return None;
+        }
+
+        let until = now + self.cooldown;
+        state.open_until = Some(until);
+        log::error!(
+            "🔌 {} failed {} times in a row, stopping until {}",
+            self.name,
+            state.failures,
+            until
+        );
+        Some(self.outage(until, now, &error.details))
+    }
+}
+
+// tries a request until it works, fails for good, runs out of retries or opens the breaker;
+// every attempt waits on the throttle, so retries spend from the same budget
+pub async fn with_retries<T, F, Fut>(
+    throttle: &Throttle,
+    breaker: &CircuitBreaker,
+    policy: &RetryPolicy,
+    mut attempt: F,
+) -> Result<T, OstrichError>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, Failure>>,
+{
+    let mut retry = 0;
+    loop {
+        breaker.check(now())?;
+        throttle.wait().await;
+
+        let (error, retry_after) = match attempt().await {
+            Ok(value) => {
+                breaker.succeeded();
+                return Ok(value);
+            }
+            // it answered, so it's up
+            Err(Failure::Fatal(error)) => {
+                breaker.succeeded();
+                return Err(error);
+            }
+            Err(Failure::Retry { error, retry_after }) => (error, retry_after),
+        };
+        if let Some(outage) = breaker.failed(now(), &error) {
+            return Err(outage);
+        }
+
+        retry += 1;
+        let jitter = rand::thread_rng().gen::<f64>();
+        let delay = match policy.delay(retry, retry_after, jitter) {
+            Some(delay) => delay,
+            None => return Err(error),
+        };
+        log::warn!(
+            "Trying again in {}ms: {}",
+            delay.num_milliseconds(),
+            error.details
+        );
+        tokio::time::sleep(delay.to_std().unwrap_or_default()).await;
+    }
+}
+
+#[test]
+fn retries_back_off_and_honour_retry_after() {
+    let policy = RetryPolicy {
+        max_retries: 3,
+        base: Duration::milliseconds(500),
+        max_delay: Duration::seconds(30),
+    };
+    assert_eq!(
+        policy.delay(1, None, 1.0),
+        Some(Duration::milliseconds(500))
+    );
+    assert_eq!(
+        policy.delay(1, None, 0.0),
+        Some(Duration::milliseconds(250))
+    );
+    assert_eq!(policy.delay(3, None, 1.0), Some(Duration::seconds(2)));
+    assert_eq!(policy.delay(4, None, 1.0), None);
+    assert_eq!(
+        policy.delay(1, Some(Duration::seconds(5)), 1.0),
+        Some(Duration::seconds(5))
+    );
+    assert_eq!(policy.delay(1, Some(Duration::seconds(60)), 1.0), None);
+
+    let start = now();
+    let mut headers = HeaderMap::new();
+    headers.insert(RETRY_AFTER, "7".parse().unwrap());
+    assert_eq!(retry_after(&headers, start), Some(Duration::seconds(7)));
+    headers.insert(
+        RETRY_AFTER,
+        "Sun, 18 Oct 2026 12:00:30 GMT".parse().unwrap(),
+    );
+    assert_eq!(
+        retry_after(
+            &headers,
+            NaiveDateTime::parse_from_str("2026-10-18 12:00:00", "%Y-%m-%d %H:%M:%S").unwrap()
+        ),
+        Some(Duration::seconds(30))
+    );
+}
+
+#[test]
+fn breaker_opens_after_failures_in_a_row() {
+    let breaker = CircuitBreaker::new("zillow", 3, Duration::seconds(60));
+    let error = OstrichError::new(String::from("503"), OstrichErrorType::ApiError);
+    let start = now();
+
+    assert!(breaker.failed(start, &error).is_none());
+    breaker.succeeded();
+    assert!(breaker.failed(start, &error).is_none());
+    assert!(breaker.failed(start, &error).is_none());
+    assert!(breaker.failed(start, &error).is_some());
+    assert!(breaker.check(start + Duration::seconds(59)).is_err());
+
+    // after the cooldown one more failure is enough
+    let later = start + Duration::seconds(60);
+    assert!(breaker.check(later).is_ok());
+    assert!(breaker.failed(later, &error).is_some());
+    breaker.succeeded();
+    assert!(breaker.check(later).is_ok());
+}
@@ -0,0 +1,42 @@
+use crate::db_conn::DbConn;
+use diesel::{
+    pg::PgConnection,
+    prelude::*,
+    r2d2::{ConnectionManager, PooledConnection},
+    sql_types::{BigInt, Bool},
+};
+
+// emailer_service holds this for its whole run, so a cron that fires twice only runs once
+pub const EMAILER_SERVICE: i64 = 7_100_001;
+
+sql_function!(fn pg_try_advisory_lock(key: BigInt) -> Bool);
+sql_function!(fn pg_advisory_unlock(key: BigInt) -> Bool);
+
+// a postgres advisory lock, released when this is dropped. it belongs to the session, so
+// it keeps the connection it was taken on out of the pool until then
+pub struct RunLock {
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+    key: i64,
+}
+
+impl RunLock {
+    // None when someone else is holding it
+    pub fn try_acquire(db_conn: &DbConn, key: i64) -> QueryResult<Option<RunLock>> {
+        let conn = db_conn.get_conn();
+        let locked = diesel::select(pg_try_advisory_lock(key)).get_result::<bool>(&conn)?;
+        Ok(if locked {
+            Some(RunLock { conn, key })
+        } else {
+            None
+        })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        if let Err(e) = diesel::select(pg_advisory_unlock(self.key)).get_result::<bool>(&self.conn)
+        {
+            log::error!("Could not release run lock {}: {}", self.key, e);
+        }
+    }
+}
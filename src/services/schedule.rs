@@ -0,0 +1,247 @@
+use chrono::{naive::NaiveDateTime, Datelike, Duration, NaiveDate, TimeZone, Timelike};
+use chrono_tz::Tz;
+use std::str::FromStr;
+
+// daily, weekly and monthly emails go out at this hour on the user's clock
+const SEND_HOUR: u32 = 8;
+
+// zillow only filters daysOn by these, so the window gets rounded up to one of them
+const DAYS_ON_ZILLOW: [i64; 5] = [1, 7, 14, 30, 90];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Frequency {
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+}
+
+impl FromStr for Frequency {
+    type Err = String;
+
+    fn from_str(frequency: &str) -> Result<Self, Self::Err> {
+        match frequency.trim().to_lowercase().as_str() {
+            "instant" | "hourly" => Ok(Frequency::Hourly),
+            "daily" => Ok(Frequency::Daily),
+            "weekly" => Ok(Frequency::Weekly),
+            "monthly" => Ok(Frequency::Monthly),
+            _ => Err(format!("UNKNOWN_FREQUENCY: {}", frequency)),
+        }
+    }
+}
+
+impl Frequency {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Frequency::Hourly => "hourly",
+            Frequency::Daily => "daily",
+            Frequency::Weekly => "weekly",
+            Frequency::Monthly => "monthly",
+        }
+    }
+
+    // how far back the very first run looks
+    fn window(&self) -> Duration {
+        match self {
+            Frequency::Hourly => Duration::hours(1),
+            Frequency::Daily => Duration::days(1),
+            Frequency::Weekly => Duration::days(7),
+            Frequency::Monthly => Duration::days(30),
+        }
+    }
+
+    // the first send time after now, worked out on the user's clock and handed back in utc
+    pub fn next_run_at(&self, now: NaiveDateTime, time_zone: Tz) -> NaiveDateTime {
+        if *self == Frequency::Hourly {
+            return now.date().and_hms(now.hour(), 0, 0) + Duration::hours(1);
+        }
+
+        let local_now = time_zone.from_utc_datetime(&now).naive_local();
+        let today = local_now.date();
+        let send_at = |date: NaiveDate| date.and_hms(SEND_HOUR, 0, 0);
+        let next = match self {
+            Frequency::Daily => {
+                let send_today = send_at(today);
+                if send_today > local_now {
+                    send_today
+                } else {
+                    send_at(today + Duration::days(1))
+                }
+            }
+            Frequency::Weekly => {
+                let monday = today - Duration::days(today.weekday().num_days_from_monday() as i64);
+                let send_this_week = send_at(monday);
+                if send_this_week > local_now {
+                    send_this_week
+                } else {
+                    send_at(monday + Duration::days(7))
+                }
+            }
+            _ => {
+                let send_this_month = send_at(NaiveDate::from_ymd(today.year(), today.month(), 1));
+                if send_this_month > local_now {
+                    send_this_month
+                } else if today.month() == 12 {
+                    send_at(NaiveDate::from_ymd(today.year() + 1, 1, 1))
+                } else {
+                    send_at(NaiveDate::from_ymd(today.year(), today.month() + 1, 1))
+                }
+            }
+        };
+
+        to_utc(next, time_zone)
+    }
+
+    // the start of the period a send at this time belongs to, on the user's clock, so moving
+    // next_run_at around (a new time zone, say) can't squeeze a second email into one day
+    pub fn run_window(&self, at: NaiveDateTime, time_zone: Tz) -> NaiveDateTime {
+        if *self == Frequency::Hourly {
+            return at.date().and_hms(at.hour(), 0, 0);
+        }
+
+        let today = time_zone.from_utc_datetime(&at).naive_local().date();
+        let start = match self {
+            Frequency::Daily => today,
+            Frequency::Weekly => {
+                today - Duration::days(today.weekday().num_days_from_monday() as i64)
+            }
+            _ => NaiveDate::from_ymd(today.year(), today.month(), 1),
+        };
+        start.and_hms(0, 0, 0)
+    }
+}
+
+// a local time that falls in a daylight savings gap moves to the hour after
+fn to_utc(local: NaiveDateTime, time_zone: Tz) -> NaiveDateTime {
+    match time_zone.from_local_datetime(&local).earliest() {
+        Some(at) => at.naive_utc(),
+        None => to_utc(local + Duration::hours(1), time_zone),
+    }
+}
+
+pub fn parse_time_zone(name: &str) -> Option<Tz> {
+    name.parse::<Tz>().ok()
+}
+
+// users can only save zones we know, so this only falls back for rows edited by hand
+pub fn time_zone(name: &str) -> Tz {
+    parse_time_zone(name).unwrap_or_else(|| {
+        log::warn!("Unknown time zone {}, using UTC", name);
+        Tz::UTC
+    })
+}
+
+// how many days of listings a run should ask zillow for. a run that is a little late
+// still gets the smallest window covering its frequency, so a daily email an hour behind
+// doesn't jump to a week of listings, but one that missed a whole period catches up on it
+pub fn days_on_market(
+    frequency: Frequency,
+    last_run_at: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+) -> i32 {
+    let window = match last_run_at {
+        Some(last_run_at) if now - last_run_at > frequency.window() * 2 => now - last_run_at,
+        _ => frequency.window(),
+    };
+    let days = (window.num_seconds() + 86_399) / 86_400;
+    let days_on = DAYS_ON_ZILLOW
+        .iter()
+        .find(|days_on| **days_on >= days)
+        .unwrap_or(&DAYS_ON_ZILLOW[DAYS_ON_ZILLOW.len() - 1]);
+    *days_on as i32
+}
+
+#[test]
+fn runs_land_on_the_users_clock() {
+    let new_york = time_zone("America/New_York");
+    // 2026-10-18 is a sunday, and new york is four hours behind utc
+    let now = NaiveDate::from_ymd(2026, 10, 18).and_hms(14, 30, 0);
+
+    assert_eq!(
+        Frequency::Hourly.next_run_at(now, new_york),
+        NaiveDate::from_ymd(2026, 10, 18).and_hms(15, 0, 0)
+    );
+    assert_eq!(
+        Frequency::Daily.next_run_at(now, new_york),
+        NaiveDate::from_ymd(2026, 10, 19).and_hms(12, 0, 0)
+    );
+    // still saturday night in new york
+    let late = NaiveDate::from_ymd(2026, 10, 18).and_hms(3, 0, 0);
+    assert_eq!(
+        Frequency::Daily.next_run_at(late, new_york),
+        NaiveDate::from_ymd(2026, 10, 18).and_hms(12, 0, 0)
+    );
+    assert_eq!(
+        Frequency::Weekly.next_run_at(now, new_york),
+        NaiveDate::from_ymd(2026, 10, 19).and_hms(12, 0, 0)
+    );
+    // daylight savings is over by november, so it is five hours
+    assert_eq!(
+        Frequency::Monthly.next_run_at(now, new_york),
+        NaiveDate::from_ymd(2026, 11, 1).and_hms(13, 0, 0)
+    );
+    assert_eq!(time_zone("Not/AZone"), Tz::UTC);
+    assert_eq!("Instant".parse::<Frequency>(), Ok(Frequency::Hourly));
+    assert!("fortnightly".parse::<Frequency>().is_err());
+}
+
+#[test]
+fn run_windows_are_periods_on_the_users_clock() {
+    let new_york = time_zone("America/New_York");
+    // still saturday the 17th in new york
+    let at = NaiveDate::from_ymd(2026, 10, 18).and_hms(3, 0, 0);
+    assert_eq!(
+        Frequency::Hourly.run_window(at, new_york),
+        NaiveDate::from_ymd(2026, 10, 18).and_hms(3, 0, 0)
+    );
+    assert_eq!(
+        Frequency::Daily.run_window(at, new_york),
+        NaiveDate::from_ymd(2026, 10, 17).and_hms(0, 0, 0)
+    );
+    assert_eq!(
+        Frequency::Daily.run_window(at, Tz::UTC),
+        NaiveDate::from_ymd(2026, 10, 18).and_hms(0, 0, 0)
+    );
+    assert_eq!(
+        Frequency::Weekly.run_window(at, new_york),
+        NaiveDate::from_ymd(2026, 10, 12).and_hms(0, 0, 0)
+    );
+    assert_eq!(
+        Frequency::Monthly.run_window(at, new_york),
+        NaiveDate::from_ymd(2026, 10, 1).and_hms(0, 0, 0)
+    );
+}
+
+#[test]
+fn days_on_market_covers_the_last_run() {
+    let now = NaiveDate::from_ymd(2026, 10, 18).and_hms(14, 30, 0);
+    assert_eq!(days_on_market(Frequency::Daily, None, now), 1);
+    assert_eq!(days_on_market(Frequency::Weekly, None, now), 7);
+    assert_eq!(days_on_market(Frequency::Monthly, None, now), 30);
+    // late, but not by a whole day
+    assert_eq!(
+        days_on_market(Frequency::Daily, Some(now - Duration::hours(25)), now),
+        1
+    );
+    assert_eq!(
+        days_on_market(Frequency::Daily, Some(now - Duration::hours(47)), now),
+        1
+    );
+    // a missed day is caught up on
+    assert_eq!(
+        days_on_market(Frequency::Daily, Some(now - Duration::hours(49)), now),
+        7
+    );
+    assert_eq!(
+        days_on_market(Frequency::Weekly, Some(now - Duration::days(8)), now),
+        7
+    );
+    assert_eq!(
+        days_on_market(Frequency::Monthly, Some(now - Duration::days(31)), now),
+        30
+    );
+    assert_eq!(
+        days_on_market(Frequency::Weekly, Some(now - Duration::days(400)), now),
+        90
+    );
+}
@@ -0,0 +1,212 @@
+use crate::{
+    config::Config,
+    db_conn::DbConn,
+    models::{emailer, user},
+    services::{
+        entitlements,
+        jobs::{self, RunEmailer},
+        metrics::{self, Metrics},
+        schedule::{self, Frequency},
+    },
+    utils::now,
+};
+use chrono::naive::NaiveDateTime;
+use chrono_tz::Tz;
+use diesel::prelude::*;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::sync::watch;
+
+#[derive(Default)]
+pub struct SchedulerMetrics {
+    ticks: AtomicU64,
+    emailers_enqueued: AtomicU64,
+    last_tick_at: AtomicI64,
+}
+
+impl Metrics for SchedulerMetrics {
+    fn render(&self) -> String {
+        metrics::render(
+            "ostrich_scheduler",
+            &[
+                ("ticks_total", self.ticks.load(Ordering::Relaxed)),
+                (
+                    "emailers_enqueued_total",
+                    self.emailers_enqueued.load(Ordering::Relaxed),
+                ),
+            ],
+            &[(
+                "last_tick_timestamp_seconds",
+                self.last_tick_at.load(Ordering::Relaxed),
+            )],
+        )
+    }
+}
+
+// finds due emailers and queues a job for each, the workers do the rest
+#[derive(Clone)]
+pub struct Scheduler {
+    config: Arc<Config>,
+    db_conn: Arc<DbConn>,
+    metrics: Arc<SchedulerMetrics>,
+}
+
+impl Scheduler {
+    pub fn new(config: Arc<Config>, db_conn: Arc<DbConn>) -> Self {
+        Scheduler {
+            config,
+            db_conn,
+            metrics: Arc::new(SchedulerMetrics::default()),
+        }
+    }
+
+    pub fn metrics(&self) -> Arc<SchedulerMetrics> {
+        self.metrics.clone()
+    }
+
+    // wakes every tick to queue whatever is due, until shutdown is asked for
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) {
+        let tick = std::time::Duration::from_secs(self.config.scheduler.tick_seconds.max(1) as u64);
+        let mut interval = tokio::time::interval(tick);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    // diesel blocks, so keep it off the threads serving /health and /metrics
+                    let scheduler = self.clone();
+                    match tokio::task::spawn_blocking(move || scheduler.enqueue_due_emailers()).await {
+                        Ok(enqueued) => log::info!("Queued {} emailers this tick", enqueued),
+                        Err(e) => log::error!("The scheduler tick failed: {}", e),
+                    }
+                }
+                _ = shutdown.changed() => break,
+            }
+            if *shutdown.borrow() {
+                break;
+            }
+        }
+        log::info!("Scheduler stopped");
+    }
+
+    // one pass over everyone's due emailers, handing back how many got queued
+    pub fn enqueue_due_emailers(&self) -> usize {
+        let now = now();
+        self.metrics.ticks.fetch_add(1, Ordering::Relaxed);
+        self.metrics
+            .last_tick_at
+            .store(now.timestamp(), Ordering::Relaxed);
+
+        let conn = self.db_conn.get_conn();
+        let enqueued = enqueue_due(&conn, &self.config, now).unwrap_or_else(|e| {
+            log::error!("Could not load due emailers: {}", e);
+            0
+        });
+
+        self.metrics
+            .emailers_enqueued
+            .fetch_add(enqueued as u64, Ordering::Relaxed);
+        enqueued
+    }
+
+    // queues a run for this period even if it already went out, and leaves the schedule be;
+    // false when there is no such emailer
+    pub fn enqueue_forced(&self, emailer_id: i32) -> QueryResult<bool> {
+        let conn = self.db_conn.get_conn();
+        let emailer = match emailer::get_by_id(&conn, emailer_id).into_iter().next() {
+            Some(emailer) => emailer,
+            None => return Ok(false),
+        };
+        let time_zone = match user::get_user_by_id(&conn, emailer.user_id).first() {
+            Some(user) => schedule::time_zone(&user.time_zone),
+            None => return Ok(false),
+        };
+        let run_emailer = RunEmailer {
+            emailer_id,
+            run_window: Some(frequency(&emailer).run_window(now(), time_zone)),
+            force: true,
+        };
+        jobs::enqueue(&conn, &self.config.jobs, jobs::RUN_EMAILER, &run_emailer)?;
+        Ok(true)
+    }
+}
+
+// a handful of queries however many users there are. the owners' other emailers are loaded
+// too, since whether a due one is entitled depends on the older ones before it
+fn enqueue_due(conn: &PgConnection, config: &Config, now: NaiveDateTime) -> QueryResult<usize> {
+    let due = emailer::read_due(conn, now)?;
+    if due.is_empty() {
+        return Ok(0);
+    }
+    let user_ids = due
+        .iter()
+        .map(|emailer| emailer.user_id)
+        .collect::<HashSet<i32>>()
+        .into_iter()
+        .collect::<Vec<i32>>();
+    let mut emailers_by_user = HashMap::<i32, Vec<emailer::Emailer>>::new();
+    for emailer in emailer::read_by_user_ids(conn, &user_ids)? {
+        emailers_by_user
+            .entry(emailer.user_id)
+            .or_default()
+            .push(emailer);
+    }
+    let free_plan = entitlements::get_free_plan(conn);
+
+    let mut enqueued = 0;
+    for (user, plan) in user::read_with_plans(conn, &user_ids)? {
+        let plan = plan.unwrap_or_else(|| free_plan.clone());
+        let time_zone = schedule::time_zone(&user.time_zone);
+        let emailers = emailers_by_user.remove(&user.id).unwrap_or_default();
+
+        for emailer in entitlements::take_entitled(emailers, &plan)
+            .iter()
+            .filter(|emailer| emailer.next_run_at <= now)
+        {
+            match enqueue_emailer(conn, config, emailer, time_zone, now) {
+                Ok(true) => enqueued += 1,
+                Ok(false) => (),
+                Err(e) => log::error!("Could not queue emailer {}: {}", emailer.id, e),
+            }
+        }
+    }
+    Ok(enqueued)
+}
+
+// unknown frequencies run daily, like they always have
+pub fn frequency(emailer: &emailer::Emailer) -> Frequency {
+    emailer
+        .frequency
+        .parse::<Frequency>()
+        .unwrap_or(Frequency::Daily)
+}
+
+// moves the emailer on to its next run and queues this one in the same transaction, so
+// two schedulers can't both queue it and a crash can't lose it
+fn enqueue_emailer(
+    conn: &PgConnection,
+    config: &Config,
+    emailer: &emailer::Emailer,
+    time_zone: Tz,
+    now: NaiveDateTime,
+) -> QueryResult<bool> {
+    let frequency = frequency(emailer);
+    conn.transaction(|| {
+        let next_run_at = frequency.next_run_at(now, time_zone);
+        if emailer::claim(conn, emailer.id, now, next_run_at)? == 0 {
+            return Ok(false);
+        }
+        let run_emailer = RunEmailer {
+            emailer_id: emailer.id,
+            run_window: Some(frequency.run_window(emailer.next_run_at, time_zone)),
+            force: false,
+        };
+        jobs::enqueue(conn, &config.jobs, jobs::RUN_EMAILER, &run_emailer)?;
+        Ok(true)
+    })
+}
@@ -0,0 +1,66 @@
+use crate::{config::Config, handle_rejection, handlers, routes, services::metrics::Metrics};
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    sync::watch,
+    task::JoinHandle,
+};
+use warp::{Filter, Reply};
+
+// ecs sends SIGTERM before it kills the task, ctrl c is for running it locally
+pub async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Could not listen for SIGTERM");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = terminate.recv() => {},
+    }
+}
+
+// what the background services answer, so ecs can check on them and prometheus scrape them
+pub fn health_and_metrics(
+    metrics: Arc<dyn Metrics>,
+    name: &'static str,
+) -> impl Filter<Extract = (impl Reply,), Error = Infallible> + Clone {
+    let health = warp::get().and(warp::path("health")).map(warp::reply);
+    let metrics = routes::metrics::get_metrics(metrics).and_then(handlers::metrics::get_metrics);
+
+    health
+        .or(metrics)
+        .recover(handle_rejection)
+        .with(warp::log(name))
+}
+
+// serves end until shutdown changes, letting requests in flight finish
+pub fn serve_with_shutdown<F>(
+    end: F,
+    config: &Config,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()>
+where
+    F: Filter<Error = Infallible> + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let socket_address = config
+        .app_addr
+        .parse::<SocketAddr>()
+        .expect("Could not parse Addr");
+
+    log::info!("Listening at {}", &config.app_addr);
+
+    let stopped = async move {
+        let _ = shutdown.changed().await;
+    };
+    if config.tls {
+        log::info!("TLS Enabled!");
+
+        let (_, server) = warp::serve(end)
+            .tls()
+            .cert_path(config.cert_path.as_ref().unwrap())
+            .key_path(config.key_path.as_ref().unwrap())
+            .bind_with_graceful_shutdown(socket_address, stopped);
+        tokio::spawn(server)
+    } else {
+        let (_, server) = warp::serve(end).bind_with_graceful_shutdown(socket_address, stopped);
+        tokio::spawn(server)
+    }
+}
@@ -1,8 +1,18 @@
 use crate::Config;
 use std::sync::Arc;
-use stripe::{Client, Customer, Product, Subscription, Webhook, WebhookError, WebhookEvent};
+use stripe::{Client, Customer, Product};
 use warp::{filters::BoxedFilter, Filter};
 
+#[cfg(not(feature = "mocks"))]
+use stripe::{Subscription, Webhook, WebhookError, WebhookEvent};
+
+#[cfg(feature = "mocks")]
+pub use crate::services::mocks::stripe::{
+    get_customer_from_email, get_customer_from_id, get_product_from_id,
+    get_subscription_from_customer, get_subscription_from_id, with_webhook,
+};
+
+#[cfg(not(feature = "mocks"))]
 pub async fn with_webhook(
     signature: String,
     payload: String,
@@ -37,6 +47,7 @@ pub fn get_stripe_client(config: Arc<Config>) -> Client {
 //     ).await
 // }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_customer_from_email(
     client: Arc<Client>,
     email: String,
@@ -56,6 +67,7 @@ pub async fn get_customer_from_email(
     Ok(list.data)
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_subscription_from_customer(
     client: Arc<Client>,
     customer_id: stripe::CustomerId,
@@ -80,6 +92,7 @@ pub async fn get_subscription_from_customer(
     Ok(list.data)
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_subscription_from_id(
     client: Arc<Client>,
     subscription_id: stripe::SubscriptionId,
@@ -89,6 +102,7 @@ pub async fn get_subscription_from_id(
     Ok(subscription)
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_customer_from_id(
     client: Arc<Client>,
     customer_id: stripe::CustomerId,
@@ -98,6 +112,7 @@ pub async fn get_customer_from_id(
     Ok(customer)
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_product_from_id(
     client: Arc<Client>,
     product_id: stripe::ProductId,
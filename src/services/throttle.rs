@@ -0,0 +1,200 @@
+use crate::{
+    config::{RateLimitStoreKind, ZillowApiConfig},
+    db_conn::DbConn,
+    models::token_bucket,
+    utils::now,
+};
+use chrono::{naive::NaiveDateTime, Duration};
+use diesel::prelude::*;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+const ZILLOW_BUCKET: &str = "zillow";
+
+// tokens come back at rate a second, and a full bucket holds burst of them
+#[derive(Clone, Copy, Debug)]
+pub struct TokenBucketRule {
+    pub rate: f64,
+    pub burst: f64,
+}
+
+// the tokens a bucket has now, given what it had at updated_at
+fn refill(
+    rule: &TokenBucketRule,
+    tokens: f64,
+    updated_at: NaiveDateTime,
+    now: NaiveDateTime,
+) -> f64 {
+    let elapsed = (now - updated_at).num_milliseconds().max(0) as f64 / 1000.0;
+    (tokens + elapsed * rule.rate).min(rule.burst)
+}
+
+// takes a token if there is one, otherwise says how long until there will be;
+// either way the tokens left to save
+fn spend(rule: &TokenBucketRule, tokens: f64) -> (f64, Result<(), Duration>) {
+    if tokens >= 1.0 {
+        (tokens - 1.0, Ok(()))
+    } else {
+        let milliseconds = ((1.0 - tokens) / rule.rate * 1000.0).ceil() as i64;
+        (tokens, Err(Duration::milliseconds(milliseconds.max(1))))
+    }
+}
+
+// somewhere to keep the tokens left in each bucket
+pub trait TokenBucketStore: Send + Sync {
+    // takes a token from the bucket, or says how long to wait before trying again
+    fn take(
+        &self,
+        bucket: &str,
+        rule: &TokenBucketRule,
+        now: NaiveDateTime,
+    ) -> Result<(), Duration>;
+}
+
+// good for a single worker, every process gets its own budget
+#[derive(Default)]
+pub struct MemoryTokenBucketStore {
+    buckets: Mutex<HashMap<String, (f64, NaiveDateTime)>>,
+}
+
+impl TokenBucketStore for MemoryTokenBucketStore {
+    fn take(
+        &self,
+        bucket: &str,
+        rule: &TokenBucketRule,
+        now: NaiveDateTime,
+    ) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let entry = buckets
+            .entry(String::from(bucket))
+            .or_insert((rule.burst, now));
+        let (tokens, result) = spend(rule, refill(rule, entry.0, entry.1, now));
+        *entry = (tokens, now);
+        result
+    }
+}
+
+// shared by every worker, so they split one quota between them
+pub struct PostgresTokenBucketStore {
+    db_conn: Arc<DbConn>,
+}
+
+impl PostgresTokenBucketStore {
+    pub fn new(db_conn: Arc<DbConn>) -> Self {
+        PostgresTokenBucketStore { db_conn }
+    }
+}
+
+impl TokenBucketStore for PostgresTokenBucketStore {
+    fn take(
+        &self,
+        bucket: &str,
+        rule: &TokenBucketRule,
+        now: NaiveDateTime,
+    ) -> Result<(), Duration> {
+        let conn = self.db_conn.get_conn();
+        let result = conn.transaction::<_, diesel::result::Error, _>(|| {
+            token_bucket::create_if_missing(&conn, bucket, rule.burst, now)?;
+            let (tokens, updated_at) = token_bucket::lock(&conn, bucket)?;
+            // another worker's clock may be a little ahead of ours
+            let now = now.max(updated_at);
+            let (tokens, result) = spend(rule, refill(rule, tokens, updated_at, now));
+            token_bucket::save(&conn, bucket, tokens, now)?;
+            Ok(result)
+        });
+
+        // better to spend a little too fast than to stop every run while the db is down
+        result.unwrap_or_else(|e| {
+            log::error!("Could not check the {} throttle: {}", bucket, e);
+            Ok(())
+        })
+    }
+}
+
+// spaces out the requests to one api so they stay inside its budget
+pub struct Throttle {
+    store: Arc<dyn TokenBucketStore>,
+    rule: TokenBucketRule,
+    bucket: String,
+}
+
+impl Throttle {
+    pub fn new(bucket: &str, rule: TokenBucketRule, store: Arc<dyn TokenBucketStore>) -> Self {
+        Throttle {
+            store,
+            rule,
+            bucket: String::from(bucket),
+        }
+    }
+
+    // the store may block on the db, so it's asked off the async workers
+    async fn take(&self) -> Result<(), Duration> {
+        let (store, bucket, rule) = (self.store.clone(), self.bucket.clone(), self.rule);
+        tokio::task::spawn_blocking(move || store.take(&bucket, &rule, now()))
+            .await
+            .unwrap_or_else(|e| {
+                log::error!("Could not check the {} throttle: {}", self.bucket, e);
+                Ok(())
+            })
+    }
+
+    // waits until a request can be made, and counts it
+    pub async fn wait(&self) {
+        while let Err(wait) = self.take().await {
+            log::debug!(
+                "Waiting {}ms for the {} throttle",
+                wait.num_milliseconds(),
+                self.bucket
+            );
+            tokio::time::sleep(wait.to_std().unwrap_or_default()).await;
+        }
+    }
+}
+
+pub fn get_zillow_throttle(config: &ZillowApiConfig, db_conn: Arc<DbConn>) -> Throttle {
+    let rule = TokenBucketRule {
+        rate: config.requests_per_second,
+        burst: config.burst as f64,
+    };
+    let store: Arc<dyn TokenBucketStore> = match config.throttle_store {
+        RateLimitStoreKind::Memory => {
+            log::info!("🚦 Keeping the zillow throttle in memory");
+            Arc::new(MemoryTokenBucketStore::default())
+        }
+        RateLimitStoreKind::Postgres => {
+            log::info!("🚦 Keeping the zillow throttle in the database");
+            Arc::new(PostgresTokenBucketStore::new(db_conn))
+        }
+    };
+    Throttle::new(ZILLOW_BUCKET, rule, store)
+}
+
+#[test]
+fn buckets_refill_up_to_the_burst() {
+    let store = MemoryTokenBucketStore::default();
+    let rule = TokenBucketRule {
+        rate: 2.0,
+        burst: 2.0,
+    };
+    let start = now();
+
+    assert!(store.take("zillow", &rule, start).is_ok());
+    assert!(store.take("zillow", &rule, start).is_ok());
+    assert_eq!(
+        store.take("zillow", &rule, start),
+        Err(Duration::milliseconds(500))
+    );
+
+    // half a second brings one token back
+    let later = start + Duration::milliseconds(500);
+    assert!(store.take("zillow", &rule, later).is_ok());
+    assert!(store.take("zillow", &rule, later).is_err());
+
+    // and no more than the burst, however long it sits
+    let much_later = later + Duration::seconds(60);
+    assert!(store.take("zillow", &rule, much_later).is_ok());
+    assert!(store.take("zillow", &rule, much_later).is_ok());
+    assert!(store.take("zillow", &rule, much_later).is_err());
+}
@@ -1,14 +1,20 @@
 use crate::{
+    error::map_ostrich_error,
+    handlers::auth::{reject_with_auth_error, AuthError, AuthErrorCode},
     models::{
-        emailer::{self, Emailer},
+        emailer, listing_data,
         user::{self, User},
     },
     services,
     utils::JwtPayload,
     Config, DbConn,
 };
-use diesel::prelude::*;
-use std::sync::Arc;
+use diesel::{
+    connection::TransactionManager,
+    prelude::*,
+    result::{DatabaseErrorKind, Error::DatabaseError},
+};
+use std::{future::Future, sync::Arc};
 
 pub async fn with_user(jwt: JwtPayload, db_conn: Arc<DbConn>) -> Result<User, warp::Rejection> {
     let conn = db_conn.get_conn();
@@ -31,33 +37,99 @@ pub async fn with_token_db_and_user(
     Ok((jwt, db_conn, user))
 }
 
-pub async fn create_user_from_jwt(
+// the users row gets made on the first authenticated call, and only the call that
+// actually inserts it lets the admin know about the sign up
+pub async fn get_or_create_user_from_jwt(
     jwt: JwtPayload,
     db_conn: Arc<DbConn>,
     config: Arc<Config>,
     email_client: Arc<sendgrid_async::Client>,
 ) -> Result<User, warp::Rejection> {
     let conn = db_conn.get_conn();
-    let new_user = user::NewUser::new(jwt.email.clone(), String::from("Tier 0"), jwt.sub);
-    let inserted_user = user::create(&conn, &new_user);
-    services::email::email_admin_on_signup(&email_client.clone(), config.clone(), jwt.email).await;
-    Ok(inserted_user)
+    let plan = services::entitlements::get_free_plan(&conn);
+    let new_user = user::NewUser::new(
+        jwt.email.clone(),
+        String::from("Tier 0"),
+        jwt.sub.clone(),
+        plan.id,
+    );
+    let (user, created) = user::upsert(&conn, &new_user).map_err(|e| match e {
+        // someone else signed up with it, under another sub
+        DatabaseError(DatabaseErrorKind::UniqueViolation, ref info)
+            if info.constraint_name() == Some(user::ACTIVE_EMAIL_INDEX) =>
+        {
+            log::warn!(
+                "{} is already in use, not provisioning {}",
+                jwt.email,
+                jwt.sub
+            );
+            reject_with_auth_error(
+                AuthErrorCode::UsernameExists,
+                "An account with the given email already exists",
+            )
+        }
+        e => {
+            log::error!("Could not provision user {}: {}", jwt.sub, e);
+            reject_with_auth_error(AuthErrorCode::ServerError, "Could not create user")
+        }
+    })?;
+
+    if created {
+        log::info!("Created user {} for {}", user.id, jwt.sub);
+        services::email::email_admin_on_signup(&email_client.clone(), config.clone(), jwt.email)
+            .await
+            .unwrap_or_else(map_ostrich_error);
+    }
+    Ok(user)
+}
+
+// soft delete the user and everything hanging off of them along with the identity they sign
+// in with, all or nothing. the rows go in a transaction that is only committed once the
+// identity is gone, so when either side fails the account is left whole to delete again
+pub async fn delete_account<F>(
+    db_conn: &DbConn,
+    authentication_id: String,
+    delete_identity: F,
+) -> Result<Vec<i32>, warp::Rejection>
+where
+    F: Future<Output = Result<(), AuthError>>,
+{
+    let server_error = |e: diesel::result::Error| {
+        log::error!("Could not delete rows for {}: {}", authentication_id, e);
+        reject_with_auth_error(AuthErrorCode::ServerError, "Could not delete account data")
+    };
+    let conn = db_conn.get_conn();
+    conn.transaction_manager()
+        .begin_transaction(&*conn)
+        .map_err(server_error)?;
+
+    let deleted = delete_rows(&conn, authentication_id.clone());
+    let user_ids = match deleted {
+        Ok(user_ids) => user_ids,
+        Err(e) => {
+            rollback(&conn);
+            return Err(server_error(e));
+        }
+    };
+    if let Err(e) = delete_identity.await {
+        rollback(&conn);
+        return Err(warp::reject::custom(e));
+    }
+    conn.transaction_manager()
+        .commit_transaction(&*conn)
+        .map_err(server_error)?;
+    Ok(user_ids)
+}
+
+fn delete_rows(conn: &PgConnection, authentication_id: String) -> QueryResult<Vec<i32>> {
+    let user_ids = user::delete_by_authentication_id(conn, authentication_id)?;
+    emailer::delete_by_user_ids(conn, &user_ids)?;
+    listing_data::delete_by_user_ids(conn, &user_ids)?;
+    Ok(user_ids)
 }
 
-pub fn get_emails_for_user_with_tier(
-    conn: &PgConnection,
-    user_id: i32,
-    billing_id: String,
-) -> Vec<Emailer> {
-    let emailers = emailer::read_by_user_id(conn, user_id);
-
-    if billing_id == "Tier 1" {
-        emailers[0..1].to_vec()
-    } else if billing_id == "Tier 2" {
-        emailers[0..3].to_vec()
-    } else if billing_id == "Tier 3" {
-        emailers[0..5].to_vec()
-    } else {
-        vec![]
+fn rollback(conn: &PgConnection) {
+    if let Err(e) = conn.transaction_manager().rollback_transaction(conn) {
+        log::error!("Could not roll back deleting an account: {}", e);
     }
 }
@@ -0,0 +1,322 @@
+use crate::services::{
+    listing_source::{self, SourceName},
+    schedule::Frequency,
+};
+use serde::Serialize;
+
+const MAX_SEARCH_PARAM_LENGTH: usize = 200;
+const MAX_NOTES_LENGTH: usize = 1000;
+const MAX_ROOMS: i32 = 50;
+const MAX_LOAN_MONTHS: f64 = 600.0;
+
+// the parts of an emailer people fill in, borrowed from a post, a put or a row we already
+// have so they are all held to the same rules
+pub struct EmailerFields<'a> {
+    pub search_param: &'a str,
+    pub frequency: &'a str,
+    pub max_price: Option<f64>,
+    pub min_price: Option<f64>,
+    pub no_bedrooms: Option<i32>,
+    pub no_bathrooms: Option<i32>,
+    pub insurance: f64,
+    pub vacancy: f64,
+    pub property_management: f64,
+    pub capex: f64,
+    pub repairs: f64,
+    pub utilities: f64,
+    pub down_payment: f64,
+    pub closing_cost: f64,
+    pub loan_interest: f64,
+    pub loan_months: f64,
+    pub additional_monthly_expenses: f64,
+    pub notes: Option<&'a str>,
+    pub listing_source: &'a str,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    fn new(field: &'static str, code: &'static str, message: String) -> Self {
+        FieldError {
+            field,
+            code,
+            message,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ValidationFailed {
+    pub errors: Vec<FieldError>,
+}
+impl warp::reject::Reject for ValidationFailed {}
+
+fn percent(errors: &mut Vec<FieldError>, field: &'static str, value: f64) {
+    if !(0.0..=100.0).contains(&value) {
+        errors.push(FieldError::new(
+            field,
+            "OUT_OF_RANGE",
+            format!("{} is a percentage from 0 to 100", field),
+        ));
+    }
+}
+
+fn dollars(errors: &mut Vec<FieldError>, field: &'static str, value: f64) {
+    if !(value.is_finite() && value >= 0.0) {
+        errors.push(FieldError::new(
+            field,
+            "OUT_OF_RANGE",
+            format!("{} can't be negative", field),
+        ));
+    }
+}
+
+fn price(errors: &mut Vec<FieldError>, field: &'static str, value: Option<f64>) {
+    if let Some(value) = value {
+        if !(value.is_finite() && value > 0.0) {
+            errors.push(FieldError::new(
+                field,
+                "OUT_OF_RANGE",
+                format!("{} has to be more than 0", field),
+            ));
+        }
+    }
+}
+
+fn rooms(errors: &mut Vec<FieldError>, field: &'static str, value: Option<i32>) {
+    if let Some(value) = value {
+        if !(0..=MAX_ROOMS).contains(&value) {
+            errors.push(FieldError::new(
+                field,
+                "OUT_OF_RANGE",
+                format!("{} is from 0 to {}", field, MAX_ROOMS),
+            ));
+        }
+    }
+}
+
+fn length(errors: &mut Vec<FieldError>, field: &'static str, value: &str, max: usize) {
+    if value.chars().count() > max {
+        errors.push(FieldError::new(
+            field,
+            "TOO_LONG",
+            format!("{} is at most {} characters", field, max),
+        ));
+    }
+}
+
+// every problem at once, so a form can mark them all
+pub fn validate_emailer(emailer: &EmailerFields) -> Vec<FieldError> {
+    let mut errors = Vec::new();
+
+    if emailer.search_param.trim().is_empty() {
+        errors.push(FieldError::new(
+            "search_param",
+            "REQUIRED",
+            String::from("search_param can't be empty"),
+        ));
+    }
+    length(
+        &mut errors,
+        "search_param",
+        emailer.search_param,
+        MAX_SEARCH_PARAM_LENGTH,
+    );
+    if emailer.frequency.parse::<Frequency>().is_err() {
+        errors.push(FieldError::new(
+            "frequency",
+            "UNKNOWN_FREQUENCY",
+            format!(
+                "{} isn't a frequency, use hourly, daily, weekly or monthly",
+                emailer.frequency
+            ),
+        ));
+    }
+
+    price(&mut errors, "max_price", emailer.max_price);
+    price(&mut errors, "min_price", emailer.min_price);
+    if let (Some(min_price), Some(max_price)) = (emailer.min_price, emailer.max_price) {
+        if min_price > max_price {
+            errors.push(FieldError::new(
+                "min_price",
+                "MIN_EXCEEDS_MAX",
+                String::from("min_price can't be more than max_price"),
+            ));
+        }
+    }
+    rooms(&mut errors, "no_bedrooms", emailer.no_bedrooms);
+    rooms(&mut errors, "no_bathrooms", emailer.no_bathrooms);
+
+    dollars(&mut errors, "insurance", emailer.insurance);
+    dollars(&mut errors, "utilities", emailer.utilities);
+    dollars(
+        &mut errors,
+        "additional_monthly_expenses",
+        emailer.additional_monthly_expenses,
+    );
+    percent(&mut errors, "vacancy", emailer.vacancy);
+    percent(
+        &mut errors,
+        "property_management",
+        emailer.property_management,
+    );
+    percent(&mut errors, "capex", emailer.capex);
+    percent(&mut errors, "repairs", emailer.repairs);
+    percent(&mut errors, "down_payment", emailer.down_payment);
+    percent(&mut errors, "closing_cost", emailer.closing_cost);
+    // cash on cash divides by what goes in up front
+    if emailer.down_payment == 0.0 && emailer.closing_cost == 0.0 {
+        errors.push(FieldError::new(
+            "down_payment",
+            "NO_INVESTMENT",
+            String::from("down_payment and closing_cost can't both be 0"),
+        ));
+    }
+    // and the mortgage payment divides by the interest
+    if !(emailer.loan_interest > 0.0 && emailer.loan_interest <= 100.0) {
+        errors.push(FieldError::new(
+            "loan_interest",
+            "OUT_OF_RANGE",
+            String::from("loan_interest is a percentage more than 0 and up to 100"),
+        ));
+    }
+    if !(emailer.loan_months.fract() == 0.0
+        && (1.0..=MAX_LOAN_MONTHS).contains(&emailer.loan_months))
+    {
+        errors.push(FieldError::new(
+            "loan_months",
+            "OUT_OF_RANGE",
+            format!(
+                "loan_months is a whole number from 1 to {}",
+                MAX_LOAN_MONTHS
+            ),
+        ));
+    }
+
+    if let Some(notes) = emailer.notes {
+        length(&mut errors, "notes", notes, MAX_NOTES_LENGTH);
+    }
+    if emailer.listing_source.parse::<SourceName>().is_err() {
+        errors.push(FieldError::new(
+            "listing_source",
+            "UNKNOWN_LISTING_SOURCE",
+            format!(
+                "{} isn't a listing source, use zillow or file: and a .json or .csv file name",
+                emailer.listing_source
+            ),
+        ));
+    }
+
+    errors
+}
+
+// a file source has to be one of the user's own exports, which validate_emailer can't see
+pub fn validate_listing_file(
+    files_dir: Option<&str>,
+    user_id: i32,
+    listing_source: &str,
+) -> Option<FieldError> {
+    let name = match listing_source.parse::<SourceName>() {
+        Ok(SourceName::File(name)) => name,
+        _ => return None,
+    };
+    let found = files_dir
+        .map(|dir| {
+            listing_source::owner_files_dir(dir, user_id)
+                .join(&name)
+                .is_file()
+        })
+        .unwrap_or(false);
+    if found {
+        return None;
+    }
+    Some(FieldError::new(
+        "listing_source",
+        "UNKNOWN_LISTING_SOURCE",
+        format!("{} isn't one of your listing files", name),
+    ))
+}
+
+// files_dir is LISTING_FILES_DIR and user_id the emailer's owner
+pub fn check_emailer(
+    emailer: &EmailerFields,
+    files_dir: Option<&str>,
+    user_id: i32,
+) -> Result<(), ValidationFailed> {
+    let mut errors = validate_emailer(emailer);
+    errors.extend(validate_listing_file(
+        files_dir,
+        user_id,
+        emailer.listing_source,
+    ));
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(ValidationFailed { errors })
+    }
+}
+
+#[test]
+fn emailers_are_checked_field_by_field() {
+    let mut emailer = EmailerFields {
+        search_param: "Astoria, NY",
+        frequency: "daily",
+        max_price: Some(200000.0),
+        min_price: Some(100000.0),
+        no_bedrooms: Some(3),
+        no_bathrooms: None,
+        insurance: 60.0,
+        vacancy: 5.0,
+        property_management: 4.0,
+        capex: 5.0,
+        repairs: 5.0,
+        utilities: 0.0,
+        down_payment: 25.0,
+        closing_cost: 4.0,
+        loan_interest: 4.1,
+        loan_months: 240.0,
+        additional_monthly_expenses: 0.0,
+        notes: None,
+        listing_source: "zillow",
+    };
+    assert!(check_emailer(&emailer, None, 1).is_ok());
+
+    // someone else's export is as good as none
+    let files_dir = std::env::temp_dir().join("ostrich-listing-files");
+    std::fs::create_dir_all(files_dir.join("1")).unwrap();
+    std::fs::write(files_dir.join("1").join("acme_mls.csv"), "id\n").unwrap();
+    let files_dir = files_dir.to_str();
+    emailer.listing_source = "file:acme_mls.csv";
+    assert!(check_emailer(&emailer, files_dir, 1).is_ok());
+    assert!(check_emailer(&emailer, files_dir, 2).is_err());
+    assert!(check_emailer(&emailer, None, 1).is_err());
+
+    emailer.frequency = "banana";
+    emailer.min_price = Some(300000.0);
+    emailer.vacancy = 500.0;
+    emailer.loan_months = -12.0;
+    emailer.loan_interest = f64::NAN;
+    emailer.listing_source = "file:../../etc/passwd";
+    let fields = check_emailer(&emailer, files_dir, 1)
+        .unwrap_err()
+        .errors
+        .iter()
+        .map(|e| (e.field, e.code))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        fields,
+        vec![
+            ("frequency", "UNKNOWN_FREQUENCY"),
+            ("min_price", "MIN_EXCEEDS_MAX"),
+            ("vacancy", "OUT_OF_RANGE"),
+            ("loan_interest", "OUT_OF_RANGE"),
+            ("loan_months", "OUT_OF_RANGE"),
+            ("listing_source", "UNKNOWN_LISTING_SOURCE"),
+        ]
+    );
+}
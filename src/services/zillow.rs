@@ -2,23 +2,32 @@ use crate::{
     config::Config,
     db_conn::DbConn,
     error::{OstrichError, OstrichErrorType},
-    models::{emailer::Emailer, listing_data::NewListingData},
+    services::{
+        listing_source::{Listing, ListingPages, ListingSource, PropertyDetails, SearchParameters},
+        resilience::{self, CircuitBreaker, Failure, RetryPolicy},
+        throttle::{get_zillow_throttle, Throttle},
+    },
 };
+use async_trait::async_trait;
+use futures::stream::unfold;
 use reqwest::Error;
 use serde_derive::Deserialize;
-use std::sync::Arc;
-use tokio::time::{sleep, Duration};
-use tokio_stream::{self as stream, StreamExt};
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time,
+};
+use tokio_stream::StreamExt;
 use urlencoding::encode;
 
-#[derive(Deserialize)]
-pub struct ZillowSearchParameters {
-    pub search_param: String,
-    pub max_price: Option<f64>,
-    pub min_price: Option<f64>,
-    pub no_bedrooms: Option<i32>,
-    pub no_bathrooms: Option<i32>,
-}
+#[cfg(feature = "mocks")]
+pub use crate::services::mocks::zillow::{
+    get_zillow_listing_results, get_zillow_property_results_by_zpid,
+};
 
 impl From<Error> for OstrichError {
     fn from(e: Error) -> OstrichError {
@@ -29,6 +38,52 @@ impl From<Error> for OstrichError {
     }
 }
 
+// every request to zillow goes through one of these, so they all share the RapidAPI budget,
+// and the breaker of the process
+pub struct ZillowClient {
+    pub http: reqwest::Client,
+    pub throttle: Throttle,
+    breaker: CircuitBreaker,
+    retry_policy: RetryPolicy,
+}
+
+impl ZillowClient {
+    pub fn new(config: &Config, db_conn: Arc<DbConn>) -> Self {
+        let zillow_api = &config.zillow_api;
+        let http = reqwest::Client::builder()
+            .timeout(time::Duration::from_secs(zillow_api.timeout_seconds as u64))
+            .connect_timeout(time::Duration::from_secs(
+                zillow_api.connect_timeout_seconds as u64,
+            ))
+            .build()
+            .expect("Could not build the zillow http client");
+
+        ZillowClient {
+            http,
+            throttle: get_zillow_throttle(zillow_api, db_conn),
+            breaker: CircuitBreaker::new(
+                "Zillow",
+                zillow_api.breaker_failures,
+                chrono::Duration::seconds(zillow_api.breaker_cooldown_seconds),
+            ),
+            retry_policy: RetryPolicy {
+                max_retries: zillow_api.max_retries as u32,
+                base: chrono::Duration::milliseconds(zillow_api.retry_base_milliseconds),
+                max_delay: chrono::Duration::seconds(zillow_api.retry_max_seconds),
+            },
+        }
+    }
+
+    // a request with the throttle, retries and breaker around it
+    pub async fn call<T, F, Fut>(&self, attempt: F) -> Result<T, OstrichError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, Failure>>,
+    {
+        resilience::with_retries(&self.throttle, &self.breaker, &self.retry_policy, attempt).await
+    }
+}
+
 #[derive(Deserialize, Debug)]
 pub struct ListingResultsSubType {
     pub is_FSBA: Option<bool>,
@@ -73,38 +128,144 @@ pub struct ZillowListingsSearchRoot {
     pub totalPages: Option<i64>,
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_zillow_listing_results(
     config: Arc<Config>,
-    reqwest_client: Arc<reqwest::Client>,
+    zillow_client: Arc<ZillowClient>,
     api_url: String,
-) -> Result<ZillowListingsSearchRoot, OstrichError> {
+) -> Result<ZillowListingsSearchRoot, Failure> {
     log::info!("Getting listings at {}", api_url);
 
-    let listing_data = reqwest_client
+    let response = zillow_client
+        .http
         .get(api_url)
         .header("X-RapidAPI-Host", config.zillow_api.api_host.clone())
         .header("X-RapidAPI-Key", config.zillow_api.api_key.clone())
         .send()
-        .await?
+        .await?;
+    let listing_data = resilience::check_status(response)?
         .json::<ZillowListingsSearchRoot>()
         .await
         .map_err(|e| {
-            OstrichError::new(
-                format!(
-                    "Search Parameters did not result in a valid response {:?}",
-                    e
-                ),
-                OstrichErrorType::ListingResultError,
-            )
+            if e.is_decode() {
+                Failure::Fatal(OstrichError::new(
+                    format!(
+                        "Search Parameters did not result in a valid response {:?}",
+                        e
+                    ),
+                    OstrichErrorType::ListingResultError,
+                ))
+            } else {
+                Failure::from(e)
+            }
         })?;
 
     Ok(listing_data)
 }
 
+// the pages we read of a search zillow says has total_pages
+fn pages_to_read(total_pages: Option<i64>, max_pages: i64) -> i64 {
+    total_pages.unwrap_or(1).min(max_pages).max(1)
+}
+
+// reads the first page straight away, so a search that finds nothing still fails here,
+// then the rest up to the configured cap only as far as the listings are read. a later page
+// that fails ends the listings with its error
+pub async fn get_zillow_listing_pages(
+    config: Arc<Config>,
+    zillow_client: Arc<ZillowClient>,
+    api_url: String,
+    calls: Arc<AtomicI64>,
+) -> Result<ListingPages, OstrichError> {
+    let max_pages = config.zillow_api.max_pages;
+    read_listing_pages(api_url, max_pages, move |page_url| {
+        let config = config.clone();
+        let zillow_client = zillow_client.clone();
+        let calls = calls.clone();
+        async move {
+            zillow_client
+                .call(|| {
+                    calls.fetch_add(1, Ordering::Relaxed);
+                    get_zillow_listing_results(
+                        config.clone(),
+                        zillow_client.clone(),
+                        page_url.clone(),
+                    )
+                })
+                .await
+        }
+    })
+    .await
+}
+
+// the paging of get_zillow_listing_pages, with whatever reads a page of results
+async fn read_listing_pages<F, Fut>(
+    api_url: String,
+    max_pages: i64,
+    read_page: F,
+) -> Result<ListingPages, OstrichError>
+where
+    F: Fn(String) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<ZillowListingsSearchRoot, OstrichError>> + Send + 'static,
+{
+    let first_page = read_page(api_url.clone()).await?;
+    let total_results = first_page.totalResultCount;
+    let last_page = pages_to_read(first_page.totalPages, max_pages);
+    if first_page.totalPages.unwrap_or(1) > last_page {
+        log::warn!(
+            "Only reading {} of {} pages at {}",
+            last_page,
+            first_page.totalPages.unwrap_or(1),
+            api_url
+        );
+    }
+
+    let read_page = Arc::new(read_page);
+    let props = unfold(
+        (VecDeque::from(first_page.props), 2),
+        move |(mut props, page)| {
+            let read_page = read_page.clone();
+            let page_url = format!("{}&page={}", api_url, page);
+            async move {
+                if let Some(prop) = props.pop_front() {
+                    return Some((Ok(prop), (props, page)));
+                }
+                if page > last_page {
+                    return None;
+                }
+                let mut props = match read_page(page_url).await {
+                    Ok(listing_results) => VecDeque::from(listing_results.props),
+                    Err(e) => {
+                        log::error!("Stopped at page {}: {:?}", page, e);
+                        return Some((Err(e), (props, last_page + 1)));
+                    }
+                };
+                props.pop_front().map(|prop| (Ok(prop), (props, page + 1)))
+            }
+        },
+    );
+
+    // zillow's listings without a zpid can't be looked up, so they are no use to us
+    let listings = props.filter_map(|prop| match prop {
+        Ok(prop) => prop.zpid.map(|id| {
+            Ok(Listing {
+                id,
+                address: prop.address,
+                price: prop.price,
+            })
+        }),
+        Err(e) => Some(Err(e)),
+    });
+
+    Ok(ListingPages {
+        total_results,
+        listings: Box::pin(listings),
+    })
+}
+
 pub fn get_zillow_listing_url_from_params(
     config: Arc<Config>,
-    zillow_search_params: &ZillowSearchParameters,
-    days_on_market: Option<i32>,
+    zillow_search_params: &SearchParameters,
 ) -> String {
     let mut api_url = format!(
         //?location=northampton%20county&home_type=Houses&minPrice=100000&maxPrice=200000&daysOn=1
@@ -145,8 +306,8 @@ pub fn get_zillow_listing_url_from_params(
         );
     }
 
-    if days_on_market.is_some() {
-        api_url = format!("{}&daysOn={}", api_url, days_on_market.unwrap());
+    if let Some(days_on_market) = zillow_search_params.days_on_market {
+        api_url = format!("{}&daysOn={}", api_url, days_on_market);
     }
 
     api_url
@@ -221,7 +382,7 @@ pub struct ResoFacts {
     pub taxAnnualAmount: Option<i64>,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Default)]
 pub struct Address {
     pub city: Option<String>,
     pub neighborhood: Option<String>,
@@ -230,102 +391,220 @@ pub struct Address {
     pub zipcode: Option<String>,
 }
 
+#[cfg(not(feature = "mocks"))]
 pub async fn get_zillow_property_results_by_zpid(
     config: Arc<Config>,
-    reqwest_client: Arc<reqwest::Client>,
+    zillow_client: Arc<ZillowClient>,
     zpid: String,
-    delay: Option<u64>,
-) -> Result<ZillowPropertySearchRoot, OstrichError> {
-    if delay.is_some() {
-        sleep(Duration::from_millis(delay.unwrap())).await;
-    }
-
+) -> Result<ZillowPropertySearchRoot, Failure> {
     let api_url = format!(
         "https://{}/property?zpid={}",
         config.zillow_api.api_host, zpid
     );
     log::info!("Getting property at {}", api_url);
 
-    let listing_data = reqwest_client
+    let response = zillow_client
+        .http
         .get(api_url)
         .header("X-RapidAPI-Host", config.zillow_api.api_host.clone())
         .header("X-RapidAPI-Key", config.zillow_api.api_key.clone())
         .send()
-        .await?
+        .await?;
+    let listing_data = resilience::check_status(response)?
         .json::<ZillowPropertySearchRoot>()
         .await
         .map_err(|e| {
-            OstrichError::new(
-                format!(
-                    "Zillow Property {} did not result in a valid response {:?}",
-                    zpid, e
-                ),
-                OstrichErrorType::PropertyResultError,
-            )
+            if e.is_decode() {
+                Failure::Fatal(OstrichError::new(
+                    format!(
+                        "Zillow Property {} did not result in a valid response {:?}",
+                        zpid, e
+                    ),
+                    OstrichErrorType::PropertyResultError,
+                ))
+            } else {
+                Failure::from(e)
+            }
         })?;
 
     Ok(listing_data)
 }
 
-pub async fn get_listing_email_for_search_params(
+// what a property page on zillow tells us, in the shape every source uses
+fn property_details(id: String, property: ZillowPropertySearchRoot) -> PropertyDetails {
+    let address = property.address.unwrap_or_default();
+    PropertyDetails {
+        id,
+        street_address: address.streetAddress,
+        city: address.city,
+        state: address.state,
+        zipcode: address.zipcode,
+        bedrooms: property.bedrooms.map(|bedrooms| bedrooms as i32),
+        bathrooms: property.bathrooms.map(|bathrooms| bathrooms as i32),
+        price: property.price,
+        tax_rate: property.propertyTaxRate,
+        rent_estimate: property.rentZestimate,
+        time_on_market: property.timeOnZillow,
+        img_src: property.imgSrc,
+        url: property
+            .url
+            .map(|url| format!("https://www.zillow.com{}", url)),
+    }
+}
+
+// zillow through RapidAPI, every request goes through the client's throttle and breaker
+pub struct ZillowSource {
     config: Arc<Config>,
-    db_conn: Arc<DbConn>,
-    reqwest_client: Arc<reqwest::Client>,
-    emailer_record: &Emailer,
-    body: String,
-    delay: u64,
-    days_on_market: Option<i32>,
-) -> Result<String, OstrichError> {
-    let api_url =
-        get_zillow_listing_url_from_params(config.clone(), &emailer_record.into(), days_on_market);
-    let listing_results =
-        get_zillow_listing_results(config.clone(), reqwest_client.clone(), api_url).await?;
-
-    log::debug!("listing_results = {:?}", listing_results);
-
-    let zpids = listing_results
-        .props
-        .into_iter()
-        .map(|listing| listing.zpid)
-        .filter(|x| x.is_some())
-        .map(|x| x.unwrap())
-        .collect::<Vec<String>>();
-
-    log::info!("Found {} properties", zpids.len());
-
-    let email_body = stream::iter(zpids)
-        .then(|zpid| {
-            get_zillow_property_results_by_zpid(
-                config.clone(),
-                reqwest_client.clone(),
-                zpid,
-                Some(delay),
-            )
-        })
-        .filter(|x| {
-            if x.is_ok() {
-                log::debug!("{:?}", x);
-                true
-            } else {
-                log::error!("{:?}", x);
-                false
-            }
-        })
-        .map(|x| x.unwrap())
-        .map(|property_result| NewListingData::new(property_result, emailer_record))
-        .map(|zillow_email_data| {
-            zillow_email_data.insert(&db_conn.get_conn());
-            let formatted_property_string = zillow_email_data.to_email();
-            log::debug!("{:?}", formatted_property_string);
-            formatted_property_string
-        })
-        .fold(body, |acc, listing_data| {
-            format!(
-                "{}<div style=\"border-top:1px solid black;\">{}</div>",
-                acc, listing_data
-            )
-        })
+    zillow_client: Arc<ZillowClient>,
+    // every request sent, retries included, since rapidapi bills them all
+    calls: Arc<AtomicI64>,
+}
+
+impl ZillowSource {
+    pub fn new(config: Arc<Config>, zillow_client: Arc<ZillowClient>) -> Self {
+        ZillowSource {
+            config,
+            zillow_client,
+            calls: Arc::new(AtomicI64::new(0)),
+        }
+    }
+}
+
+#[async_trait]
+impl ListingSource for ZillowSource {
+    fn search_url(&self, search: &SearchParameters) -> String {
+        get_zillow_listing_url_from_params(self.config.clone(), search)
+    }
+
+    async fn search(&self, search: &SearchParameters) -> Result<ListingPages, OstrichError> {
+        get_zillow_listing_pages(
+            self.config.clone(),
+            self.zillow_client.clone(),
+            self.search_url(search),
+            self.calls.clone(),
+        )
+        .await
+    }
+
+    fn concurrency(&self) -> usize {
+        self.config.zillow_api.concurrency as usize
+    }
+
+    fn api_calls(&self) -> Option<i64> {
+        Some(self.calls.load(Ordering::Relaxed))
+    }
+
+    async fn details(&self, listing: &Listing) -> Result<PropertyDetails, OstrichError> {
+        let property = self
+            .zillow_client
+            .call(|| {
+                self.calls.fetch_add(1, Ordering::Relaxed);
+                get_zillow_property_results_by_zpid(
+                    self.config.clone(),
+                    self.zillow_client.clone(),
+                    listing.id.clone(),
+                )
+            })
+            .await?;
+        Ok(property_details(listing.id.clone(), property))
+    }
+}
+
+#[test]
+fn pages_are_capped() {
+    assert_eq!(pages_to_read(Some(20), 5), 5);
+    assert_eq!(pages_to_read(Some(2), 5), 2);
+    assert_eq!(pages_to_read(None, 5), 1);
+    assert_eq!(pages_to_read(Some(0), 5), 1);
+}
+
+#[tokio::test]
+async fn later_pages_are_read_as_the_listings_are() {
+    use std::sync::Mutex;
+
+    // three pages of two, two and one listings, where the second can be made to fail
+    let read = Arc::new(Mutex::new(Vec::<String>::new()));
+    let pages = |max_pages: i64, fail_page_2: bool| {
+        let read = read.clone();
+        read_listing_pages(
+            String::from("search?location=Austin"),
+            max_pages,
+            move |url| {
+                read.lock().unwrap().push(url.clone());
+                async move {
+                    let ids = match url.rsplit("&page=").next() {
+                        Some("2") if fail_page_2 => {
+                            return Err(OstrichError::new(
+                                String::from("page 2"),
+                                OstrichErrorType::ApiError,
+                            ))
+                        }
+                        Some("2") => vec!["3", "4"],
+                        Some("3") => vec!["5"],
+                        _ => vec!["1", "2"],
+                    };
+                    let props = ids
+                        .iter()
+                        .map(|id| serde_json::json!({ "zpid": id }))
+                        .collect::<Vec<serde_json::Value>>();
+                    Ok(serde_json::from_value(serde_json::json!({
+                        "props": props,
+                        "totalResultCount": 5,
+                        "totalPages": 3,
+                    }))
+                    .unwrap())
+                }
+            },
+        )
+    };
+    let ids = |listings: Vec<Result<Listing, OstrichError>>| {
+        listings
+            .into_iter()
+            .map(|listing| listing.map(|listing| listing.id).map_err(|e| e.details))
+            .collect::<Vec<Result<String, String>>>()
+    };
+    let ok = |ids: &[&str]| {
+        ids.iter()
+            .map(|id| Ok(String::from(*id)))
+            .collect::<Vec<Result<String, String>>>()
+    };
+
+    let listing_pages = pages(5, false).await.unwrap();
+    assert_eq!(listing_pages.total_results, Some(5));
+    // nothing past the first page is read until its listings are
+    assert_eq!(read.lock().unwrap().len(), 1);
+    let listings = listing_pages.listings.collect::<Vec<_>>().await;
+    assert_eq!(ids(listings), ok(&["1", "2", "3", "4", "5"]));
+    assert_eq!(
+        *read.lock().unwrap(),
+        vec![
+            "search?location=Austin",
+            "search?location=Austin&page=2",
+            "search?location=Austin&page=3"
+        ]
+    );
+
+    // the cap stops it at the last page it may read
+    read.lock().unwrap().clear();
+    let listings = pages(2, false)
+        .await
+        .unwrap()
+        .listings
+        .collect::<Vec<_>>()
         .await;
+    assert_eq!(ids(listings), ok(&["1", "2", "3", "4"]));
+    assert_eq!(read.lock().unwrap().len(), 2);
 
-    Ok(email_body)
+    // a failed page keeps what was read before it, then ends with its error
+    read.lock().unwrap().clear();
+    let listings = pages(5, true)
+        .await
+        .unwrap()
+        .listings
+        .collect::<Vec<_>>()
+        .await;
+    let mut expected = ok(&["1", "2"]);
+    expected.push(Err(String::from("page 2")));
+    assert_eq!(ids(listings), expected);
+    assert_eq!(read.lock().unwrap().len(), 2);
 }
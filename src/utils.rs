@@ -2,7 +2,7 @@ use crate::DynResult;
 use bytes::Bytes;
 use chrono::prelude::*;
 use hmac::{Hmac, Mac};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use sha2::Sha256;
 use thousands::Separable;
 use warp::Filter;
@@ -11,6 +11,8 @@ use warp::Filter;
 pub struct JwtPayload {
     pub sub: String,
     pub email: String,
+    #[serde(rename = "cognito:groups", default)]
+    pub groups: Vec<String>,
 }
 
 // Base64 ( HMAC_SHA256 ( "Client Secret Key", "Username" + "Client Id" ) )
@@ -26,6 +28,7 @@ pub fn now() -> chrono::naive::NaiveDateTime {
     Utc::now().naive_local()
 }
 
+// reads the claims WITHOUT checking the signature, use services::jwt to authenticate
 pub fn decode_jwt(token: &str) -> DynResult<JwtPayload> {
     let body = token.split(".").collect::<Vec<&str>>();
     let jwt_string = String::from_utf8(base64::decode(body[1])?)?;
@@ -38,6 +41,16 @@ pub fn decode_bearer(header: &str) -> DynResult<JwtPayload> {
     decode_jwt(parts[1])
 }
 
+// pull the raw token out of an `Authorization: Bearer <token>` header
+pub fn bearer_token(header: &str) -> Option<&str> {
+    header.strip_prefix("Bearer ").map(|token| token.trim())
+}
+
+// pull the raw key out of an `Authorization: ApiKey <key>` header
+pub fn api_key_token(header: &str) -> Option<&str> {
+    header.strip_prefix("ApiKey ").map(|key| key.trim())
+}
+
 pub fn format_optional_float(x: Option<f64>) -> String {
     x.map_or(String::from("N/A"), |x| {
         format!("${}", x.separate_with_commas())
@@ -61,6 +74,42 @@ async fn convert_to_string(bytes: Bytes) -> Result<String, warp::Rejection> {
     String::from_utf8(bytes.to_vec()).map_err(|_| warp::reject())
 }
 
+// tells a field that was left out (None) from one sent as null (Some(None)), use it with
+// #[serde(default, deserialize_with = "double_option")]
+pub fn double_option<'de, T, D>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+const DEFAULT_PER_PAGE: i64 = 20;
+const MAX_PER_PAGE: i64 = 100;
+
+// ?page=2&per_page=50, pages start at 1
+#[derive(Deserialize)]
+pub struct PageQuery {
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+}
+
+impl PageQuery {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page
+            .unwrap_or(DEFAULT_PER_PAGE)
+            .clamp(1, MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page() - 1).saturating_mul(self.per_page())
+    }
+}
+
 #[test]
 fn auth_id_in_jwt() {
     let x = decode_jwt("eyJraWQiOiIxUExQVXpIYThLNGsxaEZjTkN6cXpOWThuXC8ydjd4UVI3NUFkZ1BcL0duWEE9IiwiYWxnIjoiUlMyNTYifQ.eyJzdWIiOiI2MWI5NzZhNS04MDdhLTQyNTctYmE1ZS00Y2RlYTZjMzlkMTgiLCJlbWFpbF92ZXJpZmllZCI6dHJ1ZSwiaXNzIjoiaHR0cHM6XC9cL2NvZ25pdG8taWRwLnVzLWVhc3QtMi5hbWF6b25hd3MuY29tXC91cy1lYXN0LTJfMG9jMzJ5N2RtIiwiY29nbml0bzp1c2VybmFtZSI6ImhnbWF4d2VsbGtpbmdAZ21haWwuY29tIiwib3JpZ2luX2p0aSI6IjMxNWUxNGMwLWM2OWItNDI2MS1hNWMxLTBlMWI3OTdhNzhhMSIsImF1ZCI6ImQ5djU4Z282OTJpY2xzNDRkMDZwb2M3N2EiLCJldmVudF9pZCI6IjhmZTRjYjFkLThiNmYtNDcxNC1hMDRhLTFhYzZkZWIxNDQ1ZiIsInRva2VuX3VzZSI6ImlkIiwiYXV0aF90aW1lIjoxNjUzMDAyNDkyLCJleHAiOjE2NTMwMDYwOTIsImlhdCI6MTY1MzAwMjQ5MiwianRpIjoiZDE3OTU1ODAtZGJjNy00MTY0LTljN2YtMjBlM2RkN2NhMmNkIiwiZW1haWwiOiJoZ21heHdlbGxraW5nQGdtYWlsLmNvbSJ9.mWRuP8BENKQhbPX2H_-L0myKtS9_yvwkRnqcHCF7Aij8D3oU6in1P2x1Yf0_wh9Tn32XMzX4baMeePpz27_9GpwpX3q2_XmGpncdt1mKpSx5SqWzo3gbmTq4LcoPoay-JBYMzzuEu6be0_rnHLt-oXir6oftLzwsv8vwz5096uTcdfDrsYCBIYiEtytR6JbVrxtT8IkZVPDGtk-SNFhKTQoNPLJGlN5nVo_n4L2NWG8fFVCDMZBsKFcmkTzDCTNCcLQzfsVfdM-mYug7gq7GQkO4v8ZJpc-jnvnaaCtpVcJnwBd4nlMIT1Dd95lCCoo3Yavf82hGHiRgDVpOxk4RmQ");
@@ -78,3 +127,22 @@ fn email_in_jwt() {
     let x = decode_jwt("eyJraWQiOiIxUExQVXpIYThLNGsxaEZjTkN6cXpOWThuXC8ydjd4UVI3NUFkZ1BcL0duWEE9IiwiYWxnIjoiUlMyNTYifQ.eyJzdWIiOiI2MWI5NzZhNS04MDdhLTQyNTctYmE1ZS00Y2RlYTZjMzlkMTgiLCJlbWFpbF92ZXJpZmllZCI6dHJ1ZSwiaXNzIjoiaHR0cHM6XC9cL2NvZ25pdG8taWRwLnVzLWVhc3QtMi5hbWF6b25hd3MuY29tXC91cy1lYXN0LTJfMG9jMzJ5N2RtIiwiY29nbml0bzp1c2VybmFtZSI6ImhnbWF4d2VsbGtpbmdAZ21haWwuY29tIiwib3JpZ2luX2p0aSI6IjMxNWUxNGMwLWM2OWItNDI2MS1hNWMxLTBlMWI3OTdhNzhhMSIsImF1ZCI6ImQ5djU4Z282OTJpY2xzNDRkMDZwb2M3N2EiLCJldmVudF9pZCI6IjhmZTRjYjFkLThiNmYtNDcxNC1hMDRhLTFhYzZkZWIxNDQ1ZiIsInRva2VuX3VzZSI6ImlkIiwiYXV0aF90aW1lIjoxNjUzMDAyNDkyLCJleHAiOjE2NTMwMDYwOTIsImlhdCI6MTY1MzAwMjQ5MiwianRpIjoiZDE3OTU1ODAtZGJjNy00MTY0LTljN2YtMjBlM2RkN2NhMmNkIiwiZW1haWwiOiJoZ21heHdlbGxraW5nQGdtYWlsLmNvbSJ9.mWRuP8BENKQhbPX2H_-L0myKtS9_yvwkRnqcHCF7Aij8D3oU6in1P2x1Yf0_wh9Tn32XMzX4baMeePpz27_9GpwpX3q2_XmGpncdt1mKpSx5SqWzo3gbmTq4LcoPoay-JBYMzzuEu6be0_rnHLt-oXir6oftLzwsv8vwz5096uTcdfDrsYCBIYiEtytR6JbVrxtT8IkZVPDGtk-SNFhKTQoNPLJGlN5nVo_n4L2NWG8fFVCDMZBsKFcmkTzDCTNCcLQzfsVfdM-mYug7gq7GQkO4v8ZJpc-jnvnaaCtpVcJnwBd4nlMIT1Dd95lCCoo3Yavf82hGHiRgDVpOxk4RmQ");
     assert_eq!(x.unwrap().email, "hgmaxwellking@gmail.com");
 }
+
+#[test]
+fn pages_stay_in_bounds() {
+    let page = PageQuery {
+        page: None,
+        per_page: None,
+    };
+    assert_eq!((page.page(), page.per_page(), page.offset()), (1, 20, 0));
+    let page = PageQuery {
+        page: Some(3),
+        per_page: Some(1000),
+    };
+    assert_eq!((page.page(), page.per_page(), page.offset()), (3, 100, 200));
+    let page = PageQuery {
+        page: Some(-1),
+        per_page: Some(0),
+    };
+    assert_eq!((page.page(), page.per_page(), page.offset()), (1, 1, 0));
+}